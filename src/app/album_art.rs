@@ -0,0 +1,390 @@
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use eframe::egui;
+use rayon::ThreadPool;
+use symphonia::core::meta::{MetadataRevision, StandardVisualKey};
+
+use super::library::StableHasher;
+use super::{media, FileStamp, LibraryItem};
+
+// Big enough for the player, which shows the art the largest.
+const THUMBNAIL_SIZE: u32 = 160;
+
+// How many covers are kept as textures. The ones shown the longest ago make room for new ones.
+const MAX_TEXTURES: usize = 256;
+
+// How many thumbnails are kept on disk. The ones read the longest ago are removed on startup.
+const MAX_CACHED_THUMBNAILS: usize = 4096;
+
+// The names of cover images kept next to the tracks, most likely to be the front cover first.
+const SIDECAR_NAMES: [&str; 4] = ["cover", "folder", "front", "album"];
+const SIDECAR_EXTENSIONS: [&str; 3] = ["jpg", "jpeg", "png"];
+
+/// The album art of tracks as textures, loaded in the background the first time they're shown.
+/// Thumbnails are cached on disk, so the art doesn't have to be decoded again. Tracks with the
+/// same cover, ex. the tracks of an album, share its thumbnail and texture.
+pub struct AlbumArt {
+    // The cover of each track, or `None` while it's loading or when the track has none.
+    covers: HashMap<PathBuf, Option<u64>>,
+    // The textures of the covers, with when they were last shown.
+    textures: HashMap<u64, (egui::TextureHandle, u64)>,
+    // Counts up every time a texture is asked for, to tell which was shown the longest ago.
+    uses: u64,
+    thread_pool: Option<Arc<ThreadPool>>,
+    cache_dir: Option<PathBuf>,
+    loaded_tx: Sender<(PathBuf, Option<(u64, egui::ColorImage)>)>,
+    loaded_rx: Receiver<(PathBuf, Option<(u64, egui::ColorImage)>)>,
+}
+
+impl Default for AlbumArt {
+    fn default() -> Self {
+        let (loaded_tx, loaded_rx) = channel();
+
+        Self {
+            covers: HashMap::new(),
+            textures: HashMap::new(),
+            uses: 0,
+            thread_pool: None,
+            cache_dir: None,
+            loaded_tx,
+            loaded_rx,
+        }
+    }
+}
+
+impl AlbumArt {
+    pub fn new(thread_pool: Arc<ThreadPool>) -> Self {
+        let cache_dir = default_cache_dir();
+
+        if let Some(cache_dir) = &cache_dir {
+            if let Err(err) = std::fs::create_dir_all(cache_dir) {
+                tracing::warn!(
+                    "Couldn't create the album art cache {:?}: {}",
+                    cache_dir,
+                    err
+                );
+            }
+
+            let cache_dir = cache_dir.clone();
+            thread_pool.spawn(move || prune_cache(&cache_dir, MAX_CACHED_THUMBNAILS));
+        }
+
+        Self {
+            thread_pool: Some(thread_pool),
+            cache_dir,
+            ..Self::default()
+        }
+    }
+
+    /// The art of the track, or `None` while it's loading or when the track has none.
+    pub fn texture(
+        &mut self,
+        ctx: &egui::Context,
+        item: &LibraryItem,
+    ) -> Option<egui::TextureHandle> {
+        let loaded = self.loaded_rx.try_iter().collect::<Vec<_>>();
+        for (path, cover) in loaded {
+            let id = cover.map(|(id, image)| {
+                if !self.textures.contains_key(&id) {
+                    let name = format!("album-art-{:016x}", id);
+                    let texture = ctx.load_texture(name, image, Default::default());
+                    self.insert_texture(id, texture);
+                }
+                id
+            });
+            self.covers.insert(path, id);
+        }
+
+        self.uses += 1;
+        let path = item.path();
+
+        match self.covers.get(&path) {
+            Some(Some(id)) => {
+                if let Some((texture, last_used)) = self.textures.get_mut(id) {
+                    *last_used = self.uses;
+                    return Some(texture.clone());
+                }
+            }
+            Some(None) => return None,
+            None => (),
+        }
+
+        // Not loaded yet, or its texture made room for others since.
+        self.covers.insert(path.clone(), None);
+
+        if let Some(thread_pool) = &self.thread_pool {
+            let cache_dir = self.cache_dir.clone();
+            let loaded_tx = self.loaded_tx.clone();
+
+            thread_pool.spawn(move || {
+                let cover = load_thumbnail(&path, cache_dir.as_deref());
+                let _ = loaded_tx.send((path, cover));
+            });
+        }
+
+        None
+    }
+
+    // Loads the art of the track again the next time it's shown, ex. after its file changed.
+    pub fn forget(&mut self, path: &Path) {
+        self.covers.remove(path);
+    }
+
+    fn insert_texture(&mut self, id: u64, texture: egui::TextureHandle) {
+        if self.textures.len() >= MAX_TEXTURES {
+            let least_recent = self
+                .textures
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(id, _)| *id);
+
+            if let Some(least_recent) = least_recent {
+                self.textures.remove(&least_recent);
+            }
+        }
+
+        self.textures.insert(id, (texture, self.uses));
+    }
+}
+
+// The thumbnails are kept next to the app state.
+fn default_cache_dir() -> Option<PathBuf> {
+    confy::get_configuration_file_path("music_player", None)
+        .ok()
+        .map(|config_path| config_path.with_file_name("album_art"))
+}
+
+// Removes the thumbnails read the longest ago, so only the newest `max_len` are kept.
+fn prune_cache(cache_dir: &Path, max_len: usize) {
+    let Ok(entries) = std::fs::read_dir(cache_dir) else {
+        return;
+    };
+
+    let mut thumbnails = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let modified = entry.metadata().ok()?.modified().ok()?;
+            Some((modified, entry.path()))
+        })
+        .collect::<Vec<(SystemTime, PathBuf)>>();
+
+    if thumbnails.len() <= max_len {
+        return;
+    }
+
+    thumbnails.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    for (_, path) in &thumbnails[max_len..] {
+        if let Err(err) = std::fs::remove_file(path) {
+            tracing::warn!("Couldn't remove the cached album art {:?}: {}", path, err);
+        }
+    }
+}
+
+// Where the cover of a track comes from.
+enum CoverSource {
+    Embedded(Vec<u8>),
+    Sidecar(PathBuf),
+}
+
+// Reads the cached thumbnail of the track's cover, or decodes the cover and caches a thumbnail
+// of it. Returns the id of the cover along with the thumbnail.
+fn load_thumbnail(path: &Path, cache_dir: Option<&Path>) -> Option<(u64, egui::ColorImage)> {
+    let (id, source) = find_cover(path)?;
+    let cache_path = cache_dir.map(|cache_dir| cache_dir.join(format!("{:016x}.png", id)));
+
+    let cached = cache_path.as_ref().and_then(|cache_path| {
+        let thumbnail = image::open(cache_path).ok()?;
+        // Marks it as read, so pruning the cache keeps it.
+        let _ = std::fs::File::options()
+            .write(true)
+            .open(cache_path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        Some(thumbnail)
+    });
+
+    let thumbnail = match cached {
+        Some(thumbnail) => thumbnail,
+        None => {
+            let cover = match source {
+                CoverSource::Embedded(cover) => cover,
+                CoverSource::Sidecar(sidecar) => std::fs::read(sidecar).ok()?,
+            };
+            let thumbnail = match image::load_from_memory(&cover) {
+                Ok(image) => image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE),
+                Err(err) => {
+                    tracing::warn!("Couldn't decode the album art of {:?}: {}", path, err);
+                    return None;
+                }
+            };
+
+            if let Some(cache_path) = &cache_path {
+                if let Err(err) = thumbnail.save(cache_path) {
+                    tracing::warn!("Couldn't cache the album art of {:?}: {}", path, err);
+                }
+            }
+
+            thumbnail
+        }
+    };
+
+    let rgba = thumbnail.to_rgba8();
+    let size = [rgba.width() as usize, rgba.height() as usize];
+
+    Some((
+        id,
+        egui::ColorImage::from_rgba_unmultiplied(size, rgba.as_raw()),
+    ))
+}
+
+/// The cover of the track and an id for it. Art embedded in the file, ex. an ID3 APIC frame or a
+/// FLAC picture block, comes before an image next to the file, ex. "folder.jpg". Embedded art is
+/// told apart by its bytes, and an image next to the file by its path and stamp, so the id
+/// changes when the image is edited.
+fn find_cover(path: &Path) -> Option<(u64, CoverSource)> {
+    let mut hasher = StableHasher::default();
+
+    if let Some(cover) = read_embedded_cover(path) {
+        hasher.write(&cover);
+        return Some((hasher.finish(), CoverSource::Embedded(cover)));
+    }
+
+    let sidecar = find_sidecar_cover(path.parent()?)?;
+    let file_stamp = FileStamp::read(&sidecar)?;
+    hasher.write(sidecar.as_os_str().as_encoded_bytes());
+    hasher.write(&file_stamp.modified_millis.to_le_bytes());
+    hasher.write(&file_stamp.size.to_le_bytes());
+
+    Some((hasher.finish(), CoverSource::Sidecar(sidecar)))
+}
+
+fn read_embedded_cover(path: &Path) -> Option<Vec<u8>> {
+    let mut probed = media::probe(path)?;
+    let mut visuals = Vec::new();
+
+    if let Some(mut metadata) = probed.metadata.get() {
+        if let Some(revision) = metadata.skip_to_latest() {
+            visuals.extend(revision_visuals(revision));
+        }
+    }
+
+    if let Some(revision) = probed.format.metadata().skip_to_latest() {
+        visuals.extend(revision_visuals(revision));
+    }
+
+    // The front cover, or whatever picture there is when none is marked as the front cover.
+    visuals
+        .iter()
+        .find(|(usage, _)| *usage == Some(StandardVisualKey::FrontCover))
+        .or(visuals.first())
+        .map(|(_, data)| data.clone())
+}
+
+fn revision_visuals(revision: &MetadataRevision) -> Vec<(Option<StandardVisualKey>, Vec<u8>)> {
+    revision
+        .visuals()
+        .iter()
+        .map(|visual| (visual.usage, visual.data.to_vec()))
+        .collect()
+}
+
+fn find_sidecar_cover(dir: &Path) -> Option<PathBuf> {
+    let images = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| {
+                    SIDECAR_EXTENSIONS
+                        .iter()
+                        .any(|sidecar_ext| ext.eq_ignore_ascii_case(sidecar_ext))
+                })
+        })
+        .collect::<Vec<PathBuf>>();
+
+    SIDECAR_NAMES.iter().find_map(|name| {
+        images
+            .iter()
+            .find(|path| {
+                path.file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .is_some_and(|stem| stem.eq_ignore_ascii_case(name))
+            })
+            .cloned()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_sidecar_covers_by_name() {
+        let dir = std::env::temp_dir().join(format!("album_art_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for name in ["back.jpg", "Folder.JPG", "notes.txt"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+
+        assert_eq!(find_sidecar_cover(&dir), Some(dir.join("Folder.JPG")));
+
+        std::fs::write(dir.join("cover.png"), b"").unwrap();
+        assert_eq!(find_sidecar_cover(&dir), Some(dir.join("cover.png")));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn makes_thumbnails_of_sidecar_covers() {
+        let dir = std::env::temp_dir().join(format!("album_art_thumb_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        image::RgbImage::new(400, 200)
+            .save(dir.join("cover.png"))
+            .unwrap();
+
+        let (id, thumbnail) = load_thumbnail(&dir.join("track.mp3"), None).unwrap();
+        assert_eq!(thumbnail.size, [160, 80]);
+
+        // Every track of the folder shares the thumbnail, until the cover is replaced.
+        assert_eq!(load_thumbnail(&dir.join("other.mp3"), None).unwrap().0, id);
+        image::RgbImage::new(100, 400)
+            .save(dir.join("cover.png"))
+            .unwrap();
+        let (new_id, thumbnail) = load_thumbnail(&dir.join("track.mp3"), None).unwrap();
+        assert_ne!(new_id, id);
+        assert_eq!(thumbnail.size, [40, 160]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prunes_the_thumbnails_read_the_longest_ago() {
+        let dir = std::env::temp_dir().join(format!("album_art_prune_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let now = SystemTime::now();
+        for (name, age) in [("old.png", 60), ("new.png", 0), ("older.png", 120)] {
+            let file = std::fs::File::create(dir.join(name)).unwrap();
+            file.set_modified(now - std::time::Duration::from_secs(age))
+                .unwrap();
+        }
+
+        prune_cache(&dir, 2);
+        let mut left = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(left, ["new.png", "old.png"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
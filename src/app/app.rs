@@ -1,14 +1,16 @@
 use eframe::egui;
 use rb::RbConsumer;
 use std::sync::atomic::Ordering;
-use id3::{Tag, TagLike};
-use rand::Rng;
 
-use super::{App, LibraryItem, LibraryPathId, Playlist, UiCommand};
+use super::history::{REDO_SHORTCUT, UNDO_SHORTCUT};
+use super::replay_gain::ReplayGainMode;
+use super::{media, App, LibraryPathId, Playlist, UiCommand};
 use crate::app::components::{
     footer::Footer, library_component::LibraryComponent, menu_bar::MenuBar,
     player_component::PlayerComponent, playlist_table::PlaylistTable, playlist_tabs::PlaylistTabs,
-    scope_component::ScopeComponent, AppComponent,
+    queue_component::QueueComponent, scope_component::ScopeComponent,
+    smart_playlist_editor::SmartPlaylistEditor, tag_editor::TagEditor,
+    title_format_edit::title_format_edit, AppComponent,
 };
 use crate::player::TrackState;
 
@@ -31,11 +33,32 @@ impl eframe::App for App {
         if let Some(cmd_rx) = &self.ui_rx {
             match cmd_rx.try_recv() {
                 Ok(cmd) => match cmd {
-                    UiCommand::LibraryAddItem(lib_item) => self.library.add_item(lib_item),
                     UiCommand::LibraryAddItems(lib_items) => self.library.add_items(lib_items),
                     UiCommand::LibraryAddView(lib_view) => self.library.add_view(lib_view),
                     UiCommand::LibraryAddPathId(path_id) => {
-                        self.library.set_path_to_imported(path_id)
+                        self.library.set_path_to_imported(path_id);
+                        self.sync_library_watcher();
+                        self.relink_playlist_tracks();
+                    }
+                    UiCommand::LibraryUpdateItems(items) => self.update_library_items(items),
+                    UiCommand::LoudnessScanProgress { scanned, total } => {
+                        self.loudness_scan_progress = Some((scanned, total));
+                    }
+                    UiCommand::LibraryRescanned {
+                        added,
+                        updated,
+                        removed,
+                    } => {
+                        self.apply_library_rescan(added, updated, removed);
+                        self.relink_playlist_tracks();
+                    }
+                    UiCommand::LibraryFilesChanged(items) => self.library_files_changed(items),
+                    UiCommand::LibraryFileRenamed { from, to } => {
+                        self.library_file_renamed(from, to)
+                    }
+                    UiCommand::LibraryFilesRemoved(paths) => self.library_files_removed(paths),
+                    UiCommand::TagsWritten(items) => {
+                        self.apply_library_rescan(Vec::new(), items, Vec::new())
                     }
                     UiCommand::CurrentTimestamp(seek_timestamp) => {
                         self.player
@@ -45,54 +68,65 @@ impl eframe::App for App {
                     }
                     UiCommand::TotalTrackDuration(dur) => {
                         tracing::info!("Received Duration: {}", dur);
+                        self.failed_tracks = 0;
                         self.player.as_mut().unwrap().set_duration(dur);
                     }
                     UiCommand::SampleRate(sr) => {
                         tracing::info!("Received sample_rate: {}", sr);
                         self.player.as_mut().unwrap().set_sample_rate(sr);
                     }
+                    UiCommand::TrackAdvanced(path) => {
+                        tracing::info!("Audio thread advanced to {:?}", &path);
+                        self.record_play();
+                        self.player.as_mut().unwrap().advance_to_queued(&path);
+                    }
+                    UiCommand::AudioStopped => {
+                        tracing::info!("Audio thread stopped playback");
+                        self.player.as_mut().unwrap().track_state = TrackState::Stopped;
+                    }
+                    UiCommand::TrackFailed(path) => {
+                        tracing::info!("Audio thread couldn't play {:?}, skipping it", &path);
+                        self.skip_failed_track();
+                    }
                     UiCommand::AudioFinished => {
                         tracing::info!("Track finished, getting next...");
-
-                        self.player
-                            .as_mut()
-                            .unwrap()
-                            .next(&self.playlists[(self.current_playlist_idx).unwrap()]);
+                        self.record_play();
+                        self.next_track();
                     }
                 },
                 Err(_) => (),
             }
         }
 
+        self.refresh_smart_playlists();
+        self.queue_next_track();
+
         /* Drag files into playlist from Desktop */
-        if let Some(current_playlist_idx) = self.current_playlist_idx {
+        if self.current_playlist_idx.is_some() {
+            let mut dropped_items = Vec::new();
+
             ctx.input_mut(|i| {
-               for file in i.raw.dropped_files.iter() {
+                for file in i.raw.dropped_files.iter() {
                     if let Some(path) = &file.path {
                         tracing::info!("Dropped file: '{}'", path.display());
-                        if path.extension().unwrap_or(std::ffi::OsStr::new("")) == "mp3" {
-                            let tag = Tag::read_from_path(&path);
-                            let library_item = match tag {
-                                Ok(tag) => LibraryItem::new(path.clone(), LibraryPathId::new(rand::thread_rng().gen()))
-                                    .set_title(tag.title())
-                                    .set_artist(tag.artist())
-                                    .set_album(tag.album())
-                                    .set_year(tag.year())
-                                    .set_genre(tag.genre())
-                                    .set_track_number(tag.track()),
-                                Err(_err) => {
-                                    tracing::warn!("Couldn't parse to id3: {:?}", &path);
-                                    LibraryItem::new(path.clone(), LibraryPathId::new(0))
-                                }
-                            };
-
-                            let playlist = &mut self.playlists[current_playlist_idx];
-                            playlist.add(library_item);
+                        let library_id = LibraryPathId::from_path(path.parent().unwrap_or(path));
+                        if let Some(library_item) = media::read_library_item(path, library_id) {
+                            dropped_items.push(library_item);
                             tracing::info!("Added file to playlist: '{}'", &path.display());
-                       }
-                   }
-               } 
+                        }
+                    }
+                }
             });
+
+            self.add_to_current_playlist(dropped_items);
+        }
+
+        if !ctx.wants_keyboard_input() {
+            if ctx.input_mut(|i| i.consume_shortcut(&REDO_SHORTCUT)) {
+                self.redo();
+            } else if ctx.input_mut(|i| i.consume_shortcut(&UNDO_SHORTCUT)) {
+                self.undo();
+            }
         }
 
         // copy data from the gui ring buffer into a local collection
@@ -144,11 +178,7 @@ impl eframe::App for App {
         }
 
         if let Some(selected_track) = &self.player.as_mut().unwrap().selected_track {
-            let display = format!(
-                "{} - {} [ Music Player ]",
-                &selected_track.artist().unwrap_or("?".to_string()),
-                &selected_track.title().unwrap_or("?".to_string())
-            );
+            let display = self.display_formats.window_title(selected_track);
 
             ctx.send_viewport_cmd(egui::ViewportCommand::Title(display));
         }
@@ -161,13 +191,113 @@ impl eframe::App for App {
                 .enabled(true);
 
             window = window.open(&mut self.show_preferences_window);
-                
+            let mut is_watch_library_changed = false;
+
             window.show(ctx, |ui| {
                 ui.add(
                     egui::Slider::new(&mut self.rms_meter_window_size_millis, 5..=5000)
                         .text("RMS Meter Window Size (ms)"),
                 );
+
+                let crossfade_slider = ui.add(
+                    egui::Slider::new(&mut self.crossfade_millis, 0..=10000).text("Crossfade (ms)"),
+                );
+
+                if crossfade_slider.changed() {
+                    if let Some(player) = &self.player {
+                        player.set_crossfade(self.crossfade_millis);
+                    }
+                }
+
+                ui.separator();
+
+                let previous_replay_gain = self.replay_gain;
+
+                egui::ComboBox::from_label("ReplayGain")
+                    .selected_text(self.replay_gain.mode.to_string())
+                    .show_ui(ui, |ui| {
+                        for mode in [
+                            ReplayGainMode::Off,
+                            ReplayGainMode::Track,
+                            ReplayGainMode::Album,
+                        ] {
+                            ui.selectable_value(&mut self.replay_gain.mode, mode, mode.to_string());
+                        }
+                    });
+
+                ui.add(
+                    egui::Slider::new(&mut self.replay_gain.preamp_db, -15.0..=15.0)
+                        .text("ReplayGain Preamp (dB)"),
+                );
+
+                ui.checkbox(
+                    &mut self.replay_gain.prevent_clipping,
+                    "Prevent clipping according to peak",
+                );
+
+                if self.replay_gain != previous_replay_gain {
+                    if let Some(player) = &self.player {
+                        player.set_replay_gain(self.replay_gain);
+                    }
+                }
+
+                ui.checkbox(
+                    &mut self.write_replay_gain_tags,
+                    "Write scanned ReplayGain to MP3 tags",
+                );
+
+                ui.separator();
+
+                is_watch_library_changed = ui
+                    .checkbox(&mut self.watch_library, "Watch library folders for changes")
+                    .changed();
+
+                ui.separator();
+
+                ui.label("Window title");
+                title_format_edit(ui, &mut self.display_formats.window_title);
+
+                ui.label("Footer");
+                title_format_edit(ui, &mut self.display_formats.footer);
             });
+
+            if is_watch_library_changed {
+                self.sync_library_watcher();
+            }
+        }
+
+        if self.smart_playlist_draft.is_some() {
+            let mut is_open = true;
+
+            eframe::egui::Window::new("Smart Playlist")
+                .default_width(480.0)
+                .resizable([true, true])
+                .collapsible(false)
+                .open(&mut is_open)
+                .show(ctx, |ui| {
+                    SmartPlaylistEditor::add(self, ui);
+                });
+
+            if !is_open {
+                self.smart_playlist_draft = None;
+            }
+        }
+
+        if self.tag_edit_draft.is_some() {
+            let mut is_open = true;
+
+            eframe::egui::Window::new("Edit Tags")
+                .default_width(400.0)
+                .resizable([true, false])
+                .collapsible(false)
+                .open(&mut is_open)
+                .show(ctx, |ui| {
+                    TagEditor::add(self, ui);
+                });
+
+            if !is_open {
+                self.tag_edit_draft = None;
+            }
         }
 
         egui::TopBottomPanel::top("MusicPlayer").show(ctx, |ui| {
@@ -191,7 +321,7 @@ impl eframe::App for App {
                     .collapsible(false)
                     .enabled(true);
 
-                let mut show_osc = self.show_oscilloscope.clone();                    
+                let mut show_osc = self.show_oscilloscope.clone();
                 window = window.open(&mut show_osc);
 
                 window.show(ctx, |ui| {
@@ -206,6 +336,14 @@ impl eframe::App for App {
             Footer::add(self, ui);
         });
 
+        if self.show_queue {
+            egui::SidePanel::right("Queue Window")
+                .min_width(250.0)
+                .show(ctx, |ui| {
+                    QueueComponent::add(self, ui);
+                });
+        }
+
         egui::CentralPanel::default().show(ctx, |_ui| {
             egui::SidePanel::left("Library Window")
                 .min_width(250.0)
@@ -216,7 +354,8 @@ impl eframe::App for App {
 
         egui::CentralPanel::default().show(ctx, |_ui| {
             egui::TopBottomPanel::top("Playlist Tabs").show(ctx, |ui| {
-                let click_res = ui.interact(ui.response().rect, ui.response().id, egui::Sense::click());
+                let click_res =
+                    ui.interact(ui.response().rect, ui.response().id, egui::Sense::click());
 
                 if click_res.double_clicked() && !self.is_editing_playlist_name {
                     let default_name_count = self
@@ -233,14 +372,12 @@ impl eframe::App for App {
                     let mut new_playlist = Playlist::new();
                     new_playlist.set_name(playlist_name);
 
-                    self.playlists.push(new_playlist.clone());
-                    self.current_playlist_idx = Some(self.playlists.len() - 1);
+                    self.create_playlist(new_playlist);
                 }
 
                 PlaylistTabs::add(self, ui);
             });
 
-
             egui::CentralPanel::default().show(ctx, |ui| {
                 if let Some(_current_playlist_idx) = &mut self.current_playlist_idx {
                     egui::ScrollArea::both().show(ui, |ui| {
@@ -260,7 +397,7 @@ impl eframe::App for App {
                         .collapsible(false);
 
                     window = window.open(&mut self.show_rms_meter);
-                        
+
                     window.show(ctx, |ui| {
                         ui.add(
                             Meter::new(&[self.rms_meter[0], self.rms_meter[1]])
@@ -1,33 +1,68 @@
-use super::AppComponent;
-use crate::app::App;
-
-pub struct Footer;
-
-impl AppComponent for Footer {
-    type Context = App;
-
-    fn add(ctx: &mut Self::Context, ui: &mut eframe::egui::Ui) {
-        ui.horizontal(|ui| {
-            if ctx.player.as_ref().unwrap().is_stopped() {
-                ui.label("Stopped");
-            } else {
-                if let Some(selected_track) = &ctx.player.as_ref().unwrap().selected_track {
-                    ui.monospace(eframe::egui::RichText::new(
-                        ctx.player.as_ref().unwrap().track_state.to_string(),
-                    ));
-
-                    ui.label(eframe::egui::RichText::new(
-                        &selected_track
-                            .path()
-                            .as_path()
-                            .file_name()
-                            .unwrap()
-                            .to_os_string()
-                            .into_string()
-                            .unwrap(),
-                    ));
-                }
-            }
-        });
-    }
-}
+use super::AppComponent;
+use crate::app::library::format_duration;
+use crate::app::{App, LibraryItem};
+
+pub struct Footer;
+
+impl AppComponent for Footer {
+    type Context = App;
+
+    fn add(ctx: &mut Self::Context, ui: &mut eframe::egui::Ui) {
+        ui.horizontal(|ui| {
+            if ctx.player.as_ref().unwrap().is_stopped() {
+                ui.label("Stopped");
+            } else {
+                if let Some(selected_track) = &ctx.player.as_ref().unwrap().selected_track {
+                    ui.monospace(eframe::egui::RichText::new(
+                        ctx.player.as_ref().unwrap().track_state.to_string(),
+                    ));
+
+                    ui.label(ctx.display_formats.footer(selected_track));
+                }
+            }
+
+            if let Some((scanned, total)) = ctx.loudness_scan_progress {
+                if scanned < total {
+                    ui.separator();
+                    ui.label(format!("Scanning loudness {}/{}", scanned, total));
+                }
+            }
+
+            let current_playlist = ctx
+                .current_playlist_idx
+                .and_then(|idx| ctx.playlists.get(idx));
+
+            if let Some(playlist) = current_playlist {
+                let indices = playlist.selection.indices();
+                let summary = match indices.len() {
+                    0 | 1 => tracks_summary(playlist.tracks.iter()),
+                    _ => format!(
+                        "Selected {}",
+                        tracks_summary(indices.iter().map(|idx| &playlist.tracks[*idx]))
+                    ),
+                };
+
+                ui.with_layout(
+                    eframe::egui::Layout::right_to_left(eframe::egui::Align::Center),
+                    |ui| ui.label(summary),
+                );
+            }
+        });
+    }
+}
+
+// The number of tracks and their total length, ex. "12 tracks, 48:13". Tracks whose length isn't
+// known are left out of the total.
+fn tracks_summary<'a>(tracks: impl Iterator<Item = &'a LibraryItem>) -> String {
+    let (count, duration_millis) = tracks.fold((0, 0), |(count, duration_millis), track| {
+        (
+            count + 1,
+            duration_millis + track.properties().duration_millis.unwrap_or(0),
+        )
+    });
+
+    match count {
+        1 => format!("1 track, {}", format_duration(duration_millis)),
+        _ => format!("{} tracks, {}", count, format_duration(duration_millis)),
+    }
+}
@@ -1,54 +1,211 @@
-use super::AppComponent;
-use crate::app::App;
-
-pub struct LibraryComponent;
-
-impl AppComponent for LibraryComponent {
-    type Context = App;
-
-    fn add(ctx: &mut Self::Context, ui: &mut eframe::egui::Ui) {
-        eframe::egui::ScrollArea::both().show(ui, |ui| {
-            eframe::egui::CollapsingHeader::new(eframe::egui::RichText::new("All Music"))
-                .default_open(true)
-                .show(ui, |ui| {
-                    for container in &ctx.library.view().containers {
-                        let items = &container.items;
-
-                        let library_group = eframe::egui::CollapsingHeader::new(
-                            eframe::egui::RichText::new(&container.name),
-                        )
-                        .default_open(false)
-                        .show(ui, |ui| {
-                            for item in &container.items {
-                                let item_label = ui.add(
-                                    eframe::egui::Label::new(eframe::egui::RichText::new(
-                                        item.title().unwrap_or("?".to_string()),
-                                    ))
-                                    .sense(eframe::egui::Sense::click()),
-                                );
-
-                                if item_label.double_clicked() {
-                                    if let Some(current_playlist_idx) = &ctx.current_playlist_idx {
-                                        let current_playlist =
-                                            &mut ctx.playlists[*current_playlist_idx];
-
-                                        current_playlist.add(item.clone());
-                                    }
-                                }
-                            }
-                        });
-
-                        if let Some(current_playlist_idx) = &ctx.current_playlist_idx {
-                            let current_playlist = &mut ctx.playlists[*current_playlist_idx];
-
-                            if library_group.header_response.double_clicked() {
-                                for item in items {
-                                    current_playlist.add(item.clone());
-                                }
-                            }
-                        }
-                    }
-                });
-        });
-    }
-}
+use super::title_format_edit::title_format_edit;
+use super::AppComponent;
+use crate::app::album_art::AlbumArt;
+use crate::app::tag_edit::TagEditDraft;
+use crate::app::title_format::{TitleFormat, DEFAULT_GROUPING};
+use crate::app::{App, LibraryItem, LibraryItemContainer, ViewType};
+use eframe::egui::collapsing_header::CollapsingState;
+
+const THUMBNAIL_SIZE: f32 = 24.0;
+
+pub struct LibraryComponent;
+
+impl AppComponent for LibraryComponent {
+    type Context = App;
+
+    fn add(ctx: &mut Self::Context, ui: &mut eframe::egui::Ui) {
+        ui.add(
+            eframe::egui::TextEdit::singleline(&mut ctx.library_search.query)
+                .hint_text("Search, ex. artist:\"boards of canada\" year>1998")
+                .desired_width(f32::INFINITY),
+        );
+
+        ctx.library_search.update(&mut ctx.library);
+
+        if ctx.library_search.is_active() {
+            add_search_results(ctx, ui);
+            return;
+        }
+
+        let mut view_type = ctx.library.view().view_type.clone();
+
+        // The grouping script being typed, which only regroups the library once it's entered.
+        let grouping_id = ui.id().with("library_grouping");
+        let mut grouping = ui
+            .data_mut(|data| data.get_temp::<String>(grouping_id))
+            .unwrap_or_else(|| match &view_type {
+                ViewType::Custom(script) => script.clone(),
+                _ => DEFAULT_GROUPING.to_string(),
+            });
+
+        eframe::egui::ComboBox::from_label("View")
+            .selected_text(view_type.to_string())
+            .show_ui(ui, |ui| {
+                for option in ViewType::ALL {
+                    let text = option.to_string();
+                    ui.selectable_value(&mut view_type, option, text);
+                }
+
+                let custom = match &view_type {
+                    ViewType::Custom(_) => view_type.clone(),
+                    _ => ViewType::Custom(grouping.clone()),
+                };
+                ui.selectable_value(&mut view_type, custom, "Custom");
+            });
+
+        if let ViewType::Custom(script) = &view_type {
+            let response = title_format_edit(ui, &mut grouping)
+                .on_hover_text("The levels of the tree, separated by |, ex. %genre%|%album%");
+
+            if response.lost_focus() && grouping != *script && TitleFormat::parse(&grouping).is_ok()
+            {
+                view_type = ViewType::Custom(grouping.clone());
+            }
+        }
+
+        ui.data_mut(|data| data.insert_temp(grouping_id, grouping));
+
+        ctx.library.set_view_type(view_type);
+
+        // The library can't be borrowed by the playlist while it's drawn, so collect the items
+        // to add and add them afterwards.
+        let mut items_to_add = Vec::new();
+        let mut items_to_edit = None;
+
+        eframe::egui::ScrollArea::both().show(ui, |ui| {
+            eframe::egui::CollapsingHeader::new(eframe::egui::RichText::new("All Music"))
+                .default_open(true)
+                .show(ui, |ui| {
+                    for container in &ctx.library.view().containers {
+                        add_container(
+                            ui,
+                            container,
+                            &mut ctx.album_art,
+                            &mut items_to_add,
+                            &mut items_to_edit,
+                        );
+                    }
+                });
+        });
+
+        ctx.add_to_current_playlist(items_to_add);
+
+        if let Some(items) = items_to_edit {
+            ctx.tag_edit_draft = Some(TagEditDraft::new(items));
+        }
+    }
+}
+
+fn add_container(
+    ui: &mut eframe::egui::Ui,
+    container: &LibraryItemContainer,
+    album_art: &mut AlbumArt,
+    items_to_add: &mut Vec<LibraryItem>,
+    items_to_edit: &mut Option<Vec<LibraryItem>>,
+) {
+    let id = ui.make_persistent_id(&container.name);
+    let mut is_header_clicked = false;
+
+    let mut library_group = CollapsingState::load_with_default_open(ui.ctx(), id, false)
+        .show_header(ui, |ui| {
+            // Groups that hold tracks are albums, or folders that usually are, so they get art.
+            // The space for it is kept while it loads, so the names line up.
+            if let Some(item) = container.items.first() {
+                let thumbnail_size = eframe::egui::vec2(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+
+                match album_art.texture(ui.ctx(), item) {
+                    Some(texture) => {
+                        ui.add(
+                            eframe::egui::Image::new(&texture).fit_to_exact_size(thumbnail_size),
+                        );
+                    }
+                    None => {
+                        ui.allocate_space(thumbnail_size);
+                    }
+                }
+            }
+
+            let header_label = ui.add(
+                eframe::egui::Label::new(eframe::egui::RichText::new(&container.name))
+                    .sense(eframe::egui::Sense::click()),
+            );
+            is_header_clicked = header_label.clicked();
+
+            header_label
+        });
+
+    // Clicking the name opens the group, like clicking the arrow does.
+    if is_header_clicked {
+        library_group.toggle();
+    }
+
+    let (_, header, _) = library_group.body(|ui| {
+        for child in &container.children {
+            add_container(ui, child, album_art, items_to_add, items_to_edit);
+        }
+
+        for item in &container.items {
+            let item_label = ui.add(
+                eframe::egui::Label::new(eframe::egui::RichText::new(
+                    item.title().unwrap_or("?".to_string()),
+                ))
+                .sense(eframe::egui::Sense::click()),
+            );
+
+            if item_label.double_clicked() {
+                items_to_add.push(item.clone());
+            }
+
+            eframe::egui::containers::Popup::context_menu(&item_label).show(|ui| {
+                if ui.button("Edit Tags…").clicked() {
+                    *items_to_edit = Some(vec![item.clone()]);
+                }
+            });
+        }
+    });
+
+    if header.inner.double_clicked() {
+        items_to_add.extend(container.all_items().into_iter().cloned());
+    }
+
+    eframe::egui::containers::Popup::context_menu(&header.inner).show(|ui| {
+        if ui.button("Edit Tags…").clicked() {
+            *items_to_edit = Some(container.all_items().into_iter().cloned().collect());
+        }
+    });
+}
+
+fn add_search_results(ctx: &mut App, ui: &mut eframe::egui::Ui) {
+    let mut items_to_add = Vec::new();
+
+    eframe::egui::ScrollArea::both().show(ui, |ui| {
+        let results = &ctx.library_search.results;
+
+        let results_group = eframe::egui::CollapsingHeader::new(eframe::egui::RichText::new(
+            format!("Results ({})", results.len()),
+        ))
+        .default_open(true)
+        .show(ui, |ui| {
+            for item in results {
+                let item_label = ui.add(
+                    eframe::egui::Label::new(eframe::egui::RichText::new(format!(
+                        "{} - {}",
+                        item.artist().unwrap_or("?".to_string()),
+                        item.title().unwrap_or("?".to_string())
+                    )))
+                    .sense(eframe::egui::Sense::click()),
+                );
+
+                if item_label.double_clicked() {
+                    items_to_add.push(item.clone());
+                }
+            }
+        });
+
+        if results_group.header_response.double_clicked() {
+            items_to_add.extend(results.iter().cloned());
+        }
+    });
+
+    ctx.add_to_current_playlist(items_to_add);
+}
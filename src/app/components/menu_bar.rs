@@ -1,6 +1,10 @@
 use super::AppComponent;
 
-use crate::app::{library::LibraryPathStatus, App, Playlist};
+use crate::app::history::{REDO_SHORTCUT, UNDO_SHORTCUT};
+use crate::app::smart_playlist::{SmartPlaylist, SmartPlaylistDraft};
+use crate::app::{
+    library::LibraryPathStatus, player::PlaybackMode, playlist_file::PlaylistFormat, App, Playlist,
+};
 use egui_extras::{Column, TableBuilder};
 
 pub struct MenuBar;
@@ -34,11 +38,47 @@ impl AppComponent for MenuBar {
                     let mut new_playlist = Playlist::new();
                     new_playlist.set_name(playlist_name);
 
-                    ctx.playlists.push(new_playlist.clone());
-                    ctx.current_playlist_idx = Some(ctx.playlists.len() - 1);
+                    ctx.create_playlist(new_playlist);
+                }
+
+                if ui.button("New Smart Playlist").clicked() {
+                    ctx.smart_playlist_draft = Some(SmartPlaylistDraft {
+                        playlist_idx: None,
+                        name: "New Smart Playlist".to_string(),
+                        smart_playlist: SmartPlaylist::default(),
+                    });
+                }
+
+                if ui.button("Load Playlist").clicked() {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("Playlist", &PlaylistFormat::EXTENSIONS)
+                        .pick_file()
+                    {
+                        ctx.load_playlist(&path);
+                    }
+                }
+
+                let current_playlist = ctx.current_playlist_idx.and_then(|i| ctx.playlists.get(i));
+                let save_playlist_btn = ui.add_enabled(
+                    current_playlist.is_some(),
+                    eframe::egui::Button::new("Save Playlist"),
+                );
+
+                if save_playlist_btn.clicked() {
+                    let file_name = current_playlist
+                        .and_then(|playlist| playlist.get_name())
+                        .unwrap_or_else(|| "Playlist".to_string());
+
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("M3U", &["m3u8", "m3u"])
+                        .add_filter("PLS", &["pls"])
+                        .add_filter("XSPF", &["xspf"])
+                        .set_file_name(&format!("{}.m3u8", file_name))
+                        .save_file()
+                    {
+                        ctx.save_playlist(&path);
+                    }
                 }
-                let _load_playlist_btn = ui.button("Load Playlist");
-                let _save_playlist_btn = ui.button("Save Playlist");
 
                 ui.separator();
 
@@ -54,6 +94,36 @@ impl AppComponent for MenuBar {
             });
 
             ui.menu_button("Edit", |ui| {
+                let undo_text = match ctx.history.next_undo() {
+                    Some(edit) => format!("Undo {}", edit.description()),
+                    None => "Undo".to_string(),
+                };
+                let undo_btn = ui.add_enabled(
+                    ctx.history.next_undo().is_some(),
+                    eframe::egui::Button::new(undo_text)
+                        .shortcut_text(ui.ctx().format_shortcut(&UNDO_SHORTCUT)),
+                );
+
+                if undo_btn.clicked() {
+                    ctx.undo();
+                }
+
+                let redo_text = match ctx.history.next_redo() {
+                    Some(edit) => format!("Redo {}", edit.description()),
+                    None => "Redo".to_string(),
+                };
+                let redo_btn = ui.add_enabled(
+                    ctx.history.next_redo().is_some(),
+                    eframe::egui::Button::new(redo_text)
+                        .shortcut_text(ui.ctx().format_shortcut(&REDO_SHORTCUT)),
+                );
+
+                if redo_btn.clicked() {
+                    ctx.redo();
+                }
+
+                ui.separator();
+
                 let _remove_dup_btn = ui.button("Remove duplicates");
             });
 
@@ -78,17 +148,28 @@ impl AppComponent for MenuBar {
                     }
 
                     if next_btn.clicked() {
-                        ctx.player
-                            .as_mut()
-                            .unwrap()
-                            .next(&ctx.playlists[(ctx.current_playlist_idx).unwrap()])
+                        ctx.next_track();
                     }
 
                     if prev_btn.clicked() {
+                        ctx.previous_track();
+                    }
+                }
+
+                ui.separator();
+
+                for playback_mode in PlaybackMode::ALL {
+                    let mode_btn = ui.radio_value(
+                        &mut ctx.playback_mode,
+                        playback_mode,
+                        playback_mode.to_string(),
+                    );
+
+                    if mode_btn.clicked() {
                         ctx.player
                             .as_mut()
                             .unwrap()
-                            .previous(&ctx.playlists[(ctx.current_playlist_idx).unwrap()])
+                            .set_playback_mode(playback_mode);
                     }
                 }
             });
@@ -99,6 +180,22 @@ impl AppComponent for MenuBar {
                 if cfg_btn.clicked() {
                     ctx.is_library_cfg_open = true;
                 };
+
+                if ui.button("Rescan").clicked() {
+                    for lib_path in ctx.library.paths().iter() {
+                        ctx.rescan_library_path(lib_path);
+                    }
+                }
+
+                ui.separator();
+
+                if ui.button("Scan ReplayGain").clicked() {
+                    ctx.scan_loudness(false);
+                }
+
+                if ui.button("Rescan All ReplayGain").clicked() {
+                    ctx.scan_loudness(true);
+                }
             });
 
             ui.menu_button("View", |ui| {
@@ -109,6 +206,10 @@ impl AppComponent for MenuBar {
                 if ui.button("RMS Meter").clicked() {
                     ctx.show_rms_meter = !ctx.show_rms_meter;
                 }
+
+                if ui.button("Queue").clicked() {
+                    ctx.show_queue = !ctx.show_queue;
+                }
             });
 
             ui.menu_button("Help", |ui| {
@@ -195,10 +296,19 @@ impl AppComponent for MenuBar {
                             if ui.button("Remove selected paths").clicked() {
                                 // TODO - Should only appear clickable when a path is selected.
                                 // Will also remove any files in the library with the same LibraryPathId
-                                if !ctx.lib_config_selections.is_empty() {
-                                    for path_id in ctx.lib_config_selections.iter() {
-                                        ctx.library.remove_path(*path_id);
-                                    }
+                                for path_id in std::mem::take(&mut ctx.lib_config_selections) {
+                                    ctx.remove_library_path(path_id);
+                                }
+                            }
+
+                            if ui.button("Rescan selected paths").clicked() {
+                                for lib_path in ctx
+                                    .library
+                                    .paths()
+                                    .iter()
+                                    .filter(|p| ctx.lib_config_selections.contains(&p.id()))
+                                {
+                                    ctx.rescan_library_path(lib_path);
                                 }
                             }
 
@@ -1,13 +1,17 @@
-pub mod footer;
-pub mod library_component;
-pub mod menu_bar;
-pub mod player_component;
-pub mod playlist_table;
-pub mod playlist_tabs;
-pub mod scope_component;
-
-pub trait AppComponent {
-    type Context;
-
-    fn add(ctx: &mut Self::Context, ui: &mut eframe::egui::Ui);
-}
+pub mod footer;
+pub mod library_component;
+pub mod menu_bar;
+pub mod player_component;
+pub mod playlist_table;
+pub mod playlist_tabs;
+pub mod queue_component;
+pub mod scope_component;
+pub mod smart_playlist_editor;
+pub mod tag_editor;
+pub mod title_format_edit;
+
+pub trait AppComponent {
+    type Context;
+
+    fn add(ctx: &mut Self::Context, ui: &mut eframe::egui::Ui);
+}
@@ -3,6 +3,9 @@ use crate::app::App;
 use crate::egui::style::HandleShape;
 use crate::egui::SliderClamping;
 
+// The art is shown at full size when hovered.
+const ART_SIZE: f32 = 40.0;
+
 pub struct PlayerComponent;
 
 impl AppComponent for PlayerComponent {
@@ -10,6 +13,23 @@ impl AppComponent for PlayerComponent {
 
     fn add(ctx: &mut Self::Context, ui: &mut eframe::egui::Ui) {
         ui.horizontal(|ui| {
+            // The space for the art is kept while it loads, so the controls don't move.
+            let art_size = eframe::egui::vec2(ART_SIZE, ART_SIZE);
+            let selected_track = ctx.player.as_ref().unwrap().selected_track.clone();
+            let texture = selected_track.and_then(|track| ctx.album_art.texture(ui.ctx(), &track));
+
+            match texture {
+                Some(texture) => {
+                    ui.add(eframe::egui::Image::new(&texture).fit_to_exact_size(art_size))
+                        .on_hover_ui(|ui| {
+                            ui.image(&texture);
+                        });
+                }
+                None => {
+                    ui.allocate_space(art_size);
+                }
+            }
+
             let stop_btn = ui.button("■");
             let play_btn = ui.button("▶");
             let pause_btn = ui.button("⏸");
@@ -87,17 +107,11 @@ impl AppComponent for PlayerComponent {
                 }
 
                 if prev_btn.clicked() {
-                    ctx.player
-                        .as_mut()
-                        .unwrap()
-                        .previous(&ctx.playlists[(ctx.current_playlist_idx).unwrap()]);
+                    ctx.previous_track();
                 }
 
                 if next_btn.clicked() {
-                    ctx.player
-                        .as_mut()
-                        .unwrap()
-                        .next(&ctx.playlists[(ctx.current_playlist_idx).unwrap()]);
+                    ctx.next_track();
                 }
             }
 
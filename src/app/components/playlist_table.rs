@@ -1,133 +1,415 @@
+use super::title_format_edit::title_format_edit;
 use super::AppComponent;
-use crate::app::App;
+use crate::app::history::Edit;
+use crate::app::playlist_columns::{
+    self, ColumnAlign, ColumnField, PlaylistColumn, PlaylistColumns, SortKey, DEFAULT_CUSTOM_FORMAT,
+};
+use crate::app::tag_edit::TagEditDraft;
+use crate::app::{App, LibraryItem};
 use eframe::egui;
 use egui_extras::{Column, TableBuilder};
 
+/// The rows being dragged out of the playlist table, to another position or another playlist.
+pub struct DraggedTracks {
+    pub playlist_idx: usize,
+    pub indices: Vec<usize>,
+    pub tracks: Vec<LibraryItem>,
+}
+
+const MIN_COLUMN_WIDTH: f32 = 16.0;
+// The width a column starts at when it's given one.
+const DEFAULT_COLUMN_WIDTH: f32 = 100.0;
+
 pub struct PlaylistTable;
 
 impl AppComponent for PlaylistTable {
     type Context = App;
 
     fn add(ctx: &mut Self::Context, ui: &mut eframe::egui::Ui) {
-        if let Some(current_playlist_idx) = &mut ctx.current_playlist_idx {
+        if let Some(playlist_idx) = ctx.current_playlist_idx {
+            let playlist = &ctx.playlists[playlist_idx];
+            let columns = playlist
+                .columns
+                .clone()
+                .unwrap_or_else(|| ctx.playlist_columns.clone());
+            let sort_keys = playlist.sort_keys.clone();
+            // Smart playlists are sorted by their rules.
+            let is_sortable = !playlist.is_smart();
+
+            // The columns and the sort are changed once the table is drawn.
+            let mut edited_columns = columns.clone();
+            let mut has_own_columns = playlist.columns.is_some();
+            let mut clicked_header = None;
+
             let available_height = ui.available_height();
-            let table = TableBuilder::new(ui)
+            let mut table = TableBuilder::new(ui)
+                // Widths dragged in one set of columns don't carry over to another.
+                .id_salt(
+                    columns
+                        .columns
+                        .iter()
+                        .map(|column| (column.field, column.width.map(f32::to_bits)))
+                        .collect::<Vec<(ColumnField, Option<u32>)>>(),
+                )
                 .striped(false)
                 .resizable(true)
                 .cell_layout(eframe::egui::Layout::left_to_right(
                     eframe::egui::Align::Center,
-                ))
-                .column(Column::remainder()) // Playing
-                .column(Column::remainder()) // number
-                .column(Column::remainder()) // artist
-                .column(Column::remainder()) // album
-                .column(Column::remainder()) // title
-                .column(Column::remainder()) // genre
-                .sense(eframe::egui::Sense::click())
-                .min_scrolled_height(0.0)
-                .max_scroll_height(available_height);
+                ));
+
+            for column in &columns.columns {
+                let table_column = match column.width {
+                    Some(width) => Column::initial(width).at_least(MIN_COLUMN_WIDTH),
+                    None => Column::remainder(),
+                };
+                table = table.column(table_column.clip(true));
+            }
 
             table
+                .sense(eframe::egui::Sense::click_and_drag())
+                .min_scrolled_height(0.0)
+                .max_scroll_height(available_height)
                 .header(20.0, |mut header| {
-                    header.col(|ui| {
-                        ui.strong("Playing");
-                    });
-                    header.col(|ui| {
-                        ui.strong("#");
-                    });
-                    header.col(|ui| {
-                        ui.strong("Artist");
-                    });
-                    header.col(|ui| {
-                        ui.strong("Album");
-                    });
-                    header.col(|ui| {
-                        ui.strong("Title");
-                    });
-                    header.col(|ui| {
-                        ui.strong("Genre");
-                    });
+                    for (column_idx, column) in columns.columns.iter().enumerate() {
+                        header.col(|ui| {
+                            let header_text = header_text(column, &sort_keys);
+                            let header_label = ui
+                                .with_layout(column.align.layout(), |ui| {
+                                    ui.add(
+                                        egui::Label::new(egui::RichText::new(header_text).strong())
+                                            .sense(egui::Sense::click()),
+                                    )
+                                })
+                                .inner;
+
+                            if header_label.clicked() && is_sortable && column.field.is_sortable() {
+                                let is_added = ui.input(|i| i.modifiers.shift);
+                                clicked_header = Some((column.clone(), is_added));
+                            }
+
+                            egui::containers::Popup::context_menu(&header_label)
+                                .id(egui::Id::new(("playlist_column_menu", column_idx)))
+                                .show(|ui| {
+                                    column_menu(
+                                        ui,
+                                        &mut edited_columns,
+                                        column_idx,
+                                        &mut has_own_columns,
+                                    );
+                                });
+                        });
+                    }
                 })
                 .body(|mut body| {
-                    let mut track_to_remove = None;
+                    // The playlist can't change while it's being drawn, so changes to it and its
+                    // selection are applied afterwards.
+                    let mut clicked_row = None;
+                    let mut remove_selected = false;
+                    let mut drop_at = None;
 
-                    for (track_idx, track) in ctx.playlists[*current_playlist_idx]
-                        .tracks
+                    let playlist = &ctx.playlists[playlist_idx];
+
+                    let title_formats = columns
+                        .columns
                         .iter()
-                        .enumerate()
-                    {
+                        .map(PlaylistColumn::title_format)
+                        .collect::<Vec<_>>();
+
+                    for (track_idx, track) in playlist.tracks.iter().enumerate() {
                         body.row(20.0, |mut row| {
-                            // Playing
-                            if let Some(selected_track) =
-                                &ctx.player.as_ref().unwrap().selected_track
+                            row.set_selected(playlist.selection.contains(track_idx));
+
+                            let is_playing = ctx
+                                .player
+                                .as_ref()
+                                .unwrap()
+                                .selected_track
+                                .as_ref()
+                                .is_some_and(|selected_track| selected_track == track);
+
+                            for (column, title_format) in columns.columns.iter().zip(&title_formats)
                             {
-                                if selected_track == track {
-                                    row.set_selected(true);
-                                    row.col(|ui| {
-                                        ui.label("▶".to_string());
-                                    });
-                                } else {
-                                    row.col(|ui| {
-                                        ui.label(" ".to_string());
-                                    });
-                                }
-                            } else {
-                                row.col(|ui| {
-                                    ui.label(" ".to_string());
-                                });
-                            }
+                                let text = match title_format {
+                                    Some(title_format) => title_format.format(track),
+                                    None => column.field.text(track),
+                                };
 
-                            // Track No.
-                            if let Some(track_number) = &track.track_number() {
-                                row.col(|ui| {
-                                    ui.label(&track_number.to_string());
-                                });
-                            } else {
                                 row.col(|ui| {
-                                    ui.label("".to_string());
+                                    ui.with_layout(column.align.layout(), |ui| {
+                                        match column.field {
+                                            ColumnField::Playing => {
+                                                ui.label(if is_playing { "▶" } else { " " })
+                                            }
+                                            _ => ui.add(egui::Label::new(text).truncate()),
+                                        }
+                                    });
                                 });
                             }
 
-                            row.col(|ui| {
-                                ui.label(&track.artist().unwrap_or("?".to_string()));
-                            });
-                            row.col(|ui| {
-                                ui.label(&track.album().unwrap_or("?".to_string()));
-                            });
-                            row.col(|ui| {
-                                ui.label(&track.title().unwrap_or("?".to_string()));
-                            });
-                            row.col(|ui| {
-                                ui.label(&track.genre().unwrap_or("?".to_string()));
-                            });
+                            let response = row.response();
 
-                            if row.response().double_clicked() {
+                            if response.double_clicked() {
+                                ctx.playing_playlist_idx = Some(playlist_idx);
                                 ctx.player
                                     .as_mut()
                                     .unwrap()
                                     .select_track(Some(track.clone()));
                                 ctx.player.as_mut().unwrap().play();
+                            } else if response.clicked() {
+                                clicked_row =
+                                    Some((track_idx, response.ctx.input(|i| i.modifiers)));
+                            }
+
+                            // Dragging a row that isn't selected drags only that row.
+                            let dragged_indices = match playlist.selection.contains(track_idx) {
+                                true => playlist.selection.indices(),
+                                false => vec![track_idx],
+                            };
+
+                            if response.drag_started() {
+                                if !playlist.selection.contains(track_idx) {
+                                    clicked_row = Some((track_idx, egui::Modifiers::NONE));
+                                }
+
+                                response.dnd_set_drag_payload(DraggedTracks {
+                                    playlist_idx,
+                                    tracks: dragged_indices
+                                        .iter()
+                                        .map(|idx| playlist.tracks[*idx].clone())
+                                        .collect(),
+                                    indices: dragged_indices.clone(),
+                                });
                             }
 
-                            // TODO - If I decide to keep this, there needs to be some
-                            // difference between the selected track and currently playing track
-                            // if row.response().clicked() {
-                            //     ctx.player.as_mut().unwrap().selected_track = Some(track.clone());
-                            // }
+                            // Dropping on the top half of a row puts the tracks above it.
+                            if response.dnd_hover_payload::<DraggedTracks>().is_some() {
+                                let rect = response.rect;
+                                let is_above = response
+                                    .ctx
+                                    .pointer_hover_pos()
+                                    .is_some_and(|pos| pos.y < rect.center().y);
+                                let y = if is_above { rect.top() } else { rect.bottom() };
+
+                                response
+                                    .ctx
+                                    .layer_painter(egui::LayerId::new(
+                                        egui::Order::Foreground,
+                                        egui::Id::new("playlist_drop_marker"),
+                                    ))
+                                    .hline(
+                                        rect.x_range(),
+                                        y,
+                                        response.ctx.style().visuals.selection.stroke,
+                                    );
+
+                                if let Some(dragged) =
+                                    response.dnd_release_payload::<DraggedTracks>()
+                                {
+                                    let destination = match is_above {
+                                        true => track_idx,
+                                        false => track_idx + 1,
+                                    };
+                                    drop_at = Some((dragged, destination));
+                                }
+                            }
+
+                            egui::containers::Popup::context_menu(&response)
+                                .id(egui::Id::new(format!("playlist_track_menu {}", track_idx)))
+                                .show(|ui| {
+                                    if ui.button("Play Next").clicked() {
+                                        // Queued in reverse so they still play in order.
+                                        for idx in dragged_indices.iter().rev() {
+                                            ctx.player
+                                                .as_mut()
+                                                .unwrap()
+                                                .play_next(playlist.tracks[*idx].clone());
+                                        }
+                                    }
+
+                                    if ui.button("Add to Queue").clicked() {
+                                        for idx in dragged_indices.iter() {
+                                            ctx.player
+                                                .as_mut()
+                                                .unwrap()
+                                                .add_to_queue(playlist.tracks[*idx].clone());
+                                        }
+                                    }
+
+                                    ui.separator();
 
-                            if row.response().clicked_by(egui::PointerButton::Secondary) {
-                                // TODO: send a msg to remove the track from the playlist
-                                // This should ideally be handled by a UI Command processor
-                                track_to_remove = Some(track_idx);
+                                    if ui.button("Edit Tags…").clicked() {
+                                        ctx.tag_edit_draft = Some(TagEditDraft::new(
+                                            dragged_indices
+                                                .iter()
+                                                .map(|idx| playlist.tracks[*idx].clone())
+                                                .collect(),
+                                        ));
+                                    }
+
+                                    if ui.button("Remove").clicked() {
+                                        remove_selected = true;
+                                    }
+                                });
+
+                            // Right clicking a row that isn't selected acts on that row alone.
+                            if response.secondary_clicked()
+                                && !playlist.selection.contains(track_idx)
+                            {
+                                clicked_row = Some((track_idx, egui::Modifiers::NONE));
                             }
                         })
                     }
 
-                    // We can't remove the track from the playlist while it is iterating
-                    if let Some(remove_id) = track_to_remove {
-                        ctx.playlists[*current_playlist_idx].remove(remove_id);
+                    let is_typing = body.ui_mut().ctx().wants_keyboard_input();
+                    if !is_typing && body.ui_mut().input(|i| i.key_pressed(egui::Key::Delete)) {
+                        remove_selected = true;
+                    }
+
+                    let playlist = &mut ctx.playlists[playlist_idx];
+
+                    if let Some((track_idx, modifiers)) = clicked_row {
+                        if modifiers.shift {
+                            playlist.selection.extend_to(track_idx);
+                        } else if modifiers.command {
+                            playlist.selection.toggle(track_idx);
+                        } else {
+                            playlist.selection.select_only(track_idx);
+                        }
+                    }
+
+                    if remove_selected {
+                        let indices = playlist.selection.indices();
+                        let edit = Edit::remove_tracks(playlist, playlist_idx, &indices);
+                        ctx.execute(edit);
+                    }
+
+                    // Tracks dragged in from another playlist, by holding them over this
+                    // playlist's tab, are copied.
+                    if let Some((dragged, destination)) = drop_at {
+                        if dragged.playlist_idx == playlist_idx {
+                            ctx.execute(Edit::move_tracks(
+                                playlist_idx,
+                                &dragged.indices,
+                                destination,
+                            ));
+                        } else {
+                            ctx.execute(Edit::AddTracks {
+                                playlist_idx,
+                                at: destination,
+                                tracks: dragged.tracks.clone(),
+                            });
+                        }
                     }
                 });
+
+            let playlist = &mut ctx.playlists[playlist_idx];
+
+            if has_own_columns {
+                playlist.columns = Some(edited_columns);
+            } else if playlist.columns.take().is_none() {
+                ctx.playlist_columns = edited_columns;
+            }
+
+            if let Some((column, is_added)) = clicked_header {
+                let sort_keys = playlist_columns::clicked_sort_keys(&sort_keys, &column, is_added);
+                let edit = Edit::sort_tracks(&ctx.playlists[playlist_idx], playlist_idx, sort_keys);
+                ctx.execute(edit);
+            }
+        }
+    }
+}
+
+// The title of the column, with an arrow when the tracks are sorted by it. The arrows are
+// numbered when they're sorted by more than one column.
+fn header_text(column: &PlaylistColumn, sort_keys: &[SortKey]) -> String {
+    let title = column.title();
+    let Some(key_idx) = sort_keys.iter().position(|key| key.sorts_by(column)) else {
+        return title;
+    };
+
+    let arrow = match sort_keys[key_idx].descending {
+        true => "⏷",
+        false => "⏶",
+    };
+
+    match sort_keys.len() {
+        1 => format!("{} {}", title, arrow),
+        _ => format!("{} {}{}", title, arrow, key_idx + 1),
+    }
+}
+
+// Shows and hides columns, and arranges the one that was right-clicked.
+fn column_menu(
+    ui: &mut egui::Ui,
+    columns: &mut PlaylistColumns,
+    column_idx: usize,
+    has_own_columns: &mut bool,
+) {
+    for field in ColumnField::ALL {
+        let mut is_shown = columns.contains(field);
+        if ui.checkbox(&mut is_shown, field.to_string()).changed() {
+            columns.toggle(field);
+        }
+    }
+
+    if ui.button("➕ Add Custom Column").clicked() {
+        columns
+            .columns
+            .push(PlaylistColumn::custom("Custom", DEFAULT_CUSTOM_FORMAT));
+    }
+
+    ui.separator();
+
+    ui.horizontal(|ui| {
+        if ui.button("⏴ Move Left").clicked() {
+            columns.move_column(column_idx, false);
+        }
+
+        if ui.button("Move Right ⏵").clicked() {
+            columns.move_column(column_idx, true);
+        }
+    });
+
+    if let Some(column) = columns.columns.get_mut(column_idx) {
+        ui.horizontal(|ui| {
+            ui.label("Align");
+
+            for align in ColumnAlign::ALL {
+                ui.selectable_value(&mut column.align, align, align.to_string());
+            }
+        });
+
+        ui.horizontal(|ui| {
+            let mut has_width = column.width.is_some();
+            let mut width = column.width.unwrap_or(DEFAULT_COLUMN_WIDTH);
+
+            ui.checkbox(&mut has_width, "Width");
+            ui.add_enabled(
+                has_width,
+                egui::DragValue::new(&mut width).range(MIN_COLUMN_WIDTH..=1000.0),
+            );
+
+            column.width = has_width.then_some(width);
+        });
+
+        if column.field == ColumnField::Custom {
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                ui.label("Name");
+                ui.text_edit_singleline(&mut column.name);
+            });
+
+            ui.label("Title formatting script");
+            title_format_edit(ui, &mut column.format);
+
+            if ui.button("➖ Remove Column").clicked() {
+                columns.remove_column(column_idx);
+            }
         }
     }
+
+    ui.separator();
+
+    ui.checkbox(has_own_columns, "Only for this playlist");
 }
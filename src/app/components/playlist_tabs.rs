@@ -1,4 +1,7 @@
+use super::playlist_table::DraggedTracks;
 use super::AppComponent;
+use crate::app::history::Edit;
+use crate::app::smart_playlist::SmartPlaylistDraft;
 use crate::app::App;
 use eframe::egui;
 
@@ -9,6 +12,10 @@ impl AppComponent for PlaylistTabs {
 
     fn add(ctx: &mut Self::Context, ui: &mut eframe::egui::Ui) {
         ui.horizontal_wrapped(|ui| {
+            // The playlists can't be edited through the history while they're being drawn, so
+            // the edit is made afterwards.
+            let mut edit = None;
+
             for (idx, playlist) in ctx.playlists.iter_mut().enumerate() {
                 let mut playlist_name = playlist.get_name().unwrap();
 
@@ -21,48 +28,75 @@ impl AppComponent for PlaylistTabs {
 
                     if response.lost_focus() || ui.input(|i| i.key_pressed(egui::Key::Enter)) {
                         ctx.is_editing_playlist_name = false;
-                        playlist.is_editing_name = false;
+
+                        if let Some((from, to)) = playlist.finish_editing_name() {
+                            edit = Some(Edit::RenamePlaylist {
+                                playlist_idx: idx,
+                                from,
+                                to,
+                            });
+                        }
                     }
                 } else {
+                    // Smart playlists stand out, since their tracks can't be changed by hand.
+                    let tab_text = match playlist.is_smart() {
+                        true => egui::RichText::new(format!("⚙ {}", playlist_name)).italics(),
+                        false => egui::RichText::new(playlist_name),
+                    };
                     let playlist_tab =
-                        ui.add(egui::Label::new(playlist_name).sense(egui::Sense::click()));
+                        ui.add(egui::Label::new(tab_text).sense(egui::Sense::click()));
 
                     if playlist_tab.clicked() {
                         ctx.current_playlist_idx = Some(idx);
                     }
 
+                    // Holding dragged tracks over a tab opens it, so they can be dropped at a
+                    // position in its table. Dropping them on the tab adds them at the end.
+                    if playlist_tab.dnd_hover_payload::<DraggedTracks>().is_some() {
+                        ctx.current_playlist_idx = Some(idx);
+                    }
+
+                    if let Some(dragged) = playlist_tab.dnd_release_payload::<DraggedTracks>() {
+                        if dragged.playlist_idx != idx {
+                            edit = Some(Edit::AddTracks {
+                                playlist_idx: idx,
+                                at: playlist.tracks.len(),
+                                tracks: dragged.tracks.clone(),
+                            });
+                        }
+                    }
+
                     egui::containers::Popup::context_menu(&playlist_tab)
                         .id(egui::Id::new(format!("playlist_options_menu {}", idx)))
                         .show(|ui| {
+                            if let Some(smart_playlist) = playlist.smart_playlist() {
+                                if ui.button("Edit Rules").clicked() {
+                                    ctx.smart_playlist_draft = Some(SmartPlaylistDraft {
+                                        playlist_idx: Some(idx),
+                                        name: playlist.get_name().unwrap_or_default(),
+                                        smart_playlist: smart_playlist.clone(),
+                                    });
+                                }
+                            }
+
                             if ui.button("Remove Playlist").clicked() {
                                 ctx.playlist_idx_to_remove = Some(idx);
                             }
                         });
 
                     if playlist_tab.double_clicked() && !ctx.is_editing_playlist_name {
-                        playlist.is_editing_name = true;
+                        playlist.start_editing_name();
                         ctx.is_editing_playlist_name = true;
                     }
                 }
             }
 
-            if let Some(idx) = ctx.playlist_idx_to_remove {
-                ctx.playlist_idx_to_remove = None;
-
-                // Because the current playlist is referenced via index, we need to take
-                // into account that the index may be out of bounds when removing a
-                // playlist. This should be resolved when I figure out how to reference the
-                // actual selected playlist.
-                if let Some(mut current_playlist_idx) = ctx.current_playlist_idx {
-                    if current_playlist_idx == 0 && idx == 0 {
-                        ctx.current_playlist_idx = None;
-                    } else if current_playlist_idx >= idx {
-                        current_playlist_idx -= 1;
-                        ctx.current_playlist_idx = Some(current_playlist_idx);
-                    }
-                }
+            if let Some(edit) = edit {
+                ctx.execute(edit);
+            }
 
-                ctx.playlists.remove(idx);
+            if let Some(idx) = ctx.playlist_idx_to_remove.take() {
+                ctx.remove_playlist(idx);
             }
         });
     }
@@ -0,0 +1,68 @@
+use super::AppComponent;
+use crate::app::App;
+use eframe::egui;
+
+pub struct QueueComponent;
+
+impl AppComponent for QueueComponent {
+    type Context = App;
+
+    fn add(ctx: &mut Self::Context, ui: &mut eframe::egui::Ui) {
+        let player = ctx.player.as_mut().unwrap();
+
+        ui.horizontal(|ui| {
+            ui.strong(format!("Queue ({})", player.queue.len()));
+
+            if ui.button("Clear").clicked() {
+                player.clear_queue();
+            }
+        });
+
+        ui.separator();
+
+        if player.queue.is_empty() {
+            ui.label("Right click a track and choose \"Add to Queue\" to play it next.");
+            return;
+        }
+
+        // The queue can't change while it's being drawn, so apply the change afterwards.
+        let mut track_to_move = None;
+        let mut track_to_remove = None;
+        let last_idx = player.queue.len() - 1;
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for (idx, track) in player.queue.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(idx > 0, egui::Button::new("⏶")).clicked() {
+                        track_to_move = Some((idx, idx - 1));
+                    }
+
+                    if ui
+                        .add_enabled(idx < last_idx, egui::Button::new("⏷"))
+                        .clicked()
+                    {
+                        track_to_move = Some((idx, idx + 1));
+                    }
+
+                    if ui.button("✖").clicked() {
+                        track_to_remove = Some(idx);
+                    }
+
+                    ui.label(format!(
+                        "{} - {}",
+                        track.artist().unwrap_or("?".to_string()),
+                        track.title().unwrap_or("?".to_string())
+                    ));
+                });
+            }
+        });
+
+        if let Some((current_pos, destination_pos)) = track_to_move {
+            player.move_in_queue(current_pos, destination_pos);
+        }
+
+        if let Some(idx) = track_to_remove {
+            player.remove_from_queue(idx);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use super::AppComponent;
+use crate::app::smart_playlist::{Rule, RuleField};
+use crate::app::{App, Playlist};
+use eframe::egui;
+
+// How many tracks a new limit starts at.
+const DEFAULT_LIMIT: usize = 25;
+
+/// The rule builder of the smart playlist being edited.
+pub struct SmartPlaylistEditor;
+
+impl AppComponent for SmartPlaylistEditor {
+    type Context = App;
+
+    fn add(ctx: &mut Self::Context, ui: &mut eframe::egui::Ui) {
+        let Some(draft) = ctx.smart_playlist_draft.as_mut() else {
+            return;
+        };
+        let smart_playlist = &mut draft.smart_playlist;
+
+        ui.horizontal(|ui| {
+            ui.label("Name");
+            ui.text_edit_singleline(&mut draft.name);
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Match");
+            egui::ComboBox::from_id_salt("smart_playlist_match")
+                .selected_text(if smart_playlist.match_all {
+                    "all"
+                } else {
+                    "any"
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut smart_playlist.match_all, true, "all");
+                    ui.selectable_value(&mut smart_playlist.match_all, false, "any");
+                });
+            ui.label("of the following rules");
+        });
+
+        ui.separator();
+
+        // A rule can't be removed while the rules are being drawn.
+        let mut rule_to_remove = None;
+
+        for (idx, rule) in smart_playlist.rules.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                let mut field = rule.field;
+                egui::ComboBox::from_id_salt(("smart_playlist_field", idx))
+                    .selected_text(field.to_string())
+                    .show_ui(ui, |ui| {
+                        for rule_field in RuleField::ALL {
+                            ui.selectable_value(&mut field, rule_field, rule_field.to_string());
+                        }
+                    });
+
+                if field != rule.field {
+                    rule.set_field(field);
+                }
+
+                egui::ComboBox::from_id_salt(("smart_playlist_operator", idx))
+                    .selected_text(rule.operator.to_string())
+                    .show_ui(ui, |ui| {
+                        for operator in rule.field.operators() {
+                            ui.selectable_value(
+                                &mut rule.operator,
+                                *operator,
+                                operator.to_string(),
+                            );
+                        }
+                    });
+
+                let value =
+                    ui.add(egui::TextEdit::singleline(&mut rule.value).desired_width(120.0));
+                if !rule.is_valid() {
+                    value.on_hover_text("Should be a whole number");
+                    ui.colored_label(ui.visuals().error_fg_color, "⚠");
+                }
+
+                if let Some(unit) = rule.field.unit() {
+                    ui.label(unit);
+                }
+
+                if ui.button("➖").clicked() {
+                    rule_to_remove = Some(idx);
+                }
+            });
+        }
+
+        if let Some(idx) = rule_to_remove {
+            smart_playlist.rules.remove(idx);
+        }
+
+        if ui.button("➕ Add Rule").clicked() {
+            smart_playlist.rules.push(Rule::new(RuleField::Genre));
+        }
+
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            ui.label("Sort by");
+            egui::ComboBox::from_id_salt("smart_playlist_sort_by")
+                .selected_text(match smart_playlist.sort_by {
+                    Some(field) => field.to_string(),
+                    None => "Library order".to_string(),
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut smart_playlist.sort_by, None, "Library order");
+
+                    for field in RuleField::ALL {
+                        ui.selectable_value(
+                            &mut smart_playlist.sort_by,
+                            Some(field),
+                            field.to_string(),
+                        );
+                    }
+                });
+
+            ui.add_enabled(
+                smart_playlist.sort_by.is_some(),
+                egui::Checkbox::new(&mut smart_playlist.descending, "Descending"),
+            );
+        });
+
+        ui.horizontal(|ui| {
+            let mut is_limited = smart_playlist.limit.is_some();
+            let mut limit = smart_playlist.limit.unwrap_or(DEFAULT_LIMIT);
+
+            ui.checkbox(&mut is_limited, "Limit to");
+            ui.add_enabled(
+                is_limited,
+                egui::DragValue::new(&mut limit).range(1..=usize::MAX),
+            );
+            ui.label("tracks");
+
+            smart_playlist.limit = is_limited.then_some(limit);
+        });
+
+        ui.separator();
+
+        let mut is_saved = false;
+        let mut is_cancelled = false;
+
+        ui.horizontal(|ui| {
+            is_saved = ui.button("Save").clicked();
+            is_cancelled = ui.button("Cancel").clicked();
+        });
+
+        if is_cancelled {
+            ctx.smart_playlist_draft = None;
+        }
+
+        if !is_saved {
+            return;
+        }
+
+        let Some(draft) = ctx.smart_playlist_draft.take() else {
+            return;
+        };
+
+        match draft
+            .playlist_idx
+            .and_then(|idx| ctx.playlists.get_mut(idx))
+        {
+            Some(playlist) => {
+                playlist.set_name(draft.name);
+                playlist.set_smart_playlist(draft.smart_playlist);
+            }
+            None => {
+                let mut playlist = Playlist::new_smart(draft.smart_playlist);
+                playlist.set_name(draft.name);
+
+                ctx.create_playlist(playlist);
+            }
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use super::AppComponent;
+use crate::app::tag_edit::{CaseStyle, MULTIPLE_VALUES};
+use crate::app::App;
+use eframe::egui;
+
+/// The tags of the tracks open for editing, with changes to all of them at once.
+pub struct TagEditor;
+
+impl AppComponent for TagEditor {
+    type Context = App;
+
+    fn add(ctx: &mut Self::Context, ui: &mut eframe::egui::Ui) {
+        let Some(draft) = ctx.tag_edit_draft.as_mut() else {
+            return;
+        };
+
+        match draft.tracks.len() {
+            1 => ui.label(draft.tracks[0].path().display().to_string()),
+            count => ui.label(format!("{} tracks", count)),
+        };
+
+        ui.separator();
+
+        egui::Grid::new("tag_editor_fields")
+            .num_columns(2)
+            .spacing([8.0, 4.0])
+            .show(ui, |ui| {
+                for (field, value) in draft.values.iter_mut() {
+                    ui.label(field.to_string());
+
+                    ui.horizontal(|ui| {
+                        let response =
+                            ui.add(egui::TextEdit::singleline(value).desired_width(240.0));
+
+                        if value != MULTIPLE_VALUES && !field.is_valid(value) {
+                            response.on_hover_text("Should be a whole number");
+                            ui.colored_label(ui.visuals().error_fg_color, "⚠");
+                        }
+                    });
+
+                    ui.end_row();
+                }
+            });
+
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            if ui.button("Number Tracks").clicked() {
+                draft.number_tracks();
+            }
+
+            ui.menu_button("Fix Case", |ui| {
+                for case_style in CaseStyle::ALL {
+                    if ui.button(case_style.to_string()).clicked() {
+                        draft.fix_case(case_style);
+                    }
+                }
+            });
+        });
+
+        let unwritable_count = draft.unwritable_count();
+        if unwritable_count > 0 {
+            ui.colored_label(
+                ui.visuals().warn_fg_color,
+                format!(
+                    "The tags of {} of the files can't be written. Only MP3 files are supported.",
+                    unwritable_count
+                ),
+            );
+        }
+
+        ui.separator();
+
+        let mut is_saved = false;
+        let mut is_cancelled = false;
+
+        ui.horizontal(|ui| {
+            is_saved = ui
+                .add_enabled(
+                    draft.is_valid() && unwritable_count < draft.tracks.len(),
+                    egui::Button::new("Save"),
+                )
+                .clicked();
+            is_cancelled = ui.button("Cancel").clicked();
+        });
+
+        if is_cancelled {
+            ctx.tag_edit_draft = None;
+        }
+
+        if is_saved {
+            if let Some(draft) = ctx.tag_edit_draft.take() {
+                ctx.write_tags(draft.edited_tracks());
+            }
+        }
+    }
+}
@@ -0,0 +1,17 @@
+use crate::app::title_format::TitleFormat;
+use eframe::egui;
+
+/// A text field for a title formatting script, with the error when the script doesn't parse.
+pub fn title_format_edit(ui: &mut egui::Ui, script: &mut String) -> egui::Response {
+    let response = ui.add(
+        egui::TextEdit::singleline(script)
+            .code_editor()
+            .desired_width(320.0),
+    );
+
+    if let Err(err) = TitleFormat::parse(script) {
+        ui.colored_label(ui.visuals().error_fg_color, format!("⚠ {}", err));
+    }
+
+    response
+}
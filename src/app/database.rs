@@ -0,0 +1,605 @@
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::path::{Path, PathBuf};
+
+use super::library::{
+    AudioProperties, FileStamp, LibraryItem, LibraryPath, LibraryPathId, LibraryPathStatus,
+};
+use super::replay_gain::ReplayGain;
+
+// Bumped whenever the schema changes, with a migration added to `migrate`.
+const SCHEMA_VERSION: i32 = 6;
+
+const SCHEMA: &str = "
+    CREATE TABLE library_paths (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL UNIQUE,
+        imported INTEGER NOT NULL
+    );
+
+    CREATE TABLE tracks (
+        key INTEGER PRIMARY KEY,
+        library_id INTEGER NOT NULL REFERENCES library_paths(id) ON DELETE CASCADE,
+        path TEXT NOT NULL,
+        track_gain REAL,
+        track_peak REAL,
+        album_gain REAL,
+        album_peak REAL
+    );
+
+    CREATE INDEX tracks_library_id ON tracks(library_id);
+
+    CREATE TABLE tags (
+        track_key INTEGER NOT NULL REFERENCES tracks(key) ON DELETE CASCADE,
+        name TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (track_key, name)
+    );
+";
+
+// Adds the file modification time and size, which tell whether a track changed when rescanning.
+const ADD_FILE_STAMPS: &str = "
+    ALTER TABLE tracks ADD COLUMN modified_millis INTEGER;
+    ALTER TABLE tracks ADD COLUMN size INTEGER;
+";
+
+// Adds the audio fingerprint, which finds a track again after its file was moved.
+const ADD_FINGERPRINTS: &str = "
+    ALTER TABLE tracks ADD COLUMN fingerprint INTEGER;
+";
+
+// Adds when tracks were added and played, for smart playlists. Tracks that are already in the
+// library count as added when their file was last modified.
+const ADD_PLAY_STATISTICS: &str = "
+    ALTER TABLE tracks ADD COLUMN date_added INTEGER;
+    ALTER TABLE tracks ADD COLUMN play_count INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE tracks ADD COLUMN last_played INTEGER;
+    UPDATE tracks SET date_added = modified_millis / 1000;
+";
+
+// Adds the technical properties of the audio. The file stamps of the tracks already in the
+// library are cleared, so the next rescan reads their files again to fill them in.
+const ADD_AUDIO_PROPERTIES: &str = "
+    ALTER TABLE tracks ADD COLUMN duration_millis INTEGER;
+    ALTER TABLE tracks ADD COLUMN sample_rate INTEGER;
+    ALTER TABLE tracks ADD COLUMN channels INTEGER;
+    ALTER TABLE tracks ADD COLUMN bits_per_sample INTEGER;
+    ALTER TABLE tracks ADD COLUMN bitrate INTEGER;
+    ALTER TABLE tracks ADD COLUMN codec TEXT;
+    UPDATE tracks SET modified_millis = NULL, size = NULL;
+";
+
+// Clears the file stamps of tracks without a fingerprint, so the next rescan reads their files to
+// fill it in. Runs after `rekey_tracks`.
+const BACKFILL_FINGERPRINTS: &str = "
+    UPDATE tracks SET modified_millis = NULL, size = NULL WHERE fingerprint IS NULL;
+";
+
+/// The library paths and tracks, stored in SQLite so changes can be written as they happen
+/// instead of with the rest of the app state on exit.
+#[derive(Debug)]
+pub struct LibraryDatabase {
+    conn: Connection,
+}
+
+impl LibraryDatabase {
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    // The database lives next to the app state file.
+    pub fn default_path() -> Option<PathBuf> {
+        confy::get_configuration_file_path("music_player", None)
+            .ok()
+            .map(|config_path| config_path.with_file_name("library.sqlite3"))
+    }
+
+    fn from_connection(conn: Connection) -> rusqlite::Result<Self> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+
+        let mut database = Self { conn };
+        database.migrate()?;
+        Ok(database)
+    }
+
+    // All steps run in one transaction, so a failed migration leaves the previous version intact
+    // instead of a half-migrated schema.
+    fn migrate(&mut self) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        let version: i32 = tx.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+        if version < 1 {
+            tx.execute_batch(SCHEMA)?;
+        }
+
+        if version < 2 {
+            tx.execute_batch(ADD_FILE_STAMPS)?;
+        }
+
+        if version < 3 {
+            tx.execute_batch(ADD_FINGERPRINTS)?;
+        }
+
+        if version < 4 {
+            tx.execute_batch(ADD_PLAY_STATISTICS)?;
+        }
+
+        if version < 5 {
+            tx.execute_batch(ADD_AUDIO_PROPERTIES)?;
+        }
+
+        if version < 6 {
+            rekey_tracks(&tx)?;
+            tx.execute_batch(BACKFILL_FINGERPRINTS)?;
+        }
+
+        if version < SCHEMA_VERSION {
+            tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        }
+
+        tx.commit()
+    }
+
+    pub fn is_empty(&self) -> rusqlite::Result<bool> {
+        let path: Option<i64> = self
+            .conn
+            .query_row("SELECT id FROM library_paths LIMIT 1", [], |row| row.get(0))
+            .optional()?;
+
+        Ok(path.is_none())
+    }
+
+    pub fn insert_path(&self, library_path: &LibraryPath) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO library_paths (id, path, imported) VALUES (?1, ?2, ?3)",
+            params![
+                library_path.id().get() as i64,
+                library_path.path().to_string_lossy(),
+                library_path.status() == LibraryPathStatus::Imported,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn set_path_status(
+        &self,
+        id: LibraryPathId,
+        status: LibraryPathStatus,
+    ) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "UPDATE library_paths SET imported = ?2 WHERE id = ?1",
+            params![id.get() as i64, status == LibraryPathStatus::Imported],
+        )?;
+
+        Ok(())
+    }
+
+    // Also removes the tracks of the path.
+    pub fn remove_path(&self, id: LibraryPathId) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "DELETE FROM library_paths WHERE id = ?1",
+            params![id.get() as i64],
+        )?;
+
+        Ok(())
+    }
+
+    // Inserts the items, or replaces them and their tags if they're already stored, in one
+    // transaction.
+    pub fn upsert_items(&mut self, items: &[LibraryItem]) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+
+        {
+            let mut upsert_track = tx.prepare_cached(
+                "INSERT INTO tracks
+                    (key, library_id, path, track_gain, track_peak, album_gain, album_peak,
+                     modified_millis, size, fingerprint, date_added, play_count, last_played,
+                     duration_millis, sample_rate, channels, bits_per_sample, bitrate, codec)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16,
+                     ?17, ?18, ?19)
+                 ON CONFLICT(key) DO UPDATE SET
+                    library_id = excluded.library_id,
+                    path = excluded.path,
+                    track_gain = excluded.track_gain,
+                    track_peak = excluded.track_peak,
+                    album_gain = excluded.album_gain,
+                    album_peak = excluded.album_peak,
+                    modified_millis = excluded.modified_millis,
+                    size = excluded.size,
+                    fingerprint = excluded.fingerprint,
+                    date_added = excluded.date_added,
+                    play_count = excluded.play_count,
+                    last_played = excluded.last_played,
+                    duration_millis = excluded.duration_millis,
+                    sample_rate = excluded.sample_rate,
+                    channels = excluded.channels,
+                    bits_per_sample = excluded.bits_per_sample,
+                    bitrate = excluded.bitrate,
+                    codec = excluded.codec",
+            )?;
+            let mut delete_tags = tx.prepare_cached("DELETE FROM tags WHERE track_key = ?1")?;
+            let mut insert_tag =
+                tx.prepare_cached("INSERT INTO tags (track_key, name, value) VALUES (?1, ?2, ?3)")?;
+
+            for item in items {
+                let key = item.key() as i64;
+                let replay_gain = item.replay_gain();
+                let file_stamp = item.file_stamp();
+                let properties = item.properties();
+
+                upsert_track.execute(params![
+                    key,
+                    item.library_id().get() as i64,
+                    item.path().to_string_lossy(),
+                    replay_gain.track_gain,
+                    replay_gain.track_peak,
+                    replay_gain.album_gain,
+                    replay_gain.album_peak,
+                    file_stamp.map(|stamp| stamp.modified_millis as i64),
+                    file_stamp.map(|stamp| stamp.size as i64),
+                    item.fingerprint().map(|fingerprint| fingerprint as i64),
+                    item.date_added().map(|date| date as i64),
+                    item.play_count(),
+                    item.last_played().map(|date| date as i64),
+                    properties.duration_millis.map(|millis| millis as i64),
+                    properties.sample_rate,
+                    properties.channels,
+                    properties.bits_per_sample,
+                    properties.bitrate,
+                    properties.codec,
+                ])?;
+
+                delete_tags.execute(params![key])?;
+
+                for (name, value) in tags(item) {
+                    insert_tag.execute(params![key, name, value])?;
+                }
+            }
+        }
+
+        tx.commit()
+    }
+
+    pub fn remove_items(&mut self, keys: &[usize]) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+
+        {
+            let mut delete_track = tx.prepare_cached("DELETE FROM tracks WHERE key = ?1")?;
+
+            for key in keys {
+                delete_track.execute(params![*key as i64])?;
+            }
+        }
+
+        tx.commit()
+    }
+
+    pub fn load(&self) -> rusqlite::Result<(Vec<LibraryPath>, Vec<LibraryItem>)> {
+        let paths = self
+            .conn
+            .prepare("SELECT id, path, imported FROM library_paths ORDER BY path")?
+            .query_map([], |row| {
+                let status = match row.get::<_, bool>(2)? {
+                    true => LibraryPathStatus::Imported,
+                    false => LibraryPathStatus::NotImported,
+                };
+
+                Ok(LibraryPath::from_parts(
+                    LibraryPathId::new(row.get::<_, i64>(0)? as usize),
+                    PathBuf::from(row.get::<_, String>(1)?),
+                    status,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<LibraryPath>>>()?;
+
+        let mut items = self
+            .conn
+            .prepare(
+                "SELECT key, library_id, path, track_gain, track_peak, album_gain, album_peak,
+                    modified_millis, size, fingerprint, date_added, play_count, last_played,
+                    duration_millis, sample_rate, channels, bits_per_sample, bitrate, codec
+                 FROM tracks",
+            )?
+            .query_map([], |row| {
+                let replay_gain = ReplayGain {
+                    track_gain: row.get(3)?,
+                    track_peak: row.get(4)?,
+                    album_gain: row.get(5)?,
+                    album_peak: row.get(6)?,
+                };
+
+                let modified_millis = row.get::<_, Option<i64>>(7)?;
+                let size = row.get::<_, Option<i64>>(8)?;
+                let file_stamp =
+                    modified_millis
+                        .zip(size)
+                        .map(|(modified_millis, size)| FileStamp {
+                            modified_millis: modified_millis as u64,
+                            size: size as u64,
+                        });
+
+                Ok(LibraryItem::new(
+                    PathBuf::from(row.get::<_, String>(2)?),
+                    LibraryPathId::new(row.get::<_, i64>(1)? as usize),
+                )
+                .set_key(row.get::<_, i64>(0)? as usize)
+                .set_replay_gain(replay_gain)
+                .set_file_stamp(file_stamp)
+                .set_fingerprint(row.get::<_, Option<i64>>(9)?.map(|f| f as u64))
+                .set_date_added(row.get::<_, Option<i64>>(10)?.map(|date| date as u64))
+                .set_play_count(row.get(11)?)
+                .set_last_played(row.get::<_, Option<i64>>(12)?.map(|date| date as u64))
+                .set_properties(AudioProperties {
+                    duration_millis: row.get::<_, Option<i64>>(13)?.map(|millis| millis as u64),
+                    sample_rate: row.get(14)?,
+                    channels: row.get(15)?,
+                    bits_per_sample: row.get(16)?,
+                    bitrate: row.get(17)?,
+                    codec: row.get(18)?,
+                }))
+            })?
+            .collect::<rusqlite::Result<Vec<LibraryItem>>>()?;
+
+        let positions = items
+            .iter()
+            .enumerate()
+            .map(|(idx, item)| (item.key(), idx))
+            .collect::<std::collections::HashMap<usize, usize>>();
+
+        let mut statement = self
+            .conn
+            .prepare("SELECT track_key, name, value FROM tags")?;
+        let mut rows = statement.query([])?;
+
+        while let Some(row) = rows.next()? {
+            let key = row.get::<_, i64>(0)? as usize;
+            let name = row.get::<_, String>(1)?;
+            let value = row.get::<_, String>(2)?;
+
+            if let Some(item) = positions.get(&key).map(|idx| &mut items[*idx]) {
+                set_tag(item, &name, &value);
+            }
+        }
+
+        Ok((paths, items))
+    }
+}
+
+// Tracks added before keys were derived from paths have random ones. A track whose new key is
+// already taken by the same file, ex. imported twice through overlapping library paths, is a
+// duplicate and dropped.
+fn rekey_tracks(tx: &Transaction) -> rusqlite::Result<()> {
+    // The tags point at the old keys until they're moved over too.
+    tx.pragma_update(None, "defer_foreign_keys", true)?;
+
+    let tracks = tx
+        .prepare("SELECT key, path FROM tracks")?
+        .query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<(i64, String)>>>()?;
+
+    for (key, path) in tracks {
+        let new_key = LibraryItem::key_for_path(Path::new(&path)) as i64;
+        if new_key == key {
+            continue;
+        }
+
+        let taken_by: Option<String> = tx
+            .query_row(
+                "SELECT path FROM tracks WHERE key = ?1",
+                params![new_key],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match taken_by {
+            Some(taken_by) if taken_by == path => {
+                tx.execute("DELETE FROM tracks WHERE key = ?1", params![key])?;
+            }
+            Some(_) => (),
+            None => {
+                tx.execute(
+                    "UPDATE tracks SET key = ?1 WHERE key = ?2",
+                    params![new_key, key],
+                )?;
+                tx.execute(
+                    "UPDATE tags SET track_key = ?1 WHERE track_key = ?2",
+                    params![new_key, key],
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn tags(item: &LibraryItem) -> Vec<(&'static str, String)> {
+    [
+        ("title", item.title()),
+        ("artist", item.artist()),
+        ("album", item.album()),
+        ("year", item.year().map(|year| year.to_string())),
+        ("genre", item.genre()),
+        (
+            "track_number",
+            item.track_number().map(|number| number.to_string()),
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| value.map(|value| (name, value)))
+    .collect()
+}
+
+fn set_tag(item: &mut LibraryItem, name: &str, value: &str) {
+    match name {
+        "title" => item.set_title(Some(value)),
+        "artist" => item.set_artist(Some(value)),
+        "album" => item.set_album(Some(value)),
+        "year" => item.set_year(value.parse().ok()),
+        "genre" => item.set_genre(Some(value)),
+        "track_number" => item.set_track_number(value.parse().ok()),
+        _ => return,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::library::Library;
+
+    fn database() -> LibraryDatabase {
+        LibraryDatabase::from_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn stores_paths_tracks_and_tags() {
+        let mut database = database();
+        let library_path = LibraryPath::new(PathBuf::from("/music"));
+        let item = LibraryItem::new(PathBuf::from("/music/roygbiv.flac"), library_path.id())
+            .set_title(Some("Roygbiv"))
+            .set_artist(Some("Boards of Canada"))
+            .set_year(Some(1998))
+            .set_track_number(Some(7))
+            .set_replay_gain(ReplayGain {
+                track_gain: Some(-6.5),
+                track_peak: Some(0.9),
+                album_gain: None,
+                album_peak: None,
+            })
+            .set_properties(AudioProperties {
+                duration_millis: Some(355_120),
+                sample_rate: Some(44100),
+                channels: Some(2),
+                bits_per_sample: Some(16),
+                bitrate: Some(1011),
+                codec: Some("FLAC".to_string()),
+            });
+
+        assert!(database.is_empty().unwrap());
+
+        database.insert_path(&library_path).unwrap();
+        database
+            .set_path_status(library_path.id(), LibraryPathStatus::Imported)
+            .unwrap();
+        database.upsert_items(std::slice::from_ref(&item)).unwrap();
+
+        let updated = item
+            .clone()
+            .set_album(Some("Music Has the Right to Children"))
+            .played(1_700_000_000);
+        database
+            .upsert_items(std::slice::from_ref(&updated))
+            .unwrap();
+
+        let (paths, items) = database.load().unwrap();
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].id(), library_path.id());
+        assert_eq!(paths[0].status(), LibraryPathStatus::Imported);
+        assert_eq!(items, std::slice::from_ref(&updated));
+        assert_eq!(items[0].album(), updated.album());
+        assert_eq!(items[0].replay_gain(), updated.replay_gain());
+        assert_eq!(items[0].track_number(), Some(7));
+        assert_eq!(items[0].play_count(), 1);
+        assert_eq!(items[0].last_played(), Some(1_700_000_000));
+        assert_eq!(items[0].properties(), item.properties());
+
+        database.remove_path(library_path.id()).unwrap();
+        let (paths, items) = database.load().unwrap();
+        assert!(paths.is_empty() && items.is_empty());
+    }
+
+    #[test]
+    fn reads_tracks_again_after_adding_audio_properties() {
+        let conn = Connection::open_in_memory().unwrap();
+        for migration in [
+            SCHEMA,
+            ADD_FILE_STAMPS,
+            ADD_FINGERPRINTS,
+            ADD_PLAY_STATISTICS,
+        ] {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.pragma_update(None, "user_version", 4).unwrap();
+        conn.execute_batch(
+            "INSERT INTO library_paths (id, path, imported) VALUES (1, '/music', 1);
+             INSERT INTO tracks (key, library_id, path, modified_millis, size, play_count)
+                VALUES (2, 1, '/music/trust.flac', 1000, 2000, 3);",
+        )
+        .unwrap();
+
+        let database = LibraryDatabase::from_connection(conn).unwrap();
+        let (_, items) = database.load().unwrap();
+
+        // Without a file stamp, the next rescan reads the file as if it changed.
+        assert_eq!(items[0].file_stamp(), None);
+        assert_eq!(items[0].play_count(), 3);
+        assert_eq!(items[0].properties(), &AudioProperties::default());
+    }
+
+    #[test]
+    fn rewrites_random_keys_and_backfills_fingerprints() {
+        let conn = Connection::open_in_memory().unwrap();
+        for migration in [
+            SCHEMA,
+            ADD_FILE_STAMPS,
+            ADD_FINGERPRINTS,
+            ADD_PLAY_STATISTICS,
+            ADD_AUDIO_PROPERTIES,
+        ] {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.pragma_update(None, "user_version", 5).unwrap();
+        conn.execute_batch(
+            "INSERT INTO library_paths (id, path, imported) VALUES (1, '/music', 1);
+             INSERT INTO library_paths (id, path, imported) VALUES (2, '/music/trust', 1);
+             INSERT INTO tracks (key, library_id, path, modified_millis, size, fingerprint)
+                VALUES (11, 1, '/music/trust/01.flac', 1000, 2000, NULL),
+                       (12, 2, '/music/trust/01.flac', 1000, 2000, NULL),
+                       (13, 1, '/music/trust/02.flac', 1000, 2000, 42);
+             INSERT INTO tags (track_key, name, value) VALUES (11, 'title', 'Amazing Grace');",
+        )
+        .unwrap();
+
+        let database = LibraryDatabase::from_connection(conn).unwrap();
+        let (_, mut items) = database.load().unwrap();
+        items.sort_by_key(|item| item.path());
+
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].key(), LibraryItem::key_for_path(&items[0].path()));
+        assert_eq!(items[0].title().as_deref(), Some("Amazing Grace"));
+        assert_eq!(items[0].file_stamp(), None);
+        assert_eq!(items[1].key(), LibraryItem::key_for_path(&items[1].path()));
+        assert!(items[1].file_stamp().is_some());
+    }
+
+    #[test]
+    fn migrates_a_library_from_the_app_state() {
+        let library_path = LibraryPath::new(PathBuf::from("/music"));
+        let item = LibraryItem::new(PathBuf::from("/music/trust.flac"), library_path.id())
+            .set_album(Some("Trust"));
+
+        // A library saved by confy before the database existed.
+        let app_state = serde_json::json!({
+            "paths": [library_path],
+            "items": [item],
+            "library_view": { "view_type": "Album", "containers": [] },
+        });
+        let mut library = serde_json::from_value::<Library>(app_state).unwrap();
+
+        let database = database();
+        library.open_database(database).unwrap();
+
+        assert_eq!(library.paths(), &[library_path]);
+        assert_eq!(library.items(), std::slice::from_ref(&item));
+        assert_eq!(library.view().containers[0].items, [item]);
+
+        // Only the view type is left to save in the app state.
+        assert_eq!(
+            serde_json::to_value(&library).unwrap(),
+            serde_json::json!({ "library_view": { "view_type": "Album" } })
+        );
+    }
+}
@@ -0,0 +1,489 @@
+use eframe::egui::{Key, KeyboardShortcut, Modifiers};
+
+use super::library::{LibraryItem, LibraryPath};
+use super::playlist::Playlist;
+use super::playlist_columns::{self, SortKey};
+use super::App;
+
+pub const UNDO_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Z);
+pub const REDO_SHORTCUT: KeyboardShortcut =
+    KeyboardShortcut::new(Modifiers::COMMAND.plus(Modifiers::SHIFT), Key::Z);
+
+// Older edits are forgotten once there are this many.
+const MAX_EDITS: usize = 100;
+
+/// A change to the playlists or the library that can be undone. Each one holds what it needs to
+/// be made again and to be reverted.
+#[derive(Debug, Clone)]
+pub enum Edit {
+    AddTracks {
+        playlist_idx: usize,
+        at: usize,
+        tracks: Vec<LibraryItem>,
+    },
+    // The removed tracks with their positions, top to bottom.
+    RemoveTracks {
+        playlist_idx: usize,
+        removed: Vec<(usize, LibraryItem)>,
+    },
+    MoveTracks {
+        playlist_idx: usize,
+        indices: Vec<usize>,
+        destination: usize,
+    },
+    // The new order of the tracks, as their positions before the sort.
+    SortTracks {
+        playlist_idx: usize,
+        sort_keys: Vec<SortKey>,
+        order: Vec<usize>,
+    },
+    CreatePlaylist {
+        playlist_idx: usize,
+        playlist: Playlist,
+    },
+    RenamePlaylist {
+        playlist_idx: usize,
+        from: String,
+        to: String,
+    },
+    RemovePlaylist {
+        playlist_idx: usize,
+        playlist: Playlist,
+    },
+    RemoveLibraryPath {
+        library_path: LibraryPath,
+        items: Vec<LibraryItem>,
+    },
+}
+
+impl Edit {
+    pub fn remove_tracks(playlist: &Playlist, playlist_idx: usize, indices: &[usize]) -> Self {
+        let mut indices = indices.to_vec();
+        indices.sort_unstable();
+        indices.dedup();
+
+        Self::RemoveTracks {
+            playlist_idx,
+            removed: indices
+                .into_iter()
+                .filter_map(|idx| playlist.tracks.get(idx).map(|track| (idx, track.clone())))
+                .collect(),
+        }
+    }
+
+    pub fn sort_tracks(playlist: &Playlist, playlist_idx: usize, sort_keys: Vec<SortKey>) -> Self {
+        Self::SortTracks {
+            playlist_idx,
+            order: playlist_columns::sorted_order(&playlist.tracks, &sort_keys),
+            sort_keys,
+        }
+    }
+
+    pub fn move_tracks(playlist_idx: usize, indices: &[usize], destination: usize) -> Self {
+        let mut indices = indices.to_vec();
+        indices.sort_unstable();
+        indices.dedup();
+
+        Self::MoveTracks {
+            playlist_idx,
+            indices,
+            destination,
+        }
+    }
+
+    // Whether the edit changes anything. Smart playlists ignore changes to their tracks.
+    fn is_effective(&self, app: &App) -> bool {
+        let is_manual = |idx: &usize| app.playlists.get(*idx).is_some_and(|p| !p.is_smart());
+
+        match self {
+            Edit::AddTracks {
+                playlist_idx,
+                tracks,
+                ..
+            } => is_manual(playlist_idx) && !tracks.is_empty(),
+            Edit::RemoveTracks {
+                playlist_idx,
+                removed,
+            } => is_manual(playlist_idx) && !removed.is_empty(),
+            Edit::MoveTracks {
+                playlist_idx,
+                indices,
+                ..
+            } => is_manual(playlist_idx) && !indices.is_empty(),
+            Edit::SortTracks { playlist_idx, .. } => is_manual(playlist_idx),
+            Edit::RenamePlaylist { from, to, .. } => from != to,
+            Edit::CreatePlaylist { .. } | Edit::RemoveLibraryPath { .. } => true,
+            Edit::RemovePlaylist { playlist_idx, .. } => *playlist_idx < app.playlists.len(),
+        }
+    }
+
+    // What the edit did, for the Edit menu.
+    pub fn description(&self) -> String {
+        let tracks = |count: usize| match count {
+            1 => "1 track".to_string(),
+            count => format!("{} tracks", count),
+        };
+
+        match self {
+            Edit::AddTracks { tracks: added, .. } => format!("Add {}", tracks(added.len())),
+            Edit::RemoveTracks { removed, .. } => format!("Remove {}", tracks(removed.len())),
+            Edit::MoveTracks { indices, .. } => format!("Move {}", tracks(indices.len())),
+            Edit::SortTracks { .. } => "Sort Playlist".to_string(),
+            Edit::CreatePlaylist { .. } => "Create Playlist".to_string(),
+            Edit::RenamePlaylist { .. } => "Rename Playlist".to_string(),
+            Edit::RemovePlaylist { .. } => "Remove Playlist".to_string(),
+            Edit::RemoveLibraryPath { .. } => "Remove Library Path".to_string(),
+        }
+    }
+
+    fn apply(&self, app: &mut App) {
+        match self {
+            Edit::AddTracks {
+                playlist_idx,
+                at,
+                tracks,
+            } => app.playlists[*playlist_idx].insert_tracks(*at, tracks.clone()),
+            Edit::RemoveTracks {
+                playlist_idx,
+                removed,
+            } => {
+                let indices = removed.iter().map(|(idx, _)| *idx).collect::<Vec<usize>>();
+                app.playlists[*playlist_idx].remove_tracks(&indices);
+            }
+            Edit::MoveTracks {
+                playlist_idx,
+                indices,
+                destination,
+            } => app.playlists[*playlist_idx].move_tracks(indices, *destination),
+            Edit::SortTracks {
+                playlist_idx,
+                sort_keys,
+                order,
+            } => app.playlists[*playlist_idx].arrange_tracks(order, sort_keys.clone()),
+            Edit::CreatePlaylist {
+                playlist_idx,
+                playlist,
+            } => app.insert_playlist(*playlist_idx, playlist.clone()),
+            Edit::RenamePlaylist {
+                playlist_idx, to, ..
+            } => app.playlists[*playlist_idx].set_name(to.clone()),
+            Edit::RemovePlaylist { playlist_idx, .. } => {
+                app.take_playlist(*playlist_idx);
+            }
+            Edit::RemoveLibraryPath { library_path, .. } => {
+                app.library.remove_path(library_path.id());
+                app.sync_library_watcher();
+            }
+        }
+    }
+
+    fn revert(&self, app: &mut App) {
+        match self {
+            Edit::AddTracks {
+                playlist_idx,
+                at,
+                tracks,
+            } => {
+                let indices = (*at..at + tracks.len()).collect::<Vec<usize>>();
+                app.playlists[*playlist_idx].remove_tracks(&indices);
+            }
+            Edit::RemoveTracks {
+                playlist_idx,
+                removed,
+            } => app.playlists[*playlist_idx].restore_tracks(removed.clone()),
+            Edit::MoveTracks {
+                playlist_idx,
+                indices,
+                destination,
+            } => app.playlists[*playlist_idx].unmove_tracks(indices, *destination),
+            Edit::SortTracks {
+                playlist_idx,
+                order,
+                ..
+            } => {
+                // The track now at each position goes back to the position it was sorted from.
+                let mut unsorted = vec![0; order.len()];
+                for (idx, sorted_from) in order.iter().enumerate() {
+                    unsorted[*sorted_from] = idx;
+                }
+
+                app.playlists[*playlist_idx].arrange_tracks(&unsorted, Vec::new());
+            }
+            Edit::CreatePlaylist { playlist_idx, .. } => {
+                app.take_playlist(*playlist_idx);
+            }
+            Edit::RenamePlaylist {
+                playlist_idx, from, ..
+            } => app.playlists[*playlist_idx].set_name(from.clone()),
+            Edit::RemovePlaylist {
+                playlist_idx,
+                playlist,
+            } => app.insert_playlist(*playlist_idx, playlist.clone()),
+            Edit::RemoveLibraryPath {
+                library_path,
+                items,
+            } => {
+                app.library
+                    .restore_path(library_path.clone(), items.clone());
+                app.sync_library_watcher();
+            }
+        }
+    }
+}
+
+/// The edits that can be undone, and the undone ones that can be made again.
+#[derive(Debug, Default)]
+pub struct History {
+    undo_stack: Vec<Edit>,
+    redo_stack: Vec<Edit>,
+}
+
+impl History {
+    pub fn next_undo(&self) -> Option<&Edit> {
+        self.undo_stack.last()
+    }
+
+    pub fn next_redo(&self) -> Option<&Edit> {
+        self.redo_stack.last()
+    }
+}
+
+impl App {
+    /// Makes the edit and remembers it so it can be undone. A new edit can't be redone past, so
+    /// the undone edits are forgotten.
+    pub fn execute(&mut self, edit: Edit) {
+        if !edit.is_effective(self) {
+            return;
+        }
+
+        edit.apply(self);
+
+        let history = &mut self.history;
+        history.redo_stack.clear();
+        history.undo_stack.push(edit);
+
+        if history.undo_stack.len() > MAX_EDITS {
+            history.undo_stack.remove(0);
+        }
+    }
+
+    pub fn undo(&mut self) {
+        if let Some(edit) = self.history.undo_stack.pop() {
+            tracing::info!("Undoing {}", edit.description());
+            edit.revert(self);
+            self.history.redo_stack.push(edit);
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(edit) = self.history.redo_stack.pop() {
+            tracing::info!("Redoing {}", edit.description());
+            edit.apply(self);
+            self.history.undo_stack.push(edit);
+        }
+    }
+
+    // Adds the tracks to the end of the playlist being viewed.
+    pub fn add_to_current_playlist(&mut self, tracks: Vec<LibraryItem>) {
+        let Some(playlist_idx) = self.current_playlist_idx else {
+            return;
+        };
+
+        if let Some(playlist) = self.playlists.get(playlist_idx) {
+            let at = playlist.tracks.len();
+            self.execute(Edit::AddTracks {
+                playlist_idx,
+                at,
+                tracks,
+            });
+        }
+    }
+
+    // Adds the playlist at the end and views it.
+    pub fn create_playlist(&mut self, playlist: Playlist) {
+        self.execute(Edit::CreatePlaylist {
+            playlist_idx: self.playlists.len(),
+            playlist,
+        });
+    }
+
+    pub fn remove_playlist(&mut self, playlist_idx: usize) {
+        if let Some(playlist) = self.playlists.get(playlist_idx) {
+            self.execute(Edit::RemovePlaylist {
+                playlist_idx,
+                playlist: playlist.clone(),
+            });
+        }
+    }
+
+    pub fn remove_library_path(&mut self, path_id: super::LibraryPathId) {
+        let Some(library_path) = self.library.paths().iter().find(|p| p.id() == path_id) else {
+            return;
+        };
+
+        let items = self
+            .library
+            .items()
+            .iter()
+            .filter(|item| item.library_id() == path_id)
+            .cloned()
+            .collect();
+
+        self.execute(Edit::RemoveLibraryPath {
+            library_path: library_path.clone(),
+            items,
+        });
+    }
+
+    // Inserts the playlist and views it. The other playlists' indices shift to make room.
+    fn insert_playlist(&mut self, playlist_idx: usize, playlist: Playlist) {
+        let playlist_idx = playlist_idx.min(self.playlists.len());
+        let shift = |idx: usize| if idx >= playlist_idx { idx + 1 } else { idx };
+
+        self.playing_playlist_idx = self.playing_playlist_idx.map(shift);
+        if let Some(draft) = self.smart_playlist_draft.as_mut() {
+            draft.playlist_idx = draft.playlist_idx.map(shift);
+        }
+
+        self.playlists.insert(playlist_idx, playlist);
+        self.current_playlist_idx = Some(playlist_idx);
+    }
+
+    fn take_playlist(&mut self, playlist_idx: usize) -> Playlist {
+        // Because the current playlist is referenced via index, we need to take
+        // into account that the index may be out of bounds when removing a
+        // playlist. This should be resolved when I figure out how to reference the
+        // actual selected playlist.
+        if let Some(mut current_playlist_idx) = self.current_playlist_idx {
+            if current_playlist_idx == 0 && playlist_idx == 0 {
+                self.current_playlist_idx = None;
+            } else if current_playlist_idx >= playlist_idx {
+                current_playlist_idx -= 1;
+                self.current_playlist_idx = Some(current_playlist_idx);
+            }
+        }
+
+        // The playing playlist stops supplying tracks once it's gone, but whatever is
+        // playing and queued carries on.
+        self.playing_playlist_idx = match self.playing_playlist_idx {
+            Some(playing_idx) if playing_idx == playlist_idx => None,
+            Some(playing_idx) if playing_idx > playlist_idx => Some(playing_idx - 1),
+            playing_idx => playing_idx,
+        };
+
+        // The rule builder would save into whichever playlist took its place.
+        if let Some(draft) = self.smart_playlist_draft.as_mut() {
+            match draft.playlist_idx {
+                Some(draft_idx) if draft_idx == playlist_idx => self.smart_playlist_draft = None,
+                Some(draft_idx) if draft_idx > playlist_idx => {
+                    draft.playlist_idx = Some(draft_idx - 1)
+                }
+                _ => (),
+            }
+        }
+
+        self.playlists.remove(playlist_idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::library::LibraryPathId;
+    use std::path::PathBuf;
+
+    fn names(app: &App) -> Vec<String> {
+        app.playlists
+            .iter()
+            .map(|playlist| playlist.get_name().unwrap())
+            .collect()
+    }
+
+    fn order(playlist: &Playlist) -> String {
+        playlist
+            .tracks
+            .iter()
+            .map(|track| track.title().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn undoes_and_redoes_playlist_edits() {
+        let mut app = App::default();
+        let tracks = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| {
+                LibraryItem::new(PathBuf::from(name), LibraryPathId::new(0)).set_title(Some(name))
+            })
+            .collect::<Vec<LibraryItem>>();
+
+        let mut playlist = Playlist::new();
+        playlist.set_name("First".to_string());
+        app.create_playlist(playlist);
+        app.add_to_current_playlist(tracks);
+
+        app.execute(Edit::move_tracks(0, &[0, 2], 4));
+        assert_eq!(order(&app.playlists[0]), "bdac");
+
+        let remove = Edit::remove_tracks(&app.playlists[0], 0, &[1, 3]);
+        app.execute(remove);
+        assert_eq!(order(&app.playlists[0]), "ba");
+
+        app.execute(Edit::RenamePlaylist {
+            playlist_idx: 0,
+            from: "First".to_string(),
+            to: "Renamed".to_string(),
+        });
+        app.remove_playlist(0);
+        assert!(app.playlists.is_empty());
+        assert_eq!(app.current_playlist_idx, None);
+
+        app.undo();
+        assert_eq!(names(&app), ["Renamed"]);
+        assert_eq!(app.current_playlist_idx, Some(0));
+        app.undo();
+        assert_eq!(names(&app), ["First"]);
+        app.undo();
+        assert_eq!(order(&app.playlists[0]), "bdac");
+        assert_eq!(app.playlists[0].selection.indices(), [1, 3]);
+        app.undo();
+        assert_eq!(order(&app.playlists[0]), "abcd");
+        app.undo();
+        assert!(app.playlists[0].tracks.is_empty());
+
+        app.redo();
+        app.redo();
+        assert_eq!(order(&app.playlists[0]), "bdac");
+        assert_eq!(
+            app.history.next_redo().unwrap().description(),
+            "Remove 2 tracks"
+        );
+
+        // A new edit can't be redone past.
+        app.execute(Edit::move_tracks(0, &[3], 0));
+        assert!(app.history.next_redo().is_none());
+        assert_eq!(order(&app.playlists[0]), "cbda");
+    }
+
+    #[test]
+    fn restores_a_removed_library_path() {
+        let mut app = App::default();
+        app.library.add_path(PathBuf::from("/music"));
+        let path_id = app.library.paths()[0].id();
+        let item = LibraryItem::new(PathBuf::from("/music/a.flac"), path_id).set_title(Some("A"));
+        app.library
+            .add_items(vec![item.clone().played(1_700_000_000)]);
+
+        app.remove_library_path(path_id);
+        assert!(app.library.paths().is_empty() && app.library.items().is_empty());
+
+        app.undo();
+        assert_eq!(app.library.paths()[0].id(), path_id);
+        assert_eq!(app.library.items(), &[item]);
+        assert_eq!(app.library.items()[0].play_count(), 1);
+        assert_eq!(app.library.view().containers.len(), 1);
+
+        app.redo();
+        assert!(app.library.items().is_empty());
+    }
+}
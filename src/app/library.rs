@@ -1,278 +1,1126 @@
-use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Library {
-    paths: Vec<LibraryPath>,
-    items: Vec<LibraryItem>,
-    library_view: LibraryView,
-}
-
-impl Library {
-    pub fn new() -> Self {
-        Self {
-            paths: Vec::new(),
-            items: Vec::new(),
-            library_view: LibraryView {
-                view_type: ViewType::Album,
-                containers: Vec::new(),
-            },
-        }
-    }
-
-    pub fn paths(&self) -> &Vec<LibraryPath> {
-        &self.paths
-    }
-
-    pub fn add_path(&mut self, path: PathBuf) -> bool {
-        if self.paths.iter().any(|p| *p.path() == path) {
-            false
-        } else {
-            let new_path = LibraryPath::new(path);
-            self.paths.push(new_path);
-            true
-        }
-    }
-
-    pub fn remove_path(&mut self, path_id: LibraryPathId) {
-        // Remove the path from the library path list
-        if let Some(idx) = self.paths.iter().position(|l| l.id() == path_id) {
-            self.paths.remove(idx);
-        }
-
-        // Remove the actual items.
-        while let Some(idx) = self
-            .items
-            .iter()
-            .position(|item| item.library_id() == path_id)
-        {
-            self.items.swap_remove(idx);
-        }
-
-        // Remove the view container items
-        for container in &mut self.library_view.containers {
-            while let Some(ct_idx) = container
-                .items
-                .iter()
-                .position(|ci| ci.library_id() == path_id)
-            {
-                container.items.swap_remove(ct_idx);
-            }
-        }
-
-        // Remove the empty containers
-        while let Some(idx) = self
-            .library_view
-            .containers
-            .iter()
-            .position(|ct| ct.items.is_empty())
-        {
-            self.library_view.containers.swap_remove(idx);
-        }
-    }
-
-    pub fn set_path_to_imported(&mut self, id: LibraryPathId) {
-        for path in self.paths.iter_mut() {
-            if path.id() == id {
-                path.set_status(LibraryPathStatus::Imported);
-            }
-        }
-    }
-
-    pub fn items(&self) -> &Vec<LibraryItem> {
-        self.items.as_ref()
-    }
-
-    pub fn view(&self) -> &LibraryView {
-        &self.library_view
-    }
-
-    pub fn add_item(&mut self, library_item: LibraryItem) {
-        self.items.push(library_item);
-    }
-
-    pub fn add_items(&mut self, library_items: Vec<LibraryItem>) {
-        for item in library_items.into_iter() {
-            self.add_item(item);
-        }
-    }
-
-    pub fn add_view(&mut self, library_view: LibraryView) {
-        let mut new = library_view.containers.clone();
-
-        self.library_view.containers.append(&mut new);
-    }
-}
-
-#[derive(Debug, Clone, Eq, Hash, PartialEq, Serialize, Deserialize)]
-pub struct LibraryPath {
-    id: LibraryPathId,
-    path: PathBuf,
-    status: LibraryPathStatus,
-}
-
-impl LibraryPath {
-    pub fn new(path: PathBuf) -> Self {
-        use rand::Rng; // TODO - use ULID?
-        Self {
-            path,
-            status: LibraryPathStatus::NotImported,
-            id: LibraryPathId::new(rand::thread_rng().gen()),
-        }
-    }
-
-    pub fn id(&self) -> LibraryPathId {
-        self.id
-    }
-
-    pub fn path(&self) -> &PathBuf {
-        &self.path
-    }
-
-    pub fn status(&self) -> LibraryPathStatus {
-        self.status
-    }
-
-    pub fn set_status(&mut self, status: LibraryPathStatus) {
-        self.status = status;
-    }
-}
-
-#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Serialize, Deserialize)]
-pub struct LibraryPathId(usize);
-
-impl LibraryPathId {
-    pub fn new(id: usize) -> Self {
-        Self(id)
-    }
-}
-
-#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Serialize, Deserialize)]
-pub enum LibraryPathStatus {
-    NotImported,
-    Imported,
-}
-
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
-pub struct LibraryItem {
-    library_id: LibraryPathId,
-    path: PathBuf,
-    title: Option<String>,
-    artist: Option<String>,
-    album: Option<String>,
-    year: Option<i32>,
-    genre: Option<String>,
-    track_number: Option<u32>,
-    key: usize,
-}
-
-impl LibraryItem {
-    pub fn new(path: PathBuf, library_id: LibraryPathId) -> Self {
-        use rand::Rng; // TODO - use ULID?
-        Self {
-            library_id,
-            path,
-            title: None,
-            artist: None,
-            album: None,
-            year: None,
-            genre: None,
-            track_number: None,
-            key: rand::thread_rng().gen(),
-        }
-    }
-
-    pub fn library_id(&self) -> LibraryPathId {
-        self.library_id
-    }
-
-    pub fn path(&self) -> PathBuf {
-        self.path.clone()
-    }
-
-    pub fn key(&self) -> usize {
-        self.key
-    }
-
-    pub fn set_title(&mut self, title: Option<&str>) -> Self {
-        if let Some(title) = title {
-            self.title = Some(title.to_string());
-        }
-
-        self.to_owned()
-    }
-
-    pub fn title(&self) -> Option<String> {
-        self.title.clone()
-    }
-
-    pub fn set_artist(&mut self, artist: Option<&str>) -> Self {
-        if let Some(artist) = artist {
-            self.artist = Some(artist.to_string());
-        }
-        self.to_owned()
-    }
-
-    pub fn artist(&self) -> Option<String> {
-        self.artist.clone()
-    }
-
-    pub fn set_album(&mut self, album: Option<&str>) -> Self {
-        if let Some(album) = album {
-            self.album = Some(album.to_string());
-        }
-        self.to_owned()
-    }
-
-    pub fn album(&self) -> Option<String> {
-        self.album.clone()
-    }
-
-    pub fn set_year(&mut self, year: Option<i32>) -> Self {
-        self.year = year;
-        self.to_owned()
-    }
-
-    pub fn year(&self) -> Option<i32> {
-        self.year.clone()
-    }
-
-    pub fn set_genre(&mut self, genre: Option<&str>) -> Self {
-        if let Some(genre) = genre {
-            self.genre = Some(genre.to_string());
-        }
-        self.to_owned()
-    }
-
-    pub fn genre(&self) -> Option<String> {
-        self.genre.clone()
-    }
-
-    pub fn set_track_number(&mut self, track_number: Option<u32>) -> Self {
-        self.track_number = track_number;
-        self.to_owned()
-    }
-
-    pub fn track_number(&self) -> Option<u32> {
-        self.track_number.clone()
-    }
-}
-
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
-pub struct LibraryView {
-    pub view_type: ViewType,
-    pub containers: Vec<LibraryItemContainer>,
-}
-
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
-pub struct LibraryItemContainer {
-    pub name: String,
-    pub items: Vec<LibraryItem>,
-}
-
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
-pub enum ViewType {
-    Album,
-    Artist,
-    Genre,
-}
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::database::LibraryDatabase;
+use super::replay_gain::ReplayGain;
+use super::search::{Query, SearchIndex};
+use super::title_format::{self, DEFAULT_GROUPING};
+
+// The paths and items are kept in the library database. They're still read from the app state
+// so libraries saved before the database existed can be migrated into it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Library {
+    #[serde(skip_serializing, default)]
+    paths: Vec<LibraryPath>,
+    #[serde(skip_serializing, default)]
+    items: Vec<LibraryItem>,
+    library_view: LibraryView,
+    #[serde(skip_serializing, skip_deserializing)]
+    database: Option<LibraryDatabase>,
+    // Built on the first search and kept up to date as items are added.
+    #[serde(skip_serializing, skip_deserializing)]
+    search_index: Option<SearchIndex>,
+    // Bumped on every change to the items, so searches know when to run again.
+    #[serde(skip_serializing, skip_deserializing)]
+    revision: u64,
+}
+
+impl Library {
+    pub fn new() -> Self {
+        Self {
+            paths: Vec::new(),
+            items: Vec::new(),
+            library_view: LibraryView {
+                view_type: ViewType::Album,
+                containers: Vec::new(),
+            },
+            database: None,
+            search_index: None,
+            revision: 0,
+        }
+    }
+
+    // Loads the library from the database, first moving a library from the old app state into
+    // it if the database is new.
+    pub fn open_database(&mut self, mut database: LibraryDatabase) -> rusqlite::Result<()> {
+        if database.is_empty()? && !self.paths.is_empty() {
+            tracing::info!(
+                "Migrating {} library items into the database",
+                self.items.len()
+            );
+
+            for library_path in &self.paths {
+                database.insert_path(library_path)?;
+            }
+
+            // Items saved before keys were derived from paths have random ones.
+            let paths = &self.paths;
+            let items = self
+                .items
+                .iter()
+                .filter(|item| paths.iter().any(|p| p.id() == item.library_id()))
+                .map(|item| {
+                    item.clone()
+                        .set_key(LibraryItem::key_for_path(&item.path()))
+                })
+                .collect::<Vec<LibraryItem>>();
+
+            database.upsert_items(&items)?;
+        }
+
+        let (paths, items) = database.load()?;
+        self.paths = paths;
+        self.items = items;
+        self.database = Some(database);
+
+        self.regroup();
+        self.invalidate_search_index();
+
+        Ok(())
+    }
+
+    // Failing to save a change isn't fatal, the library still works from memory.
+    fn write_to_database(
+        &mut self,
+        write: impl FnOnce(&mut LibraryDatabase) -> rusqlite::Result<()>,
+    ) {
+        if let Some(database) = self.database.as_mut() {
+            if let Err(err) = write(database) {
+                tracing::error!("Failed to write to the library database: {}", err);
+            }
+        }
+    }
+
+    pub fn paths(&self) -> &Vec<LibraryPath> {
+        &self.paths
+    }
+
+    pub fn add_path(&mut self, path: PathBuf) -> bool {
+        if self.paths.iter().any(|p| *p.path() == path) {
+            false
+        } else {
+            let new_path = LibraryPath::new(path);
+            self.write_to_database(|database| database.insert_path(&new_path));
+            self.paths.push(new_path);
+            true
+        }
+    }
+
+    pub fn remove_path(&mut self, path_id: LibraryPathId) {
+        // Remove the path from the library path list
+        if let Some(idx) = self.paths.iter().position(|l| l.id() == path_id) {
+            self.paths.remove(idx);
+        }
+
+        self.write_to_database(|database| database.remove_path(path_id));
+
+        // Remove the actual items.
+        while let Some(idx) = self
+            .items
+            .iter()
+            .position(|item| item.library_id() == path_id)
+        {
+            self.items.swap_remove(idx);
+        }
+
+        self.regroup();
+        self.invalidate_search_index();
+    }
+
+    // Adds back a removed path with its items as they were, ex. to undo removing it.
+    pub fn restore_path(&mut self, library_path: LibraryPath, items: Vec<LibraryItem>) {
+        self.write_to_database(|database| database.insert_path(&library_path));
+        self.paths.push(library_path);
+        self.add_items(items);
+        self.regroup();
+    }
+
+    pub fn set_path_to_imported(&mut self, id: LibraryPathId) {
+        for path in self.paths.iter_mut() {
+            if path.id() == id {
+                path.set_status(LibraryPathStatus::Imported);
+            }
+        }
+
+        self.write_to_database(|database| {
+            database.set_path_status(id, LibraryPathStatus::Imported)
+        });
+    }
+
+    pub fn items(&self) -> &Vec<LibraryItem> {
+        self.items.as_ref()
+    }
+
+    pub fn view(&self) -> &LibraryView {
+        &self.library_view
+    }
+
+    pub fn add_item(&mut self, library_item: LibraryItem) {
+        self.add_items(vec![library_item]);
+    }
+
+    // A file that is already in the library, ex. imported again through overlapping library
+    // paths, replaces its item. A new file whose key is taken, ex. because the track that was
+    // first seen at its path has been moved, gets the next free key.
+    pub fn add_items(&mut self, library_items: Vec<LibraryItem>) {
+        let now = unix_now();
+        let mut by_path = self
+            .items
+            .iter()
+            .enumerate()
+            .map(|(idx, item)| (item.path.clone(), idx))
+            .collect::<HashMap<PathBuf, usize>>();
+        let mut keys = self
+            .items
+            .iter()
+            .map(|item| item.key())
+            .collect::<HashSet<usize>>();
+        let mut changed = Vec::with_capacity(library_items.len());
+        let mut replaced = HashMap::new();
+
+        for mut library_item in library_items {
+            if library_item.date_added.is_none() {
+                library_item.date_added = Some(now);
+            }
+
+            if let Some(&idx) = by_path.get(&library_item.path) {
+                let library_item = library_item.replacing(&self.items[idx]);
+                self.items[idx] = library_item.clone();
+                replaced.insert(library_item.key(), library_item.clone());
+                changed.push(library_item);
+                continue;
+            }
+
+            while !keys.insert(library_item.key) {
+                library_item.key = library_item.key.wrapping_add(1);
+            }
+
+            if let Some(search_index) = self.search_index.as_mut() {
+                search_index.add(&library_item);
+            }
+
+            by_path.insert(library_item.path.clone(), self.items.len());
+            self.items.push(library_item.clone());
+            changed.push(library_item);
+        }
+
+        self.write_to_database(|database| database.upsert_items(&changed));
+
+        if !replaced.is_empty() {
+            update_container_items(&mut self.library_view.containers, &replaced);
+            self.invalidate_search_index();
+        }
+
+        self.revision += 1;
+    }
+
+    // Replaces the items with the same keys, wherever they are in the library. The view isn't
+    // regrouped, so this is only for changes that don't move items to another container. Items
+    // that aren't in the library, ex. tracks dropped onto a playlist, aren't stored.
+    pub fn update_items(&mut self, library_items: &[LibraryItem]) {
+        let keys = self
+            .items
+            .iter()
+            .map(|item| item.key())
+            .collect::<HashSet<usize>>();
+        let known_items = library_items
+            .iter()
+            .filter(|item| keys.contains(&item.key()))
+            .cloned()
+            .collect::<Vec<LibraryItem>>();
+
+        self.write_to_database(|database| database.upsert_items(&known_items));
+
+        let updates = library_items
+            .iter()
+            .map(|item| (item.key(), item.clone()))
+            .collect::<HashMap<usize, LibraryItem>>();
+
+        for item in self.items.iter_mut() {
+            if let Some(update) = updates.get(&item.key()) {
+                *item = update.clone();
+            }
+        }
+
+        update_container_items(&mut self.library_view.containers, &updates);
+        self.invalidate_search_index();
+    }
+
+    // The library path the file or folder is in.
+    pub fn path_id_of(&self, path: &Path) -> Option<LibraryPathId> {
+        self.paths
+            .iter()
+            .find(|library_path| path.starts_with(library_path.path()))
+            .map(|library_path| library_path.id())
+    }
+
+    // The item of the file, or the items of every file in the folder.
+    pub fn items_under(&self, path: &Path) -> Vec<&LibraryItem> {
+        self.items
+            .iter()
+            .filter(|item| item.path().starts_with(path))
+            .collect()
+    }
+
+    pub fn remove_items(&mut self, keys: &[usize]) {
+        if keys.is_empty() {
+            return;
+        }
+
+        self.write_to_database(|database| database.remove_items(keys));
+        let keys = keys.iter().copied().collect::<HashSet<usize>>();
+        self.items.retain(|item| !keys.contains(&item.key()));
+        self.regroup();
+        self.invalidate_search_index();
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn search(&mut self, query: &Query) -> Vec<LibraryItem> {
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        let search_index = self
+            .search_index
+            .get_or_insert_with(|| SearchIndex::new(&self.items));
+
+        search_index
+            .search(query)
+            .into_iter()
+            .map(|idx| self.items[idx].clone())
+            .collect()
+    }
+
+    fn invalidate_search_index(&mut self) {
+        self.search_index = None;
+        self.revision += 1;
+    }
+
+    // Merges the containers of a newly imported path into the view. If the view was switched
+    // while importing, the whole library is grouped again instead.
+    pub fn add_view(&mut self, library_view: LibraryView) {
+        if library_view.view_type == self.library_view.view_type {
+            merge_containers(&mut self.library_view.containers, library_view.containers);
+        } else {
+            self.regroup();
+        }
+
+        self.revision += 1;
+    }
+
+    pub fn set_view_type(&mut self, view_type: ViewType) {
+        if view_type != self.library_view.view_type {
+            self.library_view.view_type = view_type;
+            self.regroup();
+        }
+    }
+
+    // The library grouped by album, whatever the current view is.
+    pub fn albums(&self) -> Vec<LibraryItemContainer> {
+        LibraryView::new(ViewType::Album, &self.items, &self.paths).containers
+    }
+
+    pub fn regroup(&mut self) {
+        self.library_view = LibraryView::new(
+            self.library_view.view_type.clone(),
+            &self.items,
+            &self.paths,
+        );
+    }
+}
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct LibraryPath {
+    id: LibraryPathId,
+    path: PathBuf,
+    status: LibraryPathStatus,
+}
+
+impl LibraryPath {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            id: LibraryPathId::from_path(&path),
+            path,
+            status: LibraryPathStatus::NotImported,
+        }
+    }
+
+    pub fn from_parts(id: LibraryPathId, path: PathBuf, status: LibraryPathStatus) -> Self {
+        Self { id, path, status }
+    }
+
+    pub fn id(&self) -> LibraryPathId {
+        self.id
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    pub fn status(&self) -> LibraryPathStatus {
+        self.status
+    }
+
+    pub fn set_status(&mut self, status: LibraryPathStatus) {
+        self.status = status;
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct LibraryPathId(usize);
+
+impl LibraryPathId {
+    pub fn new(id: usize) -> Self {
+        Self(id)
+    }
+
+    // The same folder always gets the same id, so it can be removed and added again.
+    pub fn from_path(path: &Path) -> Self {
+        Self(stable_hash(path) as usize)
+    }
+
+    pub fn get(&self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub enum LibraryPathStatus {
+    NotImported,
+    Imported,
+}
+
+// Items are the same track when their keys are, whatever the state of their tags.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryItem {
+    library_id: LibraryPathId,
+    path: PathBuf,
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    year: Option<i32>,
+    genre: Option<String>,
+    track_number: Option<u32>,
+    #[serde(default)]
+    replay_gain: ReplayGain,
+    #[serde(default)]
+    file_stamp: Option<FileStamp>,
+    // A hash of the start of the encoded audio, which stays the same when the file is tagged or
+    // moved.
+    #[serde(default)]
+    fingerprint: Option<u64>,
+    // Seconds since the Unix epoch.
+    #[serde(default)]
+    date_added: Option<u64>,
+    #[serde(default)]
+    play_count: u32,
+    #[serde(default)]
+    last_played: Option<u64>,
+    #[serde(default)]
+    properties: AudioProperties,
+    // Derived from the path the file was first seen at, then kept when it's renamed or changed.
+    // The library makes it unique when the item is added.
+    key: usize,
+}
+
+impl PartialEq for LibraryItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for LibraryItem {}
+
+impl Hash for LibraryItem {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key.hash(state);
+    }
+}
+
+impl LibraryItem {
+    pub fn new(path: PathBuf, library_id: LibraryPathId) -> Self {
+        Self {
+            library_id,
+            key: Self::key_for_path(&path),
+            path,
+            title: None,
+            artist: None,
+            album: None,
+            year: None,
+            genre: None,
+            track_number: None,
+            replay_gain: ReplayGain::default(),
+            file_stamp: None,
+            fingerprint: None,
+            date_added: None,
+            play_count: 0,
+            last_played: None,
+            properties: AudioProperties::default(),
+        }
+    }
+
+    // The key of a file first seen at `path`.
+    pub fn key_for_path(path: &Path) -> usize {
+        stable_hash(path) as usize
+    }
+
+    pub fn library_id(&self) -> LibraryPathId {
+        self.library_id
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    pub fn set_path(&mut self, path: PathBuf, library_id: LibraryPathId) -> Self {
+        self.path = path;
+        self.library_id = library_id;
+        self.to_owned()
+    }
+
+    pub fn key(&self) -> usize {
+        self.key
+    }
+
+    pub fn set_key(&mut self, key: usize) -> Self {
+        self.key = key;
+        self.to_owned()
+    }
+
+    pub fn set_title(&mut self, title: Option<&str>) -> Self {
+        self.title = title.map(str::to_string);
+        self.to_owned()
+    }
+
+    pub fn title(&self) -> Option<String> {
+        self.title.clone()
+    }
+
+    pub fn set_artist(&mut self, artist: Option<&str>) -> Self {
+        self.artist = artist.map(str::to_string);
+        self.to_owned()
+    }
+
+    pub fn artist(&self) -> Option<String> {
+        self.artist.clone()
+    }
+
+    pub fn set_album(&mut self, album: Option<&str>) -> Self {
+        self.album = album.map(str::to_string);
+        self.to_owned()
+    }
+
+    pub fn album(&self) -> Option<String> {
+        self.album.clone()
+    }
+
+    pub fn set_year(&mut self, year: Option<i32>) -> Self {
+        self.year = year;
+        self.to_owned()
+    }
+
+    pub fn year(&self) -> Option<i32> {
+        self.year.clone()
+    }
+
+    pub fn set_genre(&mut self, genre: Option<&str>) -> Self {
+        self.genre = genre.map(str::to_string);
+        self.to_owned()
+    }
+
+    pub fn genre(&self) -> Option<String> {
+        self.genre.clone()
+    }
+
+    pub fn set_track_number(&mut self, track_number: Option<u32>) -> Self {
+        self.track_number = track_number;
+        self.to_owned()
+    }
+
+    pub fn track_number(&self) -> Option<u32> {
+        self.track_number.clone()
+    }
+
+    pub fn set_replay_gain(&mut self, replay_gain: ReplayGain) -> Self {
+        self.replay_gain = replay_gain;
+        self.to_owned()
+    }
+
+    pub fn replay_gain(&self) -> ReplayGain {
+        self.replay_gain
+    }
+
+    pub fn set_file_stamp(&mut self, file_stamp: Option<FileStamp>) -> Self {
+        self.file_stamp = file_stamp;
+        self.to_owned()
+    }
+
+    pub fn file_stamp(&self) -> Option<FileStamp> {
+        self.file_stamp
+    }
+
+    pub fn set_fingerprint(&mut self, fingerprint: Option<u64>) -> Self {
+        self.fingerprint = fingerprint;
+        self.to_owned()
+    }
+
+    pub fn fingerprint(&self) -> Option<u64> {
+        self.fingerprint
+    }
+
+    pub fn set_date_added(&mut self, date_added: Option<u64>) -> Self {
+        self.date_added = date_added;
+        self.to_owned()
+    }
+
+    pub fn date_added(&self) -> Option<u64> {
+        self.date_added
+    }
+
+    pub fn set_play_count(&mut self, play_count: u32) -> Self {
+        self.play_count = play_count;
+        self.to_owned()
+    }
+
+    pub fn play_count(&self) -> u32 {
+        self.play_count
+    }
+
+    pub fn set_last_played(&mut self, last_played: Option<u64>) -> Self {
+        self.last_played = last_played;
+        self.to_owned()
+    }
+
+    pub fn last_played(&self) -> Option<u64> {
+        self.last_played
+    }
+
+    pub fn set_properties(&mut self, properties: AudioProperties) -> Self {
+        self.properties = properties;
+        self.to_owned()
+    }
+
+    pub fn properties(&self) -> &AudioProperties {
+        &self.properties
+    }
+
+    // Counts a play that finished at `at`, in seconds since the Unix epoch.
+    pub fn played(&mut self, at: u64) -> Self {
+        self.play_count += 1;
+        self.last_played = Some(at);
+        self.to_owned()
+    }
+
+    // Makes a freshly read copy of a file take the place of the item already in the library, so
+    // playlists holding it still refer to the same track. Its play statistics are kept, and so is
+    // ReplayGain from a loudness scan when the file has no ReplayGain tags.
+    pub fn replacing(&mut self, known_item: &LibraryItem) -> Self {
+        self.key = known_item.key;
+        self.date_added = known_item.date_added;
+        self.play_count = known_item.play_count;
+        self.last_played = known_item.last_played;
+
+        if self.replay_gain == ReplayGain::default() {
+            self.replay_gain = known_item.replay_gain;
+        }
+
+        self.to_owned()
+    }
+}
+
+/// A 64-bit FNV-1a hasher. Unlike `DefaultHasher` its output is guaranteed to never change, so
+/// it can be used for ids that are saved.
+pub struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+fn stable_hash(path: &Path) -> u64 {
+    let mut hasher = StableHasher::default();
+    hasher.write(path.as_os_str().as_encoded_bytes());
+    hasher.finish()
+}
+
+/// The technical properties of a track's audio, read from the file along with its tags.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AudioProperties {
+    pub duration_millis: Option<u64>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+    pub bits_per_sample: Option<u32>,
+    // The average of the whole file in kbit/s, tags included.
+    pub bitrate: Option<u32>,
+    pub codec: Option<String>,
+}
+
+impl AudioProperties {
+    // Ex. "Stereo", or "6 ch" for more channels than that.
+    pub fn channels_text(&self) -> Option<String> {
+        self.channels.map(|channels| match channels {
+            1 => "Mono".to_string(),
+            2 => "Stereo".to_string(),
+            channels => format!("{} ch", channels),
+        })
+    }
+}
+
+// A duration like "3:07", or "1:02:03" when it's an hour or longer.
+pub fn format_duration(millis: u64) -> String {
+    let seconds = millis / 1000;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+
+    match hours {
+        0 => format!("{}:{:02}", minutes, seconds),
+        _ => format!("{}:{:02}:{:02}", hours, minutes, seconds),
+    }
+}
+
+/// The modification time and size of a file when it was read, to tell whether it changed since.
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct FileStamp {
+    pub modified_millis: u64,
+    pub size: u64,
+}
+
+impl FileStamp {
+    pub fn read(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+        Some(Self {
+            modified_millis: modified.as_millis() as u64,
+            size: metadata.len(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LibraryView {
+    pub view_type: ViewType,
+    // Grouped again from the items on load, so only the view type is saved.
+    #[serde(skip_serializing, default)]
+    pub containers: Vec<LibraryItemContainer>,
+}
+
+impl LibraryView {
+    // Groups the items into a tree of containers, ex. artist -> album -> track. `paths` are the
+    // library paths the items came from, which are the roots of the folder view.
+    pub fn new(view_type: ViewType, items: &[LibraryItem], paths: &[LibraryPath]) -> Self {
+        let mut containers = match &view_type {
+            ViewType::Folder => group_by_folder(items, paths),
+            ViewType::Custom(script) => group_by_format(items, script),
+            _ => group_by_tags(items.iter().collect(), view_type.levels()),
+        };
+
+        sort_containers(&mut containers);
+
+        Self {
+            view_type,
+            containers,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LibraryItemContainer {
+    pub name: String,
+    pub items: Vec<LibraryItem>,
+    #[serde(default)]
+    pub children: Vec<LibraryItemContainer>,
+}
+
+impl LibraryItemContainer {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            items: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    // Every item in this container and the ones nested in it.
+    pub fn all_items(&self) -> Vec<&LibraryItem> {
+        let mut items = self.items.iter().collect::<Vec<&LibraryItem>>();
+        for child in &self.children {
+            items.extend(child.all_items());
+        }
+        items
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ViewType {
+    Album,
+    Artist,
+    Genre,
+    Year,
+    Folder,
+    // Grouped by a title formatting script, whose text is split into levels at each "|", ex.
+    // "%genre%|%artist%".
+    Custom(String),
+}
+
+type GroupKey = fn(&LibraryItem) -> Option<String>;
+
+impl ViewType {
+    pub const ALL: [ViewType; 5] = [
+        ViewType::Album,
+        ViewType::Artist,
+        ViewType::Genre,
+        ViewType::Year,
+        ViewType::Folder,
+    ];
+
+    // The tags each level of the tree is grouped by, outermost first.
+    fn levels(&self) -> &'static [GroupKey] {
+        match self {
+            ViewType::Album => &[LibraryItem::album],
+            ViewType::Artist => &[LibraryItem::artist, LibraryItem::album],
+            ViewType::Genre => &[LibraryItem::genre, LibraryItem::album],
+            ViewType::Year => &[year_name, LibraryItem::album],
+            ViewType::Folder | ViewType::Custom(_) => &[],
+        }
+    }
+}
+
+impl std::fmt::Display for ViewType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ViewType::Album => write!(f, "Album"),
+            ViewType::Artist => write!(f, "Artist / Album"),
+            ViewType::Genre => write!(f, "Genre / Album"),
+            ViewType::Year => write!(f, "Year / Album"),
+            ViewType::Folder => write!(f, "Folder"),
+            ViewType::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+fn year_name(item: &LibraryItem) -> Option<String> {
+    item.year().map(|year| year.to_string())
+}
+
+fn group_by_tags(items: Vec<&LibraryItem>, levels: &[GroupKey]) -> Vec<LibraryItemContainer> {
+    let named_items = items
+        .into_iter()
+        .map(|item| {
+            let names = levels
+                .iter()
+                .map(|group_key| group_key(item).unwrap_or_else(|| "<?>".to_string()))
+                .collect();
+            (names, item)
+        })
+        .collect();
+
+    group_by_names(named_items)
+}
+
+// Scripts that don't parse group by the default script instead.
+fn group_by_format(items: &[LibraryItem], script: &str) -> Vec<LibraryItemContainer> {
+    let named_items = items
+        .iter()
+        .map(|item| {
+            let names = title_format::format_or_default(script, DEFAULT_GROUPING, item)
+                .split('|')
+                .map(|name| match name.trim() {
+                    "" => "<?>".to_string(),
+                    name => name.to_string(),
+                })
+                .collect();
+            (names, item)
+        })
+        .collect();
+
+    group_by_names(named_items)
+}
+
+// Groups the items by the first of their names, then each group by the names after it. An item
+// is put in the container of its last name.
+fn group_by_names(items: Vec<(Vec<String>, &LibraryItem)>) -> Vec<LibraryItemContainer> {
+    let mut grouped: BTreeMap<String, Vec<(Vec<String>, &LibraryItem)>> = BTreeMap::new();
+    for (mut names, item) in items {
+        let name = match names.is_empty() {
+            true => "<?>".to_string(),
+            false => names.remove(0),
+        };
+        grouped.entry(name).or_default().push((names, item));
+    }
+
+    grouped
+        .into_iter()
+        .map(|(name, items)| {
+            let mut container = LibraryItemContainer::new(name);
+
+            let (nested_items, own_items): (Vec<_>, Vec<_>) =
+                items.into_iter().partition(|(names, _)| !names.is_empty());
+            container.items = own_items
+                .into_iter()
+                .map(|(_, item)| item.clone())
+                .collect();
+            container.children = group_by_names(nested_items);
+
+            container
+        })
+        .collect()
+}
+
+// One container per library path, with the sub folders nested inside.
+fn group_by_folder(items: &[LibraryItem], paths: &[LibraryPath]) -> Vec<LibraryItemContainer> {
+    paths
+        .iter()
+        .filter_map(|library_path| {
+            let mut root = LibraryItemContainer::new(library_path.path().display().to_string());
+
+            for item in items
+                .iter()
+                .filter(|item| item.library_id() == library_path.id())
+            {
+                let item_path = item.path();
+                let folders = item_path
+                    .parent()
+                    .and_then(|parent| parent.strip_prefix(library_path.path()).ok())
+                    .map(|relative| {
+                        relative
+                            .iter()
+                            .map(|folder| folder.to_string_lossy().into_owned())
+                            .collect::<Vec<String>>()
+                    })
+                    .unwrap_or_default();
+
+                let mut container = &mut root;
+                for folder in folders {
+                    let idx = match container.children.iter().position(|c| c.name == folder) {
+                        Some(idx) => idx,
+                        None => {
+                            container.children.push(LibraryItemContainer::new(folder));
+                            container.children.len() - 1
+                        }
+                    };
+                    container = &mut container.children[idx];
+                }
+
+                container.items.push(item.clone());
+            }
+
+            (!root.items.is_empty() || !root.children.is_empty()).then_some(root)
+        })
+        .collect()
+}
+
+fn merge_containers(into: &mut Vec<LibraryItemContainer>, from: Vec<LibraryItemContainer>) {
+    for container in from {
+        match into
+            .iter_mut()
+            .find(|existing| existing.name == container.name)
+        {
+            Some(existing) => {
+                for item in container.items {
+                    if !existing.items.iter().any(|i| i.key() == item.key()) {
+                        existing.items.push(item);
+                    }
+                }
+
+                merge_containers(&mut existing.children, container.children);
+            }
+            None => into.push(container),
+        }
+    }
+
+    sort_containers(into);
+}
+
+// Sorts containers by name and the tracks in them by track number, then file name.
+fn sort_containers(containers: &mut [LibraryItemContainer]) {
+    containers.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for container in containers {
+        container.items.sort_by_key(|item| {
+            (
+                item.track_number().is_none(),
+                item.track_number(),
+                item.path(),
+            )
+        });
+        sort_containers(&mut container.children);
+    }
+}
+
+// Replaces the items of the containers with the updates that have the same keys.
+fn update_container_items(
+    containers: &mut [LibraryItemContainer],
+    updates: &HashMap<usize, LibraryItem>,
+) {
+    for container in containers {
+        for item in container.items.iter_mut() {
+            if let Some(update) = updates.get(&item.key()) {
+                *item = update.clone();
+            }
+        }
+
+        update_container_items(&mut container.children, updates);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(library_path: &LibraryPath, file: &str, artist: &str, album: &str) -> LibraryItem {
+        LibraryItem::new(library_path.path().join(file), library_path.id())
+            .set_artist(Some(artist))
+            .set_album(Some(album))
+            .set_year(Some(2005))
+    }
+
+    fn names(containers: &[LibraryItemContainer]) -> Vec<&str> {
+        containers.iter().map(|c| c.name.as_str()).collect()
+    }
+
+    #[test]
+    fn groups_nested_levels_and_regroups_on_demand() {
+        let mut library = Library::new();
+        library.add_path(PathBuf::from("/music"));
+        let music = library.paths()[0].clone();
+
+        library.add_items(vec![
+            item(
+                &music,
+                "boc/campfire/01.flac",
+                "Boards of Canada",
+                "The Campfire Headphase",
+            ),
+            item(
+                &music,
+                "boc/geogaddi/01.flac",
+                "Boards of Canada",
+                "Geogaddi",
+            ),
+            item(&music, "low/trust/01.flac", "Low", "Trust"),
+        ]);
+
+        library.set_view_type(ViewType::Artist);
+        let artists = &library.view().containers;
+        assert_eq!(names(artists), ["Boards of Canada", "Low"]);
+        assert_eq!(
+            names(&artists[0].children),
+            ["Geogaddi", "The Campfire Headphase"]
+        );
+        assert_eq!(artists[0].all_items().len(), 2);
+
+        library.set_view_type(ViewType::Custom(
+            "$left(%artist%,1)|%album%[ (%date%)]".to_string(),
+        ));
+        let letters = &library.view().containers;
+        assert_eq!(names(letters), ["B", "L"]);
+        assert_eq!(names(&letters[1].children), ["Trust (2005)"]);
+
+        library.set_view_type(ViewType::Folder);
+        let root = &library.view().containers[0];
+        assert_eq!(root.name, "/music");
+        assert_eq!(names(&root.children), ["boc", "low"]);
+        assert_eq!(names(&root.children[0].children), ["campfire", "geogaddi"]);
+        assert_eq!(root.children[1].children[0].items.len(), 1);
+    }
+
+    #[test]
+    fn merges_containers_across_library_paths() {
+        let mut library = Library::new();
+        library.add_path(PathBuf::from("/music"));
+        library.add_path(PathBuf::from("/more-music"));
+        let (music, more_music) = (library.paths()[0].clone(), library.paths()[1].clone());
+
+        for (path, file) in [(&music, "trust/01.flac"), (&more_music, "trust/02.flac")] {
+            let items = vec![item(path, file, "Low", "Trust")];
+            library.add_items(items.clone());
+            library.add_view(LibraryView::new(
+                ViewType::Album,
+                &items,
+                std::slice::from_ref(path),
+            ));
+        }
+
+        let albums = &library.view().containers;
+        assert_eq!(names(albums), ["Trust"]);
+        assert_eq!(albums[0].items.len(), 2);
+
+        library.remove_path(music.id());
+        assert_eq!(library.view().containers[0].items.len(), 1);
+    }
+
+    #[test]
+    fn ids_are_derived_from_paths() {
+        let path = PathBuf::from("/music/trust/01.flac");
+        let untagged = LibraryItem::new(path.clone(), LibraryPathId::new(0));
+        let tagged = LibraryItem::new(path, LibraryPathId::new(0)).set_title(Some("Canada"));
+        let other = LibraryItem::new(PathBuf::from("/music/trust/02.flac"), LibraryPathId::new(0));
+
+        assert_eq!(untagged.key(), tagged.key());
+        assert_eq!(untagged, tagged);
+        assert_ne!(untagged, other);
+
+        assert_eq!(
+            LibraryPath::new(PathBuf::from("/music")).id(),
+            LibraryPath::new(PathBuf::from("/music")).id()
+        );
+    }
+
+    #[test]
+    fn adds_each_file_once_with_a_unique_key() {
+        let music = LibraryPath::new(PathBuf::from("/music"));
+        let album = LibraryPath::new(PathBuf::from("/music/trust"));
+        let mut library = Library::new();
+        library.add_items(vec![item(&music, "trust/01.flac", "Low", "Trust")]);
+
+        // The same file again, through a library path inside the first one.
+        let again = LibraryItem::new(PathBuf::from("/music/trust/01.flac"), album.id())
+            .set_title(Some("(That's How You Sing) Amazing Grace"));
+        library.add_items(vec![again.clone(), again]);
+        assert_eq!(library.items().len(), 1);
+        assert_eq!(
+            library.items()[0].title().as_deref(),
+            Some("(That's How You Sing) Amazing Grace")
+        );
+
+        // The track moves, keeping its key, and a new file is written where it was.
+        let moved = library.items()[0]
+            .clone()
+            .set_path(PathBuf::from("/music/trust/moved.flac"), music.id());
+        library.update_items(std::slice::from_ref(&moved));
+        library.add_items(vec![item(&music, "trust/01.flac", "Low", "Trust")]);
+
+        let items = library.items();
+        assert_eq!(items.len(), 2);
+        assert_ne!(items[0].key(), items[1].key());
+        assert_eq!(items[0].path(), PathBuf::from("/music/trust/moved.flac"));
+        assert_eq!(items[1].path(), PathBuf::from("/music/trust/01.flac"));
+    }
+}
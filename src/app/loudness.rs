@@ -0,0 +1,428 @@
+use std::f64::consts::PI;
+use std::path::Path;
+
+use symphonia::core::audio::{AudioBufferRef, Channels};
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error;
+
+use super::media;
+use super::replay_gain::ReplayGain;
+
+/// ReplayGain 2.0 plays every track as if it had this integrated loudness, in LUFS.
+pub const REFERENCE_LOUDNESS: f64 = -18.0;
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+
+// Taps of each phase of the 4x oversampling filter used for true peak detection.
+const TRUE_PEAK_TAPS: usize = 12;
+const TRUE_PEAK_PHASES: usize = 4;
+
+/// Measures loudness per ITU-R BS.1770: K-weighted mean square power over 400ms blocks that
+/// overlap by 75%, and the true peak from 4x oversampled audio.
+pub struct LoudnessMeter {
+    weights: Vec<f64>,
+    filters: Vec<[Biquad; 2]>,
+    interpolator: [[f32; TRUE_PEAK_TAPS]; TRUE_PEAK_PHASES],
+    // The last samples of each channel, newest first, for the oversampling filter.
+    history: Vec<[f32; TRUE_PEAK_TAPS]>,
+    true_peak: f32,
+    // Weighted power summed over the current 100ms step and the last four complete steps.
+    step_frames: usize,
+    step_position: usize,
+    step_power: f64,
+    recent_steps: [f64; 4],
+    completed_steps: usize,
+    blocks: Vec<f64>,
+    samples: Vec<Vec<f32>>,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, channels: Channels) -> Self {
+        let weights = channels.iter().map(channel_weight).collect::<Vec<f64>>();
+        let count = weights.len();
+
+        Self {
+            weights,
+            filters: vec![k_weighting(sample_rate as f64); count],
+            interpolator: interpolator(),
+            history: vec![[0.0; TRUE_PEAK_TAPS]; count],
+            true_peak: 0.0,
+            step_frames: (sample_rate as usize / 10).max(1),
+            step_position: 0,
+            step_power: 0.0,
+            recent_steps: [0.0; 4],
+            completed_steps: 0,
+            blocks: Vec::new(),
+            samples: vec![Vec::new(); count],
+        }
+    }
+
+    pub fn process(&mut self, buffer: &AudioBufferRef<'_>) {
+        for channel in self.samples.iter_mut() {
+            channel.clear();
+        }
+        crate::resampler::convert_samples_any(buffer, &mut self.samples);
+
+        for frame in 0..buffer.frames() {
+            let mut power = 0.0;
+
+            for c in 0..self.samples.len() {
+                let sample = self.samples[c][frame];
+
+                let filtered = self.filters[c]
+                    .iter_mut()
+                    .fold(sample as f64, |x, filter| filter.process(x));
+                power += self.weights[c] * filtered * filtered;
+
+                let history = &mut self.history[c];
+                history.copy_within(0..TRUE_PEAK_TAPS - 1, 1);
+                history[0] = sample;
+
+                for phase in self.interpolator.iter() {
+                    let interpolated = phase
+                        .iter()
+                        .zip(history.iter())
+                        .map(|(tap, x)| tap * x)
+                        .sum::<f32>();
+                    self.true_peak = self.true_peak.max(interpolated.abs());
+                }
+            }
+
+            self.step_power += power;
+            self.step_position += 1;
+
+            if self.step_position == self.step_frames {
+                self.complete_step();
+            }
+        }
+    }
+
+    fn complete_step(&mut self) {
+        self.recent_steps.rotate_left(1);
+        self.recent_steps[3] = self.step_power;
+        self.completed_steps += 1;
+        self.step_power = 0.0;
+        self.step_position = 0;
+
+        if self.completed_steps >= 4 {
+            let block_power = self.recent_steps.iter().sum::<f64>() / (4 * self.step_frames) as f64;
+            self.blocks.push(block_power);
+        }
+    }
+
+    /// Mean square power of every 400ms gating block measured so far.
+    pub fn blocks(&self) -> &[f64] {
+        &self.blocks
+    }
+
+    /// The highest absolute sample value of the 4x oversampled signal, where 1.0 is full scale.
+    pub fn true_peak(&self) -> f32 {
+        self.true_peak
+    }
+}
+
+/// The gated integrated loudness of a set of blocks in LUFS, or `None` when all of them are
+/// below the absolute gate (ex. silence or a track shorter than one block).
+pub fn gated_loudness(blocks: &[f64]) -> Option<f64> {
+    let above_absolute = blocks
+        .iter()
+        .copied()
+        .filter(|power| loudness(*power) > ABSOLUTE_GATE)
+        .collect::<Vec<f64>>();
+
+    if above_absolute.is_empty() {
+        return None;
+    }
+
+    let relative_gate = loudness(mean(&above_absolute)) + RELATIVE_GATE;
+    let above_relative = above_absolute
+        .into_iter()
+        .filter(|power| loudness(*power) > relative_gate)
+        .collect::<Vec<f64>>();
+
+    Some(loudness(mean(&above_relative)))
+}
+
+fn loudness(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+// LFE channels are left out of the measurement and surround channels count for more.
+fn channel_weight(channel: Channels) -> f64 {
+    if channel == Channels::LFE1 || channel == Channels::LFE2 {
+        0.0
+    } else if [
+        Channels::SIDE_LEFT,
+        Channels::SIDE_RIGHT,
+        Channels::REAR_LEFT,
+        Channels::REAR_RIGHT,
+    ]
+    .contains(&channel)
+    {
+        1.41
+    } else {
+        1.0
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+// The BS.1770 pre-filter (a high shelf modelling the head) followed by the RLB high pass. The
+// standard only lists coefficients for 48kHz, so they're derived from the analog prototypes for
+// any other rate.
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    let f0 = 1681.974450955533;
+    let gain_db = 3.999843853973347;
+    let q = 0.7071752369554196;
+
+    let k = (PI * f0 / sample_rate).tan();
+    let vh = 10.0f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+
+    let k = (PI * f0 / sample_rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+// A Hann windowed sinc split into polyphase filters, each normalised to unity gain at DC.
+fn interpolator() -> [[f32; TRUE_PEAK_TAPS]; TRUE_PEAK_PHASES] {
+    let len = TRUE_PEAK_TAPS * TRUE_PEAK_PHASES;
+    let centre = (len - 1) as f64 / 2.0;
+    let mut phases = [[0.0; TRUE_PEAK_TAPS]; TRUE_PEAK_PHASES];
+
+    for (phase, taps) in phases.iter_mut().enumerate() {
+        for (tap, coefficient) in taps.iter_mut().enumerate() {
+            let n = (tap * TRUE_PEAK_PHASES + phase) as f64;
+            let t = (n - centre) / TRUE_PEAK_PHASES as f64;
+            let sinc = if t == 0.0 {
+                1.0
+            } else {
+                (PI * t).sin() / (PI * t)
+            };
+            let window = 0.5 - 0.5 * (2.0 * PI * (n + 0.5) / len as f64).cos();
+
+            *coefficient = (sinc * window) as f32;
+        }
+
+        let sum = taps.iter().sum::<f32>();
+        taps.iter_mut().for_each(|coefficient| *coefficient /= sum);
+    }
+
+    phases
+}
+
+/// The loudness measurements of one track.
+pub struct TrackLoudness {
+    pub blocks: Vec<f64>,
+    pub true_peak: f32,
+}
+
+impl TrackLoudness {
+    pub fn gain(&self) -> Option<f32> {
+        gated_loudness(&self.blocks).map(|loudness| (REFERENCE_LOUDNESS - loudness) as f32)
+    }
+}
+
+/// Decodes a whole track and measures it. Returns `None` if it can't be decoded.
+pub fn scan_track(path: &Path) -> Option<TrackLoudness> {
+    let mut probed = media::probe(path)?;
+    let track = probed
+        .format
+        .tracks()
+        .iter()
+        .find(|track| media::has_decoder(track))?
+        .clone();
+
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .ok()?;
+    let mut meter: Option<LoudnessMeter> = None;
+
+    loop {
+        let packet = match probed.format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => {
+                tracing::warn!("Stopped scanning {:?} early: {}", path, err);
+                break;
+            }
+        };
+
+        if packet.track_id() != track.id {
+            continue;
+        }
+
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                let spec = *decoded.spec();
+                meter
+                    .get_or_insert_with(|| LoudnessMeter::new(spec.rate, spec.channels))
+                    .process(&decoded);
+            }
+            Err(Error::DecodeError(err)) => tracing::warn!("decode error: {}", err),
+            Err(err) => {
+                tracing::warn!("Couldn't scan {:?}: {}", path, err);
+                return None;
+            }
+        }
+    }
+
+    meter.map(|meter| TrackLoudness {
+        blocks: meter.blocks().to_vec(),
+        true_peak: meter.true_peak(),
+    })
+}
+
+/// Track and album ReplayGain of the tracks of one album. The album gain is measured over the
+/// blocks of every track together, not averaged from the track gains.
+pub fn album_replay_gain(tracks: &[TrackLoudness]) -> Vec<ReplayGain> {
+    let album_blocks = tracks
+        .iter()
+        .flat_map(|track| track.blocks.iter().copied())
+        .collect::<Vec<f64>>();
+    let album_gain =
+        gated_loudness(&album_blocks).map(|loudness| (REFERENCE_LOUDNESS - loudness) as f32);
+    let album_peak = tracks.iter().map(|track| track.true_peak).reduce(f32::max);
+
+    tracks
+        .iter()
+        .map(|track| ReplayGain {
+            track_gain: track.gain(),
+            track_peak: Some(track.true_peak),
+            album_gain,
+            album_peak,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+    use symphonia::core::audio::{AudioBuffer, Signal, SignalSpec};
+
+    const RATE: u32 = 48000;
+
+    fn stereo_sine(frequency: f64, amplitude: f64, phase: f64, seconds: usize) -> AudioBuffer<f32> {
+        let frames = RATE as usize * seconds;
+        let spec = SignalSpec::new(RATE, Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+        let mut buffer = AudioBuffer::new(frames as u64, spec);
+        buffer.render_reserved(Some(frames));
+
+        for c in 0..2 {
+            for (i, sample) in buffer.chan_mut(c).iter_mut().enumerate() {
+                let t = i as f64 / RATE as f64;
+                *sample = (amplitude * (2.0 * PI * frequency * t + phase).sin()) as f32;
+            }
+        }
+
+        buffer
+    }
+
+    fn measure(buffer: &AudioBuffer<f32>) -> LoudnessMeter {
+        let mut meter = LoudnessMeter::new(RATE, buffer.spec().channels);
+        meter.process(&AudioBufferRef::F32(Cow::Borrowed(buffer)));
+        meter
+    }
+
+    // EBU Tech 3341 case 1: a stereo 1kHz sine at -23 dBFS reads -23 LUFS.
+    #[test]
+    fn measures_reference_sine() {
+        let amplitude = 10.0f64.powf(-23.0 / 20.0);
+        let meter = measure(&stereo_sine(1000.0, amplitude, 0.0, 10));
+
+        let loudness = gated_loudness(meter.blocks()).unwrap();
+        assert!((loudness - -23.0).abs() < 0.1, "{}", loudness);
+    }
+
+    #[test]
+    fn gates_out_silence() {
+        let silence = stereo_sine(1000.0, 0.0, 0.0, 2);
+        assert_eq!(gated_loudness(measure(&silence).blocks()), None);
+
+        // Quiet passages far below the rest of the track don't drag the loudness down.
+        let amplitude = 10.0f64.powf(-23.0 / 20.0);
+        let mut blocks = measure(&stereo_sine(1000.0, amplitude, 0.0, 10))
+            .blocks()
+            .to_vec();
+        let loud_only = gated_loudness(&blocks).unwrap();
+        blocks.extend(measure(&stereo_sine(1000.0, amplitude / 100.0, 0.0, 10)).blocks());
+        assert!((gated_loudness(&blocks).unwrap() - loud_only).abs() < 0.01);
+    }
+
+    #[test]
+    fn finds_true_peak_between_samples() {
+        // A quarter sample rate sine shifted by 45 degrees never has a sample on its peaks.
+        let buffer = stereo_sine(RATE as f64 / 4.0, 0.5, PI / 4.0, 1);
+        let sample_peak = buffer
+            .chan(0)
+            .iter()
+            .fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!(sample_peak < 0.36);
+
+        let true_peak = measure(&buffer).true_peak();
+        assert!((true_peak - 0.5).abs() < 0.02, "{}", true_peak);
+    }
+
+    #[test]
+    fn album_gain_uses_all_blocks() {
+        let amplitude = 10.0f64.powf(-23.0 / 20.0);
+        let loud = measure(&stereo_sine(1000.0, amplitude, 0.0, 5));
+        let quiet = measure(&stereo_sine(1000.0, amplitude / 2.0, 0.0, 5));
+        let tracks = [loud, quiet].map(|meter| TrackLoudness {
+            blocks: meter.blocks().to_vec(),
+            true_peak: meter.true_peak(),
+        });
+
+        let gains = album_replay_gain(&tracks);
+
+        assert!((gains[0].track_gain.unwrap() - 5.0).abs() < 0.1);
+        assert!((gains[1].track_gain.unwrap() - 11.02).abs() < 0.1);
+        let album_gain = gains[0].album_gain.unwrap();
+        assert!(album_gain > 5.0 && album_gain < 11.02);
+        assert!(gains.iter().all(|gain| gain.album_gain == Some(album_gain)));
+        assert!(gains
+            .iter()
+            .all(|gain| gain.album_peak == Some(tracks[0].true_peak)));
+    }
+}
@@ -0,0 +1,604 @@
+use std::fs::File;
+use std::hash::Hasher;
+use std::path::Path;
+
+use symphonia::core::codecs::{
+    CODEC_TYPE_NULL, CODEC_TYPE_PCM_F32BE, CODEC_TYPE_PCM_F32LE, CODEC_TYPE_PCM_F64BE,
+    CODEC_TYPE_PCM_F64LE,
+};
+use symphonia::core::formats::{FormatOptions, Track};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey, Value};
+use symphonia::core::probe::{Hint, ProbeResult};
+
+use super::library::{AudioProperties, StableHasher};
+use super::replay_gain::{self, ReplayGain};
+use super::{FileStamp, LibraryItem, LibraryPathId};
+
+// Enough packets to tell tracks apart, while keeping the import fast.
+const FINGERPRINT_PACKETS: usize = 16;
+
+// Files that commonly sit next to music and are never audio. Skipping these avoids probing
+// every cover image and log file, and avoids false positives from MP3 frame sync detection
+// inside arbitrary binary data.
+const NON_AUDIO_EXTENSIONS: [&str; 18] = [
+    "jpg", "jpeg", "png", "gif", "bmp", "webp", "txt", "nfo", "log", "cue", "pdf", "m3u", "m3u8",
+    "pls", "xspf", "db", "ini", "sfv",
+];
+
+/// Probes the content of a file for a container symphonia can read. The extension is only used
+/// as a hint, so mislabelled or extensionless files are still detected.
+pub fn probe(path: &Path) -> Option<ProbeResult> {
+    let file = File::open(path).ok()?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    symphonia::default::get_probe()
+        .format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .ok()
+}
+
+/// Probes a file and builds a `LibraryItem` from its tags, whatever the tag format. Returns `None`
+/// when the file has no track an enabled decoder can play.
+pub fn read_library_item(path: &Path, library_id: LibraryPathId) -> Option<LibraryItem> {
+    if is_known_non_audio(path) {
+        return None;
+    }
+
+    let mut probed = probe(path)?;
+
+    if !probed.format.tracks().iter().any(has_decoder) {
+        return None;
+    }
+
+    let tags = read_tags(&mut probed);
+    let properties = read_properties(&probed, path);
+    let fingerprint = audio_fingerprint(&mut probed);
+
+    Some(
+        LibraryItem::new(path.to_path_buf(), library_id)
+            .set_title(tags.title.as_deref())
+            .set_artist(tags.artist.or(tags.album_artist).as_deref())
+            .set_album(tags.album.as_deref())
+            .set_year(tags.year)
+            .set_genre(tags.genre.as_deref())
+            .set_track_number(tags.track_number)
+            .set_replay_gain(tags.replay_gain)
+            .set_file_stamp(FileStamp::read(path))
+            .set_fingerprint(fingerprint)
+            .set_properties(properties),
+    )
+}
+
+/// The properties of the first playable track, from its codec parameters. The duration and
+/// bitrate are missing when the container doesn't say how many frames there are.
+pub fn read_properties(probed: &ProbeResult, path: &Path) -> AudioProperties {
+    let Some(track) = probed.format.tracks().iter().find(|t| has_decoder(t)) else {
+        return AudioProperties::default();
+    };
+    let params = &track.codec_params;
+
+    let duration_millis = match (params.time_base, params.n_frames, params.sample_rate) {
+        (Some(time_base), Some(n_frames), _) => {
+            let time = time_base.calc_time(n_frames);
+            Some(time.seconds * 1000 + (time.frac * 1000.0) as u64)
+        }
+        (None, Some(n_frames), Some(sample_rate)) if sample_rate > 0 => {
+            Some(n_frames * 1000 / sample_rate as u64)
+        }
+        _ => None,
+    };
+
+    let bitrate = duration_millis
+        .filter(|millis| *millis > 0)
+        .zip(std::fs::metadata(path).ok())
+        .map(|(millis, metadata)| ((metadata.len() * 8 + millis / 2) / millis) as u32);
+
+    AudioProperties {
+        duration_millis,
+        sample_rate: params.sample_rate,
+        channels: params.channels.map(|channels| channels.count() as u32),
+        // Only integer PCM carries a bit depth, float PCM's is implied by the codec.
+        bits_per_sample: params.bits_per_sample.or(match params.codec {
+            CODEC_TYPE_PCM_F32LE | CODEC_TYPE_PCM_F32BE => Some(32),
+            CODEC_TYPE_PCM_F64LE | CODEC_TYPE_PCM_F64BE => Some(64),
+            _ => None,
+        }),
+        bitrate,
+        codec: symphonia::default::get_codecs()
+            .get_codec(params.codec)
+            .map(|descriptor| codec_name(descriptor.short_name)),
+    }
+}
+
+// The name codecs are known by, ex. "FLAC", rather than symphonia's short name, ex. "flac".
+fn codec_name(short_name: &str) -> String {
+    match short_name {
+        name if name.starts_with("pcm") => "PCM".to_string(),
+        "vorbis" => "Vorbis".to_string(),
+        "opus" => "Opus".to_string(),
+        name => name.to_uppercase(),
+    }
+}
+
+/// Hashes the format of the first playable track and the start of its encoded audio. Tags aren't
+/// part of the packets, so the fingerprint only changes when the audio does.
+fn audio_fingerprint(probed: &mut ProbeResult) -> Option<u64> {
+    let track = probed.format.tracks().iter().find(|t| has_decoder(t))?;
+    let track_id = track.id;
+    let params = &track.codec_params;
+
+    let mut hasher = StableHasher::default();
+    hasher.write(params.codec.to_string().as_bytes());
+    hasher.write(&params.sample_rate.unwrap_or(0).to_le_bytes());
+    hasher.write(&params.n_frames.unwrap_or(0).to_le_bytes());
+
+    let mut packets = 0;
+    while packets < FINGERPRINT_PACKETS {
+        match probed.format.next_packet() {
+            Ok(packet) if packet.track_id() == track_id => {
+                hasher.write(&packet.data);
+                packets += 1;
+            }
+            Ok(_) => (),
+            Err(_) => break,
+        }
+    }
+
+    (packets > 0).then(|| hasher.finish())
+}
+
+/// Whether tags can be written back to the file. Only ID3v2 in MP3 files is supported for now.
+pub fn supports_tag_writing(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.eq_ignore_ascii_case("mp3"))
+        .unwrap_or(false)
+}
+
+/// Writes the ReplayGain tags to the ID3v2 tag of the file, replacing the existing ones and
+/// removing the ones without a value.
+pub fn write_replay_gain(path: &Path, replay_gain: &ReplayGain) -> id3::Result<()> {
+    use id3::TagLike;
+
+    let mut tag = read_id3_tag(path)?;
+
+    let format_gain = |gain: Option<f32>| gain.map(|gain| format!("{:.2} dB", gain));
+    let format_peak = |peak: Option<f32>| peak.map(|peak| format!("{:.6}", peak));
+
+    for (description, value) in [
+        ("REPLAYGAIN_TRACK_GAIN", format_gain(replay_gain.track_gain)),
+        ("REPLAYGAIN_TRACK_PEAK", format_peak(replay_gain.track_peak)),
+        ("REPLAYGAIN_ALBUM_GAIN", format_gain(replay_gain.album_gain)),
+        ("REPLAYGAIN_ALBUM_PEAK", format_peak(replay_gain.album_peak)),
+    ] {
+        tag.remove_extended_text(Some(description), None);
+
+        if let Some(value) = value {
+            tag.add_frame(id3::frame::ExtendedText {
+                description: description.to_string(),
+                value,
+            });
+        }
+    }
+
+    write_id3_tag(&tag, path)
+}
+
+/// Writes the tags of the item to the ID3v2 tag of the file, removing the ones without a value.
+/// The rest of the tag, ex. ReplayGain and cover art, is kept.
+pub fn write_tags(path: &Path, item: &LibraryItem) -> id3::Result<()> {
+    use id3::TagLike;
+
+    let mut tag = read_id3_tag(path)?;
+
+    match item.title() {
+        Some(title) => tag.set_title(title),
+        None => tag.remove_title(),
+    }
+
+    // The library shows the album artist for tracks without an artist, which is only written
+    // back as the artist once it's edited.
+    let imported_artist = tag.artist().or(tag.album_artist()).map(str::to_string);
+    if item.artist() != imported_artist {
+        match item.artist() {
+            Some(artist) => tag.set_artist(artist),
+            None => tag.remove_artist(),
+        }
+    }
+
+    match item.album() {
+        Some(album) => tag.set_album(album),
+        None => tag.remove_album(),
+    }
+
+    match item.genre() {
+        Some(genre) => tag.set_genre(genre),
+        None => tag.remove_genre(),
+    }
+
+    // A full recording date is kept as long as its year is still right.
+    tag.remove_year();
+    match item.year() {
+        Some(year) if tag.date_recorded().is_some_and(|date| date.year == year) => (),
+        Some(year) => tag.set_date_recorded(id3::Timestamp {
+            year,
+            month: None,
+            day: None,
+            hour: None,
+            minute: None,
+            second: None,
+        }),
+        None => tag.remove_date_recorded(),
+    }
+
+    match item.track_number() {
+        Some(track_number) => tag.set_track(track_number),
+        None => tag.remove_track(),
+    }
+
+    write_id3_tag(&tag, path)
+}
+
+// Keeps the version the file's tag already has, since not every player reads ID3v2.4. New tags
+// are ID3v2.4.
+fn write_id3_tag(tag: &id3::Tag, path: &Path) -> id3::Result<()> {
+    tag.write_to_path(path, tag.version())
+}
+
+// Files without a tag yet get a new one.
+fn read_id3_tag(path: &Path) -> id3::Result<id3::Tag> {
+    match id3::Tag::read_from_path(path) {
+        Ok(tag) => Ok(tag),
+        Err(id3::Error {
+            kind: id3::ErrorKind::NoTag,
+            ..
+        }) => Ok(id3::Tag::new()),
+        Err(err) => Err(err),
+    }
+}
+
+/// The standard tags `LibraryItem` cares about, independent of the tag format they came from.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TrackTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album_artist: Option<String>,
+    pub album: Option<String>,
+    pub year: Option<i32>,
+    pub genre: Option<String>,
+    pub track_number: Option<u32>,
+    pub replay_gain: ReplayGain,
+}
+
+impl TrackTags {
+    fn apply(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            let Some(std_key) = tag.std_key.or_else(|| replay_gain_key(&tag.key)) else {
+                continue;
+            };
+
+            match std_key {
+                StandardTagKey::TrackTitle => {
+                    set_if_some(&mut self.title, value_to_string(&tag.value))
+                }
+                StandardTagKey::Artist => {
+                    set_if_some(&mut self.artist, value_to_string(&tag.value))
+                }
+                StandardTagKey::AlbumArtist => {
+                    set_if_some(&mut self.album_artist, value_to_string(&tag.value))
+                }
+                StandardTagKey::Album => set_if_some(&mut self.album, value_to_string(&tag.value)),
+                StandardTagKey::Genre => set_if_some(&mut self.genre, value_to_string(&tag.value)),
+                StandardTagKey::TrackNumber => {
+                    set_if_some(&mut self.track_number, value_to_leading_number(&tag.value))
+                }
+                // Prefer the recording date, but fall back to the other dates when it's missing.
+                StandardTagKey::Date => {
+                    set_if_some(&mut self.year, value_to_leading_number(&tag.value))
+                }
+                StandardTagKey::ReleaseDate | StandardTagKey::OriginalDate
+                    if self.year.is_none() =>
+                {
+                    self.year = value_to_leading_number(&tag.value);
+                }
+                StandardTagKey::ReplayGainTrackGain => set_if_some(
+                    &mut self.replay_gain.track_gain,
+                    value_to_replay_gain(&tag.value),
+                ),
+                StandardTagKey::ReplayGainTrackPeak => set_if_some(
+                    &mut self.replay_gain.track_peak,
+                    value_to_replay_gain(&tag.value),
+                ),
+                StandardTagKey::ReplayGainAlbumGain => set_if_some(
+                    &mut self.replay_gain.album_gain,
+                    value_to_replay_gain(&tag.value),
+                ),
+                StandardTagKey::ReplayGainAlbumPeak => set_if_some(
+                    &mut self.replay_gain.album_peak,
+                    value_to_replay_gain(&tag.value),
+                ),
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Reads the tags found while probing (ex. ID3v2 in front of an MP3 stream) and then the tags of
+/// the container itself (ex. Vorbis comments, MP4 atoms, RIFF INFO), which take precedence.
+pub fn read_tags(probed: &mut ProbeResult) -> TrackTags {
+    let mut tags = TrackTags::default();
+
+    if let Some(mut metadata) = probed.metadata.get() {
+        if let Some(revision) = metadata.skip_to_latest() {
+            tags.apply(revision);
+        }
+    }
+
+    if let Some(revision) = probed.format.metadata().skip_to_latest() {
+        tags.apply(revision);
+    }
+
+    tags
+}
+
+fn set_if_some<T>(field: &mut Option<T>, value: Option<T>) {
+    if value.is_some() {
+        *field = value;
+    }
+}
+
+fn value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::Binary(_) | Value::Flag => None,
+        value => {
+            let value = value.to_string();
+            let trimmed = value.trim();
+
+            if trimmed.is_empty() {
+                None
+            } else {
+                Some(trimmed.to_string())
+            }
+        }
+    }
+}
+
+// Numeric tags are often stored as text with extra information, ex. a track number of "3/12" or
+// a date of "1998-05-01", so only the leading digits are used.
+fn value_to_leading_number<T: std::str::FromStr>(value: &Value) -> Option<T> {
+    let text = value_to_string(value)?;
+    let digits = text
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect::<String>();
+
+    digits.parse().ok()
+}
+
+fn value_to_replay_gain(value: &Value) -> Option<f32> {
+    match value {
+        Value::Float(value) => Some(*value as f32).filter(|value| value.is_finite()),
+        value => replay_gain::parse_tag_value(&value_to_string(value)?),
+    }
+}
+
+// Only upper case ID3 TXXX descriptions are mapped to standard keys. Lower case ones and MP4
+// freeform atoms (ex. "----:com.apple.iTunes:replaygain_track_gain") are matched by name.
+fn replay_gain_key(key: &str) -> Option<StandardTagKey> {
+    let key = key.to_ascii_lowercase();
+
+    if key.ends_with("replaygain_track_gain") {
+        Some(StandardTagKey::ReplayGainTrackGain)
+    } else if key.ends_with("replaygain_track_peak") {
+        Some(StandardTagKey::ReplayGainTrackPeak)
+    } else if key.ends_with("replaygain_album_gain") {
+        Some(StandardTagKey::ReplayGainAlbumGain)
+    } else if key.ends_with("replaygain_album_peak") {
+        Some(StandardTagKey::ReplayGainAlbumPeak)
+    } else {
+        None
+    }
+}
+
+fn is_known_non_audio(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| {
+            NON_AUDIO_EXTENSIONS
+                .iter()
+                .any(|non_audio| ext.eq_ignore_ascii_case(non_audio))
+        })
+        .unwrap_or(false)
+}
+
+// Opus streams, for example, are read by the Ogg and MKV demuxers but symphonia has no decoder
+// for them yet.
+pub fn has_decoder(track: &Track) -> bool {
+    track.codec_params.codec != CODEC_TYPE_NULL
+        && symphonia::default::get_codecs()
+            .get_codec(track.codec_params.codec)
+            .is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::wav::TestDir;
+    use symphonia::core::meta::{MetadataBuilder, Tag};
+
+    fn revision(tags: &[(StandardTagKey, Value)]) -> MetadataRevision {
+        let mut builder = MetadataBuilder::new();
+        for (key, value) in tags {
+            builder.add_tag(Tag::new(Some(*key), "", value.clone()));
+        }
+        builder.metadata()
+    }
+
+    #[test]
+    fn maps_standard_tags() {
+        let mut tags = TrackTags::default();
+        tags.apply(&revision(&[
+            (StandardTagKey::TrackTitle, Value::from("Roygbiv")),
+            (StandardTagKey::Artist, Value::from("Boards of Canada")),
+            (
+                StandardTagKey::Album,
+                Value::from("Music Has the Right to Children"),
+            ),
+            (StandardTagKey::Date, Value::from("1998-04-20")),
+            (StandardTagKey::Genre, Value::from("Electronic")),
+            (StandardTagKey::TrackNumber, Value::from("6/18")),
+        ]));
+
+        assert_eq!(tags.title.as_deref(), Some("Roygbiv"));
+        assert_eq!(tags.artist.as_deref(), Some("Boards of Canada"));
+        assert_eq!(
+            tags.album.as_deref(),
+            Some("Music Has the Right to Children")
+        );
+        assert_eq!(tags.year, Some(1998));
+        assert_eq!(tags.genre.as_deref(), Some("Electronic"));
+        assert_eq!(tags.track_number, Some(6));
+    }
+
+    #[test]
+    fn later_revisions_override_earlier_ones() {
+        let mut tags = TrackTags::default();
+        tags.apply(&revision(&[
+            (StandardTagKey::TrackTitle, Value::from("id3 title")),
+            (StandardTagKey::TrackNumber, Value::from(3u32)),
+        ]));
+        tags.apply(&revision(&[(
+            StandardTagKey::TrackTitle,
+            Value::from("vorbis title"),
+        )]));
+
+        assert_eq!(tags.title.as_deref(), Some("vorbis title"));
+        assert_eq!(tags.track_number, Some(3));
+    }
+
+    #[test]
+    fn release_date_only_fills_a_missing_year() {
+        let mut tags = TrackTags::default();
+        tags.apply(&revision(&[
+            (StandardTagKey::OriginalDate, Value::from("1972")),
+            (StandardTagKey::Date, Value::from("2011")),
+        ]));
+
+        assert_eq!(tags.year, Some(2011));
+    }
+
+    #[test]
+    fn reads_replay_gain_from_standard_and_freeform_keys() {
+        let mut builder = MetadataBuilder::new();
+        builder.add_tag(Tag::new(
+            Some(StandardTagKey::ReplayGainTrackGain),
+            "REPLAYGAIN_TRACK_GAIN",
+            Value::from("-7.03 dB"),
+        ));
+        builder.add_tag(Tag::new(
+            None,
+            "TXXX:replaygain_album_gain",
+            Value::from("-8.50 dB"),
+        ));
+        builder.add_tag(Tag::new(
+            None,
+            "----:com.apple.iTunes:replaygain_album_peak",
+            Value::from("0.977"),
+        ));
+
+        let mut tags = TrackTags::default();
+        tags.apply(&builder.metadata());
+
+        assert_eq!(
+            tags.replay_gain,
+            ReplayGain {
+                track_gain: Some(-7.03),
+                track_peak: None,
+                album_gain: Some(-8.5),
+                album_peak: Some(0.977),
+            }
+        );
+    }
+
+    #[test]
+    fn writes_replay_gain_to_id3() {
+        let dir = TestDir::new("rg");
+        let path = dir.join("track.mp3");
+        std::fs::write(&path, []).unwrap();
+
+        let replay_gain = ReplayGain {
+            track_gain: Some(-7.031),
+            track_peak: Some(0.5),
+            album_gain: None,
+            album_peak: None,
+        };
+        write_replay_gain(&path, &replay_gain).unwrap();
+
+        let tag = id3::Tag::read_from_path(&path).unwrap();
+        let values = tag
+            .extended_texts()
+            .map(|text| (text.description.as_str(), text.value.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            [
+                ("REPLAYGAIN_TRACK_GAIN", "-7.03 dB"),
+                ("REPLAYGAIN_TRACK_PEAK", "0.500000")
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_tags_without_changing_the_id3_version() {
+        use id3::TagLike;
+
+        let dir = TestDir::new("tags");
+        let path = dir.join("track.mp3");
+        std::fs::write(&path, []).unwrap();
+        let mut tag = id3::Tag::new();
+        tag.set_album_artist("Various Artists");
+        tag.set_title("Old");
+        tag.write_to_path(&path, id3::Version::Id3v23).unwrap();
+
+        // Only the title is edited, the artist is the album artist the library shows.
+        let mut item = LibraryItem::new(path.clone(), LibraryPathId::new(0))
+            .set_title(Some("New"))
+            .set_artist(Some("Various Artists"));
+        write_tags(&path, &item).unwrap();
+
+        let tag = id3::Tag::read_from_path(&path).unwrap();
+        assert_eq!(tag.version(), id3::Version::Id3v23);
+        assert_eq!(tag.title(), Some("New"));
+        assert_eq!(tag.artist(), None);
+        assert_eq!(tag.album_artist(), Some("Various Artists"));
+
+        write_tags(&path, &item.set_artist(Some("Someone"))).unwrap();
+        let tag = id3::Tag::read_from_path(&path).unwrap();
+        assert_eq!(tag.artist(), Some("Someone"));
+    }
+
+    #[test]
+    fn reads_audio_properties() {
+        let dir = TestDir::new("props");
+        let path = dir.write_silence("props.wav", 44100);
+
+        let item = read_library_item(&path, LibraryPathId::new(0)).unwrap();
+        let properties = item.properties();
+        assert_eq!(properties.duration_millis, Some(1000));
+        assert_eq!(properties.sample_rate, Some(44100));
+        assert_eq!(properties.channels_text().as_deref(), Some("Mono"));
+        assert_eq!(properties.bits_per_sample, Some(32));
+        assert_eq!(properties.bitrate, Some(1412));
+        assert_eq!(properties.codec.as_deref(), Some("PCM"));
+    }
+}
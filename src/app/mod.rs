@@ -1,51 +1,106 @@
+use album_art::AlbumArt;
+use database::LibraryDatabase;
+use history::History;
 use library::{
-    Library, LibraryItem, LibraryItemContainer, LibraryPath, LibraryPathId, LibraryPathStatus,
-    LibraryView, ViewType,
+    FileStamp, Library, LibraryItem, LibraryItemContainer, LibraryPath, LibraryPathId,
+    LibraryPathStatus, LibraryView, ViewType,
 };
-use player::Player;
+use player::{PlaybackMode, Player};
 use playlist::Playlist;
+use playlist_columns::PlaylistColumns;
+use replay_gain::{ReplayGain, ReplayGainSettings};
 use rms_calculator::RmsCalculator;
 use scope::Scope;
+use search::LibrarySearch;
+use smart_playlist::SmartPlaylistDraft;
+use tag_edit::TagEditDraft;
+use title_format::DisplayFormats;
+use watcher::LibraryWatcher;
 
 use serde::{Deserialize, Serialize};
 
-use std::collections::BTreeMap;
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::AtomicBool;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
 
-use id3::{Tag, TagLike};
 use rayon::prelude::*;
 use rayon::ThreadPool;
 
+pub mod album_art;
 mod app;
 mod components;
+mod database;
+mod history;
 mod library;
+mod loudness;
+mod media;
 pub mod meter;
 pub mod player;
 mod playlist;
+mod playlist_columns;
+mod playlist_file;
+pub mod replay_gain;
 pub mod rms_calculator;
 pub mod scope;
+mod search;
+mod smart_playlist;
+mod tag_edit;
+mod title_format;
+mod watcher;
+
+// How many imported items are sent to the UI thread at once.
+const IMPORT_BATCH_LEN: usize = 256;
 
 pub enum AudioCommand {
     Stop,
     Play,
     Pause,
     Seek(u64),
-    LoadFile(std::path::PathBuf),
+    LoadFile(std::path::PathBuf, ReplayGain),
+    QueueNext {
+        path: Option<std::path::PathBuf>,
+        crossfade: bool,
+        replay_gain: ReplayGain,
+    },
+    SetCrossfade(u32),
+    SetReplayGain(ReplayGainSettings),
+    // The playing track's ReplayGain changed, ex. after a loudness scan.
+    SetTrackReplayGain(ReplayGain),
     Select(usize),
     SetVolume(f32),
 }
 
 pub enum UiCommand {
     AudioFinished,
+    // The audio thread stopped on its own, e.g. because the output device couldn't be opened.
+    AudioStopped,
+    // The audio thread couldn't open or decode the track it was asked to play.
+    TrackFailed(std::path::PathBuf),
+    TrackAdvanced(std::path::PathBuf),
     TotalTrackDuration(u64),
     CurrentTimestamp(u64),
     SampleRate(f32),
     LibraryAddView(LibraryView),
-    LibraryAddItem(LibraryItem),
     LibraryAddItems(Vec<LibraryItem>),
     LibraryAddPathId(LibraryPathId),
+    LibraryUpdateItems(Vec<LibraryItem>),
+    LoudnessScanProgress {
+        scanned: usize,
+        total: usize,
+    },
+    LibraryRescanned {
+        added: Vec<LibraryItem>,
+        updated: Vec<LibraryItem>,
+        removed: Vec<usize>,
+    },
+    LibraryFilesChanged(Vec<LibraryItem>),
+    LibraryFileRenamed {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    LibraryFilesRemoved(Vec<PathBuf>),
+    TagsWritten(Vec<LibraryItem>),
 }
 
 #[derive(Serialize, Deserialize)]
@@ -58,6 +113,9 @@ pub struct App {
 
     pub show_oscilloscope: bool,
 
+    #[serde(default)]
+    pub show_queue: bool,
+
     pub show_rms_meter: bool,
 
     pub rms_meter_window_size_millis: u16,
@@ -67,6 +125,31 @@ pub struct App {
 
     pub device_sample_rate: f32,
 
+    // Zero turns crossfading off.
+    #[serde(default)]
+    pub crossfade_millis: u32,
+
+    #[serde(default)]
+    pub replay_gain: ReplayGainSettings,
+
+    #[serde(default)]
+    pub write_replay_gain_tags: bool,
+
+    #[serde(default)]
+    pub playback_mode: PlaybackMode,
+
+    // Keeps the library up to date with changes to the files in the library paths.
+    #[serde(default)]
+    pub watch_library: bool,
+
+    // The columns of the playlists without columns of their own.
+    #[serde(default)]
+    pub playlist_columns: PlaylistColumns,
+
+    // The title formatting scripts of the window title and the footer.
+    #[serde(default)]
+    pub display_formats: DisplayFormats,
+
     #[serde(skip_serializing, skip_deserializing)]
     pub rms_calc_left: RmsCalculator,
 
@@ -123,6 +206,38 @@ pub struct App {
 
     #[serde(skip_serializing, skip_deserializing)]
     pub thread_pool: Option<Arc<ThreadPool>>,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    pub loudness_scan_progress: Option<(usize, usize)>,
+
+    // The playlist the selected track was started from, which keeps supplying the next track
+    // while other playlists are viewed.
+    #[serde(skip_serializing, skip_deserializing)]
+    pub playing_playlist_idx: Option<usize>,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    pub library_search: LibrarySearch,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    pub library_watcher: Option<LibraryWatcher>,
+
+    // The smart playlist open in the rule builder.
+    #[serde(skip_serializing, skip_deserializing)]
+    pub smart_playlist_draft: Option<SmartPlaylistDraft>,
+
+    // The tracks open in the tag editor.
+    #[serde(skip_serializing, skip_deserializing)]
+    pub tag_edit_draft: Option<TagEditDraft>,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    pub album_art: AlbumArt,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    pub history: History,
+
+    // How many tracks in a row the audio thread couldn't play.
+    #[serde(skip_serializing, skip_deserializing)]
+    pub failed_tracks: usize,
 }
 
 impl Default for App {
@@ -134,9 +249,17 @@ impl Default for App {
             // All of these show_XYZ booleans can probably be captured in a bitmap
             show_oscilloscope: false,
             show_rms_meter: false,
+            show_queue: false,
             show_preferences_window: false,
             volume: 0.707,
             device_sample_rate: 44100.0,
+            crossfade_millis: 0,
+            replay_gain: ReplayGainSettings::default(),
+            write_replay_gain_tags: false,
+            playback_mode: PlaybackMode::Default,
+            watch_library: false,
+            playlist_columns: PlaylistColumns::default(),
+            display_formats: DisplayFormats::default(),
             rms_meter_window_size_millis: 250,
             rms_calc_left: RmsCalculator::new(5000),
             rms_calc_right: RmsCalculator::new(5000),
@@ -156,6 +279,15 @@ impl Default for App {
             is_editing_playlist_name: false,
             is_processing_ui_change: None,
             thread_pool: None,
+            loudness_scan_progress: None,
+            playing_playlist_idx: None,
+            library_search: LibrarySearch::default(),
+            library_watcher: None,
+            smart_playlist_draft: None,
+            tag_edit_draft: None,
+            album_art: AlbumArt::default(),
+            history: History::default(),
+            failed_tracks: 0,
         }
     }
 }
@@ -176,6 +308,45 @@ impl App {
         confy::load("music_player", None).map_err(|_| TempError::MissingAppState)
     }
 
+    // Opens the library database next to the app state, migrating a library saved in the app
+    // state into it. Without it, changes to the library aren't saved.
+    // Hands the app the channels and the thread pool it works with, then opens the library
+    // database with `open_database`. The database comes last, since relinking the playlists to
+    // the library reads the tracks outside of it on the thread pool.
+    pub fn start(
+        &mut self,
+        ui_tx: Sender<UiCommand>,
+        ui_rx: Receiver<UiCommand>,
+        thread_pool: Arc<ThreadPool>,
+        open_database: impl FnOnce(&mut Self),
+    ) {
+        self.ui_tx = Some(ui_tx);
+        self.ui_rx = Some(ui_rx);
+        self.thread_pool = Some(thread_pool);
+        open_database(self);
+        self.sync_library_watcher();
+    }
+
+    pub fn open_library_database(&mut self) {
+        match LibraryDatabase::default_path() {
+            Some(path) => self.open_library_database_at(&path),
+            None => tracing::error!("Couldn't find where to keep the library database"),
+        }
+    }
+
+    pub fn open_library_database_at(&mut self, path: &Path) {
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+
+        match LibraryDatabase::open(path).and_then(|db| self.library.open_database(db)) {
+            Ok(_) => tracing::info!("Opened the library database {:?}", path),
+            Err(err) => tracing::error!("Failed to open the library database: {}", err),
+        }
+
+        self.relink_playlist_tracks();
+    }
+
     pub fn save_state(&self) {
         let store_result = confy::store("music_player", None, &self);
         match store_result {
@@ -188,6 +359,552 @@ impl App {
         self.quit = true;
     }
 
+    pub fn next_track(&mut self) {
+        self.with_playing_playlist(|player, playlist| player.next(playlist));
+    }
+
+    // Moves on from a track the audio thread couldn't play. Playback stops once as many tracks
+    // failed in a row as there are to play, so a playlist of missing files isn't cycled forever.
+    pub fn skip_failed_track(&mut self) {
+        let Some(player) = self.player.as_mut() else {
+            return;
+        };
+        player.track_state = player::TrackState::Stopped;
+
+        let playable = self
+            .playing_playlist_idx
+            .and_then(|idx| self.playlists.get(idx))
+            .map_or(0, |playlist| playlist.tracks.len())
+            + player.queue.len();
+        self.failed_tracks += 1;
+        if self.failed_tracks < playable {
+            self.next_track();
+        } else {
+            tracing::warn!(
+                "Stopping after {} tracks failed to play",
+                self.failed_tracks
+            );
+            self.failed_tracks = 0;
+        }
+    }
+
+    pub fn previous_track(&mut self) {
+        self.with_playing_playlist(|player, playlist| player.previous(playlist));
+    }
+
+    // Lets the audio thread preload whatever plays after the selected track, so it can continue
+    // into it without a gap.
+    fn queue_next_track(&mut self) {
+        self.with_playing_playlist(|player, playlist| {
+            if player.track_state == player::TrackState::Playing {
+                player.queue_next(playlist);
+            }
+        });
+    }
+
+    // The queue still plays when no playlist is, so the player gets an empty one instead.
+    fn with_playing_playlist(&mut self, f: impl FnOnce(&mut Player, &Playlist)) {
+        let empty = Playlist::new();
+        let playlist = self
+            .playing_playlist_idx
+            .and_then(|idx| self.playlists.get(idx))
+            .unwrap_or(&empty);
+
+        if let Some(player) = self.player.as_mut() {
+            f(player, playlist);
+        }
+    }
+
+    // Replaces a library item everywhere a copy of it is kept.
+    pub fn update_library_items(&mut self, items: Vec<LibraryItem>) {
+        self.library.update_items(&items);
+
+        for item in &items {
+            for playlist in self.playlists.iter_mut() {
+                playlist.update_track(item);
+            }
+
+            if let Some(player) = self.player.as_mut() {
+                player.update_selected_track(item);
+            }
+
+            self.album_art.forget(&item.path());
+        }
+    }
+
+    // Spawns a background thread that writes the edited tags to the files, and sends back the
+    // items of the files that were written.
+    pub fn write_tags(&self, items: Vec<LibraryItem>) {
+        if items.is_empty() {
+            return;
+        }
+
+        let cmd_tx = self.ui_tx.as_ref().unwrap().clone();
+
+        std::thread::spawn(move || {
+            let written = items
+                .into_iter()
+                .filter_map(|mut item| {
+                    let path = item.path();
+
+                    match media::write_tags(&path, &item) {
+                        // The new modification time keeps rescans from reading the file again.
+                        Ok(()) => Some(item.set_file_stamp(FileStamp::read(&path))),
+                        Err(err) => {
+                            tracing::warn!("Couldn't write tags to {:?}: {}", path, err);
+                            None
+                        }
+                    }
+                })
+                .collect::<Vec<LibraryItem>>();
+
+            tracing::info!("Wrote tags to {} files", written.len());
+
+            cmd_tx
+                .send(UiCommand::TagsWritten(written))
+                .expect("Failed to send the items with written tags");
+        });
+    }
+
+    // Spawns a background thread that measures the loudness of each album with tracks missing
+    // ReplayGain, or every album when rescanning, and sends the results back one album at a time.
+    pub fn scan_loudness(&self, rescan: bool) {
+        let containers = self
+            .library
+            .albums()
+            .into_iter()
+            .filter(|container| {
+                rescan
+                    || container.items.iter().any(|item| {
+                        let replay_gain = item.replay_gain();
+                        replay_gain.track_gain.is_none()
+                            || (item.album().is_some() && replay_gain.album_gain.is_none())
+                    })
+            })
+            .collect::<Vec<LibraryItemContainer>>();
+
+        let total = containers
+            .iter()
+            .map(|container| container.items.len())
+            .sum::<usize>();
+
+        if total == 0 {
+            tracing::info!("Every track already has ReplayGain");
+            return;
+        }
+
+        let cmd_tx = self.ui_tx.as_ref().unwrap().clone();
+        let write_tags = self.write_replay_gain_tags;
+
+        if let Some(thread_pool) = &self.thread_pool {
+            let thread_pool = thread_pool.clone();
+
+            std::thread::spawn(move || {
+                let tx = Mutex::new(cmd_tx);
+                let scanned = AtomicUsize::new(0);
+
+                thread_pool.install(|| {
+                    containers.par_iter().for_each(|container| {
+                        let (items, tracks): (Vec<LibraryItem>, Vec<loudness::TrackLoudness>) =
+                            container
+                                .items
+                                .par_iter()
+                                .filter_map(|item| {
+                                    let track = loudness::scan_track(&item.path());
+                                    let scanned = scanned.fetch_add(1, Ordering::Relaxed) + 1;
+
+                                    tx.lock()
+                                        .unwrap()
+                                        .send(UiCommand::LoudnessScanProgress { scanned, total })
+                                        .expect("Failed to send loudness scan progress");
+
+                                    track.map(|track| (item.clone(), track))
+                                })
+                                .unzip();
+
+                        let gains = loudness::album_replay_gain(&tracks);
+                        let items = items
+                            .into_iter()
+                            .zip(gains)
+                            .map(|(mut item, mut replay_gain)| {
+                                // Tracks without an album are grouped together, but aren't one.
+                                if item.album().is_none() {
+                                    replay_gain.album_gain = None;
+                                    replay_gain.album_peak = None;
+                                }
+
+                                if write_tags && media::supports_tag_writing(&item.path()) {
+                                    if let Err(err) =
+                                        media::write_replay_gain(&item.path(), &replay_gain)
+                                    {
+                                        tracing::warn!(
+                                            "Couldn't write ReplayGain tags to {:?}: {}",
+                                            item.path(),
+                                            err
+                                        );
+                                    }
+                                }
+
+                                item.set_replay_gain(replay_gain)
+                            })
+                            .collect::<Vec<LibraryItem>>();
+
+                        tx.lock()
+                            .unwrap()
+                            .send(UiCommand::LibraryUpdateItems(items))
+                            .expect("Failed to send scanned library items");
+                    });
+                });
+
+                tracing::info!("Completed loudness scan");
+            });
+        }
+    }
+
+    // Spawns a background thread that compares the files of an imported library path to the
+    // library items from it. New files are added, files with a different modification time or
+    // size are read again and files that are gone are removed. Items keep their key, so
+    // playlists holding them still refer to the same tracks.
+    pub fn rescan_library_path(&self, lib_path: &LibraryPath) {
+        if lib_path.status() != LibraryPathStatus::Imported {
+            return;
+        }
+
+        tracing::info!("rescanning library path {:?}...", lib_path.path());
+
+        let cmd_tx = self.ui_tx.as_ref().unwrap().clone();
+        let path = lib_path.path().clone();
+        let path_id = lib_path.id();
+        let mut known_items = self
+            .library
+            .items()
+            .iter()
+            .filter(|item| item.library_id() == path_id)
+            .map(|item| (item.path(), item.clone()))
+            .collect::<std::collections::HashMap<PathBuf, LibraryItem>>();
+
+        if let Some(thread_pool) = &self.thread_pool {
+            let thread_pool = thread_pool.clone();
+
+            std::thread::spawn(move || {
+                let files = walkdir::WalkDir::new(path)
+                    .into_iter()
+                    .filter_map(|e| e.ok())
+                    .skip(1)
+                    .filter(|entry| entry.file_type().is_file())
+                    .map(|entry| entry.into_path())
+                    .collect::<Vec<PathBuf>>();
+
+                let changes = thread_pool.install(|| {
+                    files
+                        .par_iter()
+                        .filter_map(|file| {
+                            let known_item = known_items.get(file);
+                            let file_stamp = FileStamp::read(file);
+
+                            if let Some(known_item) = known_item {
+                                if file_stamp.is_some() && known_item.file_stamp() == file_stamp {
+                                    return None;
+                                }
+                            }
+
+                            let item = media::read_library_item(file, path_id);
+                            Some((known_item.cloned(), item))
+                        })
+                        .collect::<Vec<(Option<LibraryItem>, Option<LibraryItem>)>>()
+                });
+
+                let mut added = Vec::new();
+                let mut updated = Vec::new();
+                let mut removed = Vec::new();
+
+                for (known_item, item) in changes {
+                    match (known_item, item) {
+                        (None, Some(item)) => added.push(item),
+                        (Some(known_item), Some(mut item)) => {
+                            updated.push(item.replacing(&known_item));
+                        }
+                        (Some(known_item), None) => removed.push(known_item.key()),
+                        (None, None) => (),
+                    }
+                }
+
+                for file in &files {
+                    known_items.remove(file);
+                }
+                removed.extend(known_items.values().map(|item| item.key()));
+
+                tracing::info!(
+                    "Completed rescanning library path: {} added, {} updated, {} removed",
+                    added.len(),
+                    updated.len(),
+                    removed.len()
+                );
+
+                cmd_tx
+                    .send(UiCommand::LibraryRescanned {
+                        added,
+                        updated,
+                        removed,
+                    })
+                    .expect("Failed to send rescanned library items");
+            });
+        }
+    }
+
+    // Fills in the playlist tracks from the library, since the app state only keeps references to
+    // them. Tracks whose key isn't in the library anymore, ex. because their library path was
+    // removed and added again after the files were moved, go to the item with the same audio,
+    // or else the same path.
+    pub fn relink_playlist_tracks(&mut self) {
+        let items = self.library.items();
+        let by_key = items
+            .iter()
+            .map(|item| (item.key(), item))
+            .collect::<std::collections::HashMap<usize, &LibraryItem>>();
+        let by_fingerprint = items
+            .iter()
+            .filter_map(|item| item.fingerprint().map(|fingerprint| (fingerprint, item)))
+            .collect::<std::collections::HashMap<u64, &LibraryItem>>();
+        let by_path = items
+            .iter()
+            .map(|item| (item.path(), item))
+            .collect::<std::collections::HashMap<PathBuf, &LibraryItem>>();
+
+        let mut outside = std::collections::HashMap::new();
+        for playlist in self.playlists.iter_mut() {
+            let selected = playlist.selected.iter_mut();
+
+            for track in playlist.tracks.iter_mut().chain(selected) {
+                let item = by_key
+                    .get(&track.key())
+                    .or_else(|| track.fingerprint().and_then(|f| by_fingerprint.get(&f)))
+                    .or_else(|| by_path.get(&track.path()));
+
+                match item {
+                    Some(item) => *track = (*item).clone(),
+                    None => _ = outside.insert(track.key(), track.clone()),
+                }
+            }
+        }
+
+        self.read_tracks_outside_library(outside.into_values().collect());
+    }
+
+    // Playlists only keep what identifies tracks that aren't in the library, so their tags are
+    // read on the thread pool and swapped in once they're ready. Missing files keep whatever the
+    // playlist knew about them.
+    fn read_tracks_outside_library(&self, tracks: Vec<LibraryItem>) {
+        let (Some(thread_pool), Some(ui_tx)) = (&self.thread_pool, &self.ui_tx) else {
+            return;
+        };
+
+        if tracks.is_empty() {
+            return;
+        }
+
+        let ui_tx = ui_tx.clone();
+        thread_pool.spawn(move || {
+            let items = tracks
+                .iter()
+                .filter_map(|track| {
+                    let item = media::read_library_item(&track.path(), track.library_id());
+                    if item.is_none() {
+                        tracing::warn!("Keeping missing playlist entry {:?}", track.path());
+                    }
+
+                    item.map(|mut item| item.set_key(track.key()))
+                })
+                .collect::<Vec<LibraryItem>>();
+
+            if !items.is_empty() {
+                _ = ui_tx.send(UiCommand::LibraryUpdateItems(items));
+            }
+        });
+    }
+
+    pub fn apply_library_rescan(
+        &mut self,
+        added: Vec<LibraryItem>,
+        updated: Vec<LibraryItem>,
+        removed: Vec<usize>,
+    ) {
+        self.update_library_items(updated);
+        self.library.add_items(added);
+        self.library.remove_items(&removed);
+
+        // Changed tags can move items to other containers.
+        self.library.regroup();
+    }
+
+    // Starts or stops watching the library paths to match the preference, and watches paths
+    // once they are imported. Called whenever the preference or the imported paths change.
+    pub fn sync_library_watcher(&mut self) {
+        if !self.watch_library {
+            self.library_watcher = None;
+            return;
+        }
+
+        if self.library_watcher.is_none() {
+            let Some(ui_tx) = self.ui_tx.clone() else {
+                return;
+            };
+
+            match LibraryWatcher::new(ui_tx) {
+                Ok(library_watcher) => self.library_watcher = Some(library_watcher),
+                Err(err) => {
+                    tracing::error!("Failed to start watching the library: {}", err);
+                    self.watch_library = false;
+                    return;
+                }
+            }
+        }
+
+        let imported_paths = self
+            .library
+            .paths()
+            .iter()
+            .filter(|p| p.status() == LibraryPathStatus::Imported)
+            .cloned()
+            .collect::<Vec<LibraryPath>>();
+
+        if let Some(library_watcher) = self.library_watcher.as_mut() {
+            library_watcher.sync(&imported_paths);
+        }
+    }
+
+    // Files in a watched library path were created or changed.
+    pub fn library_files_changed(&mut self, items: Vec<LibraryItem>) {
+        let mut added = Vec::new();
+        let mut updated = Vec::new();
+
+        for mut item in items {
+            let path = item.path();
+
+            match self
+                .library
+                .items()
+                .iter()
+                .find(|known| known.path() == path)
+            {
+                Some(known) if known.file_stamp() == item.file_stamp() => (),
+                Some(known) => updated.push(item.replacing(known)),
+                None => added.push(item),
+            }
+        }
+
+        self.apply_library_rescan(added, updated, Vec::new());
+    }
+
+    // Moves the items of a renamed file or folder, keeping their keys.
+    pub fn library_file_renamed(&mut self, from: PathBuf, to: PathBuf) {
+        let Some(path_id) = self.library.path_id_of(&to) else {
+            self.library_files_removed(vec![from]);
+            return;
+        };
+
+        let renamed = self
+            .library
+            .items_under(&from)
+            .into_iter()
+            .map(|item| {
+                let path = match item.path().strip_prefix(&from) {
+                    Ok(rest) if !rest.as_os_str().is_empty() => to.join(rest),
+                    _ => to.clone(),
+                };
+
+                item.clone().set_path(path, path_id)
+            })
+            .collect::<Vec<LibraryItem>>();
+
+        // Files are often written under a temporary name and then renamed to their real one.
+        if renamed.is_empty() {
+            if let Some(item) = media::read_library_item(&to, path_id) {
+                self.library_files_changed(vec![item]);
+            }
+            return;
+        }
+
+        self.apply_library_rescan(Vec::new(), renamed, Vec::new());
+    }
+
+    pub fn library_files_removed(&mut self, paths: Vec<PathBuf>) {
+        let keys = paths
+            .iter()
+            .flat_map(|path| self.library.items_under(path))
+            .map(|item| item.key())
+            .collect::<Vec<usize>>();
+
+        self.apply_library_rescan(Vec::new(), Vec::new(), keys);
+    }
+
+    // Picks the tracks of the smart playlists again after the library changed.
+    pub fn refresh_smart_playlists(&mut self) {
+        let now = library::unix_now();
+        for playlist in self.playlists.iter_mut() {
+            playlist.refresh(&self.library, now);
+        }
+    }
+
+    // Counts a play of the selected track, which just finished.
+    fn record_play(&mut self) {
+        let Some(key) = self
+            .player
+            .as_ref()
+            .and_then(|player| player.selected_track.as_ref())
+            .map(|track| track.key())
+        else {
+            return;
+        };
+
+        // Tracks outside the library, ex. missing files of a playlist file, aren't counted.
+        let Some(item) = self.library.items().iter().find(|item| item.key() == key) else {
+            return;
+        };
+
+        let played = item.clone().played(library::unix_now());
+        self.update_library_items(vec![played]);
+    }
+
+    // Opens a playlist file as a new playlist and makes it the current one.
+    pub fn load_playlist(&mut self, path: &std::path::Path) {
+        match playlist_file::load(path, self.library.items()) {
+            Ok(playlist) => {
+                tracing::info!("Loaded {} tracks from {:?}", playlist.tracks.len(), path);
+                let keys = self
+                    .library
+                    .items()
+                    .iter()
+                    .map(|item| item.key())
+                    .collect::<std::collections::HashSet<usize>>();
+                let outside = playlist
+                    .tracks
+                    .iter()
+                    .filter(|track| !keys.contains(&track.key()))
+                    .cloned()
+                    .collect();
+
+                self.create_playlist(playlist);
+                self.read_tracks_outside_library(outside);
+            }
+            Err(err) => tracing::error!("Failed to load the playlist {:?}: {}", path, err),
+        }
+    }
+
+    pub fn save_playlist(&self, path: &std::path::Path) {
+        let Some(playlist) = self
+            .current_playlist_idx
+            .and_then(|idx| self.playlists.get(idx))
+        else {
+            return;
+        };
+
+        match playlist_file::save(playlist, path) {
+            Ok(_) => tracing::info!("Saved the playlist to {:?}", path),
+            Err(err) => tracing::error!("Failed to save the playlist {:?}: {}", path, err),
+        }
+    }
+
     // Spawns a background thread and imports files
     // from each unimported library path
     // TODO - Time and profile this thread
@@ -200,72 +917,50 @@ impl App {
         tracing::info!("adding library path...");
 
         let cmd_tx = self.ui_tx.as_ref().unwrap().clone();
+        let lib_path = lib_path.clone();
         let path = lib_path.path().clone();
         let path_id = lib_path.id().clone();
+        let view_type = self.library.view().view_type.clone();
 
         if let Some(thread_pool) = &self.thread_pool {
             let thread_pool = thread_pool.clone();
 
             std::thread::spawn(move || {
-                let tx = Mutex::new(cmd_tx.clone());
+                // Items are sent in batches, so the library stores them in a few transactions
+                // instead of one per file.
+                let batch = Mutex::new(Vec::with_capacity(IMPORT_BATCH_LEN));
+                let send_batch = |items: Vec<LibraryItem>| {
+                    if !items.is_empty() {
+                        cmd_tx
+                            .send(UiCommand::LibraryAddItems(items))
+                            .expect("Failed to send Library items");
+                    }
+                };
 
                 let items: Vec<LibraryItem> = thread_pool.install(|| {
                     walkdir::WalkDir::new(path)
                         .into_iter()
                         .filter_map(|e| e.ok())
                         .skip(1)
-                        .filter(|entry| {
-                            entry.file_type().is_file()
-                                && entry.path().extension().unwrap_or(std::ffi::OsStr::new("")) == "mp3"
-                        })
+                        .filter(|entry| entry.file_type().is_file())
                         .par_bridge()
-                        .map(|entry| {
-                            let tag = Tag::read_from_path(&entry.path());
-
-                            let library_item = match tag {
-                                Ok(tag) => LibraryItem::new(entry.path().to_path_buf(), path_id)
-                                    .set_title(tag.title())
-                                    .set_artist(tag.artist())
-                                    .set_album(tag.album())
-                                    .set_year(tag.year())
-                                    .set_genre(tag.genre())
-                                    .set_track_number(tag.track()),
-                                Err(_err) => {
-                                    // tracing::warn!("Couldn't parse to id3: {:?}", &entry.path());
-                                    LibraryItem::new(entry.path().to_path_buf(), path_id)
-                                }
-                            };
+                        .filter_map(|entry| media::read_library_item(entry.path(), path_id))
+                        .inspect(|item| {
+                            let mut batch = batch.lock().unwrap();
+                            batch.push(item.clone());
 
-                            return library_item;
+                            if batch.len() == IMPORT_BATCH_LEN {
+                                send_batch(std::mem::take(&mut *batch));
+                            }
                         })
-                        .inspect(|item| {
-                            tx
-                                .lock()
-                                .unwrap()
-                                .send(UiCommand::LibraryAddItem(item.clone()))
-                                .expect("Failed to send Library items");
-                         })
                         .collect::<Vec<LibraryItem>>()
                 });
+                send_batch(batch.into_inner().unwrap());
 
                 tracing::info!("Completed adding path to library");
 
-                let mut grouped: BTreeMap<String, Vec<&LibraryItem>> = BTreeMap::new();
-                for item in &items {
-                    let key = item.album().unwrap_or_else(|| "<?>".to_string());
-                    grouped.entry(key).or_default().push(item);
-                }
-
-                let library_view = LibraryView {
-                    view_type: ViewType::Album,
-                    containers: grouped
-                        .into_iter()
-                        .map(|(name, items)| LibraryItemContainer {
-                            name,
-                            items: items.into_iter().cloned().collect(),
-                        })
-                        .collect(),
-                };
+                let library_view =
+                    LibraryView::new(view_type, &items, std::slice::from_ref(&lib_path));
 
                 cmd_tx
                     .send(UiCommand::LibraryAddView(library_view))
@@ -281,3 +976,285 @@ impl App {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::wav::TestDir;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn rescan_adds_updates_and_removes_items() {
+        let dir = TestDir::new("rescan");
+        for name in ["kept.wav", "changed.wav", "deleted.wav"] {
+            dir.write_silence(name, 100);
+        }
+
+        let (ui_tx, ui_rx) = channel();
+        let mut app = App {
+            ui_tx: Some(ui_tx),
+            thread_pool: Some(Arc::new(rayon::ThreadPoolBuilder::new().build().unwrap())),
+            ..Default::default()
+        };
+
+        app.library.add_path(dir.to_path_buf());
+        let lib_path = app.library.paths()[0].clone();
+        app.library.set_path_to_imported(lib_path.id());
+
+        let mut items = vec![];
+        for entry in dir.read_dir().unwrap() {
+            let path = entry.unwrap().path();
+            items.push(media::read_library_item(&path, lib_path.id()).unwrap());
+        }
+        app.library.add_items(items.clone());
+        let key_of = |name: &str| {
+            items
+                .iter()
+                .find(|item| item.path() == dir.join(name))
+                .unwrap()
+                .key()
+        };
+
+        dir.write_silence("changed.wav", 200);
+        std::fs::remove_file(dir.join("deleted.wav")).unwrap();
+        dir.write_silence("added.wav", 100);
+
+        app.rescan_library_path(&app.library.paths()[0].clone());
+        let received = ui_rx.recv_timeout(std::time::Duration::from_secs(10));
+
+        let Ok(UiCommand::LibraryRescanned {
+            added,
+            updated,
+            removed,
+        }) = received
+        else {
+            panic!("expected the rescanned items");
+        };
+
+        assert_eq!(added.len(), 1);
+        assert_eq!(added[0].path(), dir.join("added.wav"));
+        assert_eq!(updated.len(), 1);
+        assert_eq!(updated[0].key(), key_of("changed.wav"));
+        assert_eq!(removed, [key_of("deleted.wav")]);
+
+        app.apply_library_rescan(added, updated, removed);
+
+        let keys = app
+            .library
+            .items()
+            .iter()
+            .map(|item| item.key())
+            .collect::<Vec<usize>>();
+        assert_eq!(keys.len(), 3);
+        assert!(keys.contains(&key_of("kept.wav")) && keys.contains(&key_of("changed.wav")));
+    }
+
+    #[test]
+    fn watched_file_events_keep_item_identity() {
+        let dir = TestDir::new("events");
+        std::fs::create_dir_all(dir.join("album")).unwrap();
+        dir.write_silence("album/track.wav", 100);
+
+        let mut app = App::default();
+        app.library.add_path(dir.to_path_buf());
+        let path_id = app.library.paths()[0].id();
+
+        let item = media::read_library_item(&dir.join("album/track.wav"), path_id).unwrap();
+        app.library.add_item(item.clone());
+        let mut playlist = Playlist::new();
+        playlist.add(item.clone());
+        app.playlists.push(playlist);
+
+        // Moving the folder moves the item and the playlist follows it.
+        std::fs::rename(dir.join("album"), dir.join("renamed")).unwrap();
+        app.library_file_renamed(dir.join("album"), dir.join("renamed"));
+        let moved_path = dir.join("renamed/track.wav");
+        assert_eq!(app.library.items()[0].key(), item.key());
+        assert_eq!(app.library.items()[0].path(), moved_path);
+        assert_eq!(app.playlists[0].tracks[0].path(), moved_path);
+
+        // Unchanged files are skipped, changed ones are updated in place.
+        let unchanged = media::read_library_item(&moved_path, path_id).unwrap();
+        app.library_files_changed(vec![unchanged]);
+        dir.write_silence("renamed/track.wav", 200);
+        let changed = media::read_library_item(&moved_path, path_id).unwrap();
+        app.library_files_changed(vec![changed.clone()]);
+
+        assert_eq!(app.library.items().len(), 1);
+        assert_eq!(app.library.items()[0].key(), item.key());
+        assert_eq!(app.library.items()[0].file_stamp(), changed.file_stamp());
+
+        app.library_files_removed(vec![dir.join("renamed")]);
+        assert!(app.library.items().is_empty());
+    }
+
+    #[test]
+    fn reads_playlist_tracks_outside_the_library_on_startup() {
+        let dir = TestDir::new("start");
+        dir.write_silence("outside.wav", 100);
+
+        // A saved playlist only keeps a reference to the track.
+        let mut playlist = Playlist::new();
+        playlist.add(LibraryItem::new(
+            dir.join("outside.wav"),
+            LibraryPathId::from_path(&dir),
+        ));
+        let mut app = App {
+            playlists: vec![playlist],
+            ..Default::default()
+        };
+
+        // The same way `main` starts the app.
+        let (ui_tx, ui_rx) = channel();
+        let database_path = dir.join("library.sqlite3");
+        app.start(
+            ui_tx,
+            ui_rx,
+            Arc::new(rayon::ThreadPoolBuilder::new().build().unwrap()),
+            |app| app.open_library_database_at(&database_path),
+        );
+
+        let cmd = app
+            .ui_rx
+            .as_ref()
+            .unwrap()
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .unwrap();
+        let UiCommand::LibraryUpdateItems(items) = cmd else {
+            panic!("expected the read tracks");
+        };
+        app.update_library_items(items);
+
+        assert!(app.playlists[0].tracks[0].file_stamp().is_some());
+    }
+
+    #[test]
+    fn reads_playlist_tracks_outside_the_library_in_the_background() {
+        let dir = TestDir::new("m3u");
+        dir.write_silence("outside.wav", 100);
+        std::fs::write(dir.join("mix.m3u"), "outside.wav\ngone.wav\n").unwrap();
+
+        let (ui_tx, ui_rx) = channel();
+        let mut app = App {
+            ui_tx: Some(ui_tx),
+            thread_pool: Some(Arc::new(rayon::ThreadPoolBuilder::new().build().unwrap())),
+            ..Default::default()
+        };
+
+        app.load_playlist(&dir.join("mix.m3u"));
+        assert_eq!(app.playlists[0].tracks.len(), 2);
+        assert_eq!(app.playlists[0].tracks[0].file_stamp(), None);
+
+        let cmd = ui_rx
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .unwrap();
+        let UiCommand::LibraryUpdateItems(items) = cmd else {
+            panic!("expected the read tracks");
+        };
+        assert_eq!(items.len(), 1);
+        app.update_library_items(items);
+
+        assert!(app.playlists[0].tracks[0].file_stamp().is_some());
+        assert_eq!(app.playlists[0].tracks[1].path(), dir.join("gone.wav"));
+    }
+
+    #[test]
+    fn skips_failed_tracks_until_every_track_failed() {
+        let (audio_tx, _audio_rx) = channel();
+        let mut app = App {
+            player: Some(Player::new(
+                audio_tx,
+                Arc::new(std::sync::atomic::AtomicU32::new(0)),
+            )),
+            ..Default::default()
+        };
+
+        let library_id = LibraryPathId::from_path(Path::new("/music"));
+        let mut playlist = Playlist::new();
+        for name in ["first.flac", "second.flac"] {
+            playlist.add(LibraryItem::new(Path::new("/music").join(name), library_id));
+        }
+        let first = playlist.tracks[0].clone();
+        app.playlists.push(playlist);
+        app.playing_playlist_idx = Some(0);
+        app.player.as_mut().unwrap().select_track(Some(first));
+
+        app.skip_failed_track();
+        let player = app.player.as_ref().unwrap();
+        assert_eq!(
+            player.selected_track,
+            Some(app.playlists[0].tracks[1].clone())
+        );
+        assert!(player.track_state == player::TrackState::Playing);
+
+        app.skip_failed_track();
+        let player = app.player.as_ref().unwrap();
+        assert_eq!(
+            player.selected_track,
+            Some(app.playlists[0].tracks[1].clone())
+        );
+        assert!(player.track_state == player::TrackState::Stopped);
+        assert_eq!(app.failed_tracks, 0);
+    }
+
+    #[test]
+    fn playlists_keep_references_to_library_tracks() {
+        let mut app = App::default();
+        let library_id = LibraryPathId::from_path(Path::new("/music"));
+        let track = LibraryItem::new(PathBuf::from("/music/trust.flac"), library_id)
+            .set_title(Some("Trust"));
+        app.library.add_items(vec![track.clone()]);
+
+        let mut playlist = Playlist::new();
+        playlist.add(track.clone());
+        app.playlists.push(playlist);
+
+        let saved = serde_json::to_value(&app.playlists).unwrap();
+        assert_eq!(
+            saved[0]["tracks"][0],
+            serde_json::json!({
+                "key": track.key(),
+                "library_id": library_id,
+                "path": "/music/trust.flac",
+                "fingerprint": null,
+            })
+        );
+
+        // The rest of the track comes from the library on startup.
+        app.playlists = serde_json::from_value(saved).unwrap();
+        assert_eq!(app.playlists[0].tracks[0].title(), None);
+        app.relink_playlist_tracks();
+        assert_eq!(app.playlists[0].tracks[0].title().as_deref(), Some("Trust"));
+    }
+
+    #[test]
+    fn playlists_follow_moved_tracks_across_a_reimport() {
+        let dir = TestDir::new("relink");
+        std::fs::create_dir_all(dir.join("old")).unwrap();
+        std::fs::create_dir_all(dir.join("new")).unwrap();
+        dir.write_silence("old/track.wav", 100);
+        dir.write_silence("old/other.wav", 200);
+
+        let mut app = App::default();
+        let path_id = LibraryPathId::from_path(&dir);
+        let track = media::read_library_item(&dir.join("old/track.wav"), path_id).unwrap();
+        let other = media::read_library_item(&dir.join("old/other.wav"), path_id).unwrap();
+        assert_ne!(track.fingerprint(), other.fingerprint());
+
+        let mut playlist = Playlist::new();
+        playlist.add(track.clone());
+        app.playlists.push(playlist);
+
+        // The file is moved while its library path isn't in the library, then imported again.
+        std::fs::rename(dir.join("old/track.wav"), dir.join("new/track.wav")).unwrap();
+        let moved = media::read_library_item(&dir.join("new/track.wav"), path_id).unwrap();
+
+        app.library.add_items(vec![moved.clone(), other]);
+        app.relink_playlist_tracks();
+
+        assert_eq!(moved.fingerprint(), track.fingerprint());
+        assert_ne!(moved.key(), track.key());
+        assert_eq!(app.playlists[0].tracks[0].key(), moved.key());
+        assert_eq!(app.playlists[0].get_pos(&moved), Some(0));
+    }
+}
@@ -1,6 +1,9 @@
 use crate::app::library::LibraryItem;
 use crate::app::playlist::Playlist;
+use crate::app::replay_gain::ReplayGainSettings;
 use crate::AudioCommand;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
 use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::mpsc::Sender;
 use std::sync::Arc;
@@ -8,6 +11,19 @@ use std::sync::Arc;
 pub struct Player {
     pub track_state: TrackState,
     pub selected_track: Option<LibraryItem>,
+    pub queued_track: Option<LibraryItem>,
+    // Tracks the user asked to hear next. They play before anything from the playlist.
+    pub queue: Vec<LibraryItem>,
+    // The playlist track the queue interrupted. Playback continues after it once the queue is
+    // empty.
+    resume_track: Option<LibraryItem>,
+    pub playback_mode: PlaybackMode,
+    // Tracks played before the selected one, most recent last.
+    history: Vec<LibraryItem>,
+    // Keys of the playlist's tracks in the order the shuffle modes play them.
+    shuffle_order: Vec<usize>,
+    // Key of the track random mode plays next, picked ahead of time so it can be preloaded.
+    random_pick: Option<usize>,
     pub audio_tx: Sender<AudioCommand>,
     pub volume: f32,
     pub seek_to_timestamp: u64,
@@ -21,6 +37,13 @@ impl Player {
         Self {
             track_state: TrackState::Unstarted,
             selected_track: None,
+            queued_track: None,
+            queue: Vec::new(),
+            resume_track: None,
+            playback_mode: PlaybackMode::Default,
+            history: Vec::new(),
+            shuffle_order: Vec::new(),
+            random_pick: None,
             audio_tx: audio_cmd_tx,
             volume: 1.0,
             seek_to_timestamp: 0, // TODO: This should have subsecond precision, but is okay for now.
@@ -31,11 +54,20 @@ impl Player {
     }
 
     pub fn select_track(&mut self, track: Option<LibraryItem>) {
+        self.resume_track = None;
+        self.remember_selected_track();
+        self.load_track(track);
+    }
+
+    fn load_track(&mut self, track: Option<LibraryItem>) {
         self.selected_track = track;
+        self.random_pick = None;
+        // Loading a file drops whatever the audio thread had preloaded.
+        self.queued_track = None;
 
         if let Some(track) = &self.selected_track {
             self.audio_tx
-                .send(AudioCommand::LoadFile(track.path()))
+                .send(AudioCommand::LoadFile(track.path(), track.replay_gain()))
                 .expect("Failed to send select to audio thread");
         }
     }
@@ -107,26 +139,237 @@ impl Player {
         }
     }
 
+    pub fn set_playback_mode(&mut self, playback_mode: PlaybackMode) {
+        self.playback_mode = playback_mode;
+        self.shuffle_order.clear();
+        self.random_pick = None;
+    }
+
+    fn remember_selected_track(&mut self) {
+        if let Some(selected_track) = self.selected_track.clone() {
+            if self.history.len() == MAX_HISTORY_LEN {
+                self.history.remove(0);
+            }
+
+            self.history.push(selected_track);
+        }
+    }
+
     pub fn previous(&mut self, playlist: &Playlist) {
+        // The shuffled modes go back through what was actually played.
+        if self.playback_mode.is_shuffled() {
+            if let Some(previous_track) = self.history.pop() {
+                self.load_track(Some(previous_track));
+                self.play();
+            }
+
+            return;
+        }
+
         if let Some(selected_track) = &self.selected_track {
             if let Some(current_track_position) = playlist.get_pos(&selected_track) {
-                if current_track_position > 0 {
-                    let previous_track = &playlist.tracks[current_track_position - 1];
-                    self.select_track(Some((*previous_track).clone()));
-                    self.play();
-                }
+                let previous_position = match current_track_position {
+                    0 if self.playback_mode == PlaybackMode::RepeatPlaylist => {
+                        playlist.tracks.len() - 1
+                    }
+                    0 => return,
+                    position => position - 1,
+                };
+
+                let previous_track = &playlist.tracks[previous_position];
+                self.select_track(Some((*previous_track).clone()));
+                self.play();
             }
         }
     }
 
     pub fn next(&mut self, playlist: &Playlist) {
-        if let Some(selected_track) = &self.selected_track {
-            if let Some(current_track_position) = playlist.get_pos(&selected_track) {
-                if current_track_position < playlist.tracks.len() - 1 {
-                    let next_track = &playlist.tracks[current_track_position + 1];
-                    self.select_track(Some((*next_track).clone()));
-                    self.play();
+        if let Some(next_track) = self.next_track(playlist) {
+            self.take_from_queue(&next_track);
+            self.remember_selected_track();
+            self.load_track(Some(next_track));
+            self.play();
+        }
+    }
+
+    // The track after the selected one in the current playback mode. Anything random is picked
+    // once and remembered, so the track that gets preloaded is the one that plays.
+    fn next_track(&mut self, playlist: &Playlist) -> Option<LibraryItem> {
+        if let Some(queued) = self.queue.first() {
+            return Some(queued.clone());
+        }
+
+        let selected_track = self
+            .resume_track
+            .clone()
+            .or_else(|| self.selected_track.clone())?;
+
+        match self.playback_mode {
+            PlaybackMode::Default => {
+                let current_track_position = playlist.get_pos(&selected_track)?;
+                playlist.tracks.get(current_track_position + 1).cloned()
+            }
+            PlaybackMode::RepeatPlaylist => {
+                let current_track_position = playlist.get_pos(&selected_track)?;
+                let next_position = (current_track_position + 1) % playlist.tracks.len();
+                playlist.tracks.get(next_position).cloned()
+            }
+            PlaybackMode::RepeatTrack => Some(selected_track),
+            PlaybackMode::ShuffleTracks | PlaybackMode::ShuffleAlbums => {
+                let selected_key = selected_track.key();
+                let is_order_stale = self.shuffle_order.len() != playlist.tracks.len()
+                    || !self.shuffle_order.contains(&selected_key);
+
+                if is_order_stale {
+                    self.shuffle_order = shuffle_order(playlist, selected_key, self.playback_mode);
+                }
+
+                let position = self
+                    .shuffle_order
+                    .iter()
+                    .position(|key| *key == selected_key)?;
+                let next_key = self.shuffle_order[(position + 1) % self.shuffle_order.len()];
+
+                playlist
+                    .tracks
+                    .iter()
+                    .find(|t| t.key() == next_key)
+                    .cloned()
+            }
+            PlaybackMode::Random => {
+                let pick = self
+                    .random_pick
+                    .and_then(|key| playlist.tracks.iter().find(|t| t.key() == key))
+                    .or_else(|| playlist.tracks.choose(&mut rand::thread_rng()))
+                    .cloned()?;
+
+                self.random_pick = Some(pick.key());
+                Some(pick)
+            }
+        }
+    }
+
+    /// Lets the audio thread open the track after the selected one ahead of time, so it can play
+    /// into it without a gap. Only sends a command when the upcoming track or its ReplayGain
+    /// changes.
+    pub fn queue_next(&mut self, playlist: &Playlist) {
+        let next_track = self.next_track(playlist);
+        let replay_gain = |track: &Option<LibraryItem>| track.as_ref().map(|t| t.replay_gain());
+
+        if next_track != self.queued_track
+            || replay_gain(&next_track) != replay_gain(&self.queued_track)
+        {
+            // Consecutive tracks of an album are often meant to flow into each other, so they
+            // are never crossfaded.
+            let crossfade = match (&self.selected_track, &next_track) {
+                (Some(selected), Some(next)) => {
+                    selected.album().is_none() || selected.album() != next.album()
                 }
+                _ => false,
+            };
+
+            self.audio_tx
+                .send(AudioCommand::QueueNext {
+                    path: next_track.as_ref().map(|t| t.path()),
+                    crossfade,
+                    replay_gain: next_track
+                        .as_ref()
+                        .map(|t| t.replay_gain())
+                        .unwrap_or_default(),
+                })
+                .expect("Failed to send queue next to audio thread");
+            self.queued_track = next_track;
+        }
+    }
+
+    pub fn set_crossfade(&self, millis: u32) {
+        self.audio_tx
+            .send(AudioCommand::SetCrossfade(millis))
+            .expect("Failed to send crossfade to audio thread");
+    }
+
+    pub fn set_replay_gain(&self, settings: ReplayGainSettings) {
+        self.audio_tx
+            .send(AudioCommand::SetReplayGain(settings))
+            .expect("Failed to send replay gain to audio thread");
+    }
+
+    pub fn play_next(&mut self, track: LibraryItem) {
+        self.queue.insert(0, track);
+    }
+
+    pub fn add_to_queue(&mut self, track: LibraryItem) {
+        self.queue.push(track);
+    }
+
+    pub fn move_in_queue(&mut self, current_pos: usize, destination_pos: usize) {
+        if current_pos < self.queue.len() && destination_pos < self.queue.len() {
+            let track = self.queue.remove(current_pos);
+            self.queue.insert(destination_pos, track);
+        }
+    }
+
+    pub fn remove_from_queue(&mut self, idx: usize) {
+        if idx < self.queue.len() {
+            self.queue.remove(idx);
+        }
+    }
+
+    pub fn clear_queue(&mut self) {
+        self.queue.clear();
+    }
+
+    // Drops the track from the front of the queue once it starts playing. The first queued
+    // track remembers where the playlist was interrupted, and the next playlist track forgets it.
+    fn take_from_queue(&mut self, track: &LibraryItem) {
+        if self.queue.first().map(|t| t.key()) == Some(track.key()) {
+            self.queue.remove(0);
+
+            if self.resume_track.is_none() {
+                self.resume_track = self.selected_track.clone();
+            }
+        } else {
+            self.resume_track = None;
+        }
+    }
+
+    // The queued track is left alone so `queue_next` notices a changed ReplayGain and sends it
+    // again. The playing track's new gain applies right away.
+    pub fn update_selected_track(&mut self, library_item: &LibraryItem) {
+        if let Some(selected_track) = self.selected_track.as_mut() {
+            if selected_track.key() == library_item.key() {
+                if selected_track.replay_gain() != library_item.replay_gain() {
+                    self.audio_tx
+                        .send(AudioCommand::SetTrackReplayGain(library_item.replay_gain()))
+                        .expect("Failed to send track replay gain to audio thread");
+                }
+
+                *selected_track = library_item.clone();
+            }
+        }
+
+        for track in self.queue.iter_mut().chain(self.resume_track.as_mut()) {
+            if track.key() == library_item.key() {
+                *track = library_item.clone();
+            }
+        }
+    }
+
+    /// The audio thread finished the selected track and moved on to the queued one by itself.
+    pub fn advance_to_queued(&mut self, path: &std::path::Path) {
+        match self.queued_track.take() {
+            Some(queued_track) if queued_track.path() == path => {
+                self.take_from_queue(&queued_track);
+                self.remember_selected_track();
+                self.selected_track = Some(queued_track);
+                self.random_pick = None;
+            }
+            queued_track => {
+                tracing::warn!(
+                    "Audio thread advanced to a track that wasn't queued: {:?}",
+                    path
+                );
+                self.queued_track = queued_track;
             }
         }
     }
@@ -157,6 +400,84 @@ impl Player {
     }
 }
 
+// Starts at the selected track, so every other track plays once before it comes around again.
+fn shuffle_order(playlist: &Playlist, first_key: usize, playback_mode: PlaybackMode) -> Vec<usize> {
+    let mut rng = rand::thread_rng();
+
+    let mut order = if playback_mode == PlaybackMode::ShuffleAlbums {
+        // Albums play in a random order, but the tracks of each album keep the playlist order.
+        let mut albums: Vec<(Option<String>, Vec<usize>)> = Vec::new();
+        for track in &playlist.tracks {
+            match albums.iter_mut().find(|(album, _)| *album == track.album()) {
+                Some((_, keys)) => keys.push(track.key()),
+                None => albums.push((track.album(), vec![track.key()])),
+            }
+        }
+
+        albums.shuffle(&mut rng);
+        albums.into_iter().flat_map(|(_, keys)| keys).collect()
+    } else {
+        let mut keys = playlist
+            .tracks
+            .iter()
+            .map(|t| t.key())
+            .collect::<Vec<usize>>();
+        keys.shuffle(&mut rng);
+        keys
+    };
+
+    // Rotate the order rather than moving the selected track, so its album stays together.
+    if let Some(position) = order.iter().position(|key| *key == first_key) {
+        order.rotate_left(position);
+    }
+
+    order
+}
+
+const MAX_HISTORY_LEN: usize = 1000;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlaybackMode {
+    #[default]
+    Default,
+    RepeatPlaylist,
+    RepeatTrack,
+    ShuffleTracks,
+    ShuffleAlbums,
+    Random,
+}
+
+impl PlaybackMode {
+    pub const ALL: [PlaybackMode; 6] = [
+        PlaybackMode::Default,
+        PlaybackMode::RepeatPlaylist,
+        PlaybackMode::RepeatTrack,
+        PlaybackMode::ShuffleTracks,
+        PlaybackMode::ShuffleAlbums,
+        PlaybackMode::Random,
+    ];
+
+    fn is_shuffled(&self) -> bool {
+        matches!(
+            self,
+            PlaybackMode::ShuffleTracks | PlaybackMode::ShuffleAlbums | PlaybackMode::Random
+        )
+    }
+}
+
+impl std::fmt::Display for PlaybackMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PlaybackMode::Default => write!(f, "Default"),
+            PlaybackMode::RepeatPlaylist => write!(f, "Repeat (playlist)"),
+            PlaybackMode::RepeatTrack => write!(f, "Repeat (track)"),
+            PlaybackMode::ShuffleTracks => write!(f, "Shuffle (tracks)"),
+            PlaybackMode::ShuffleAlbums => write!(f, "Shuffle (albums)"),
+            PlaybackMode::Random => write!(f, "Random"),
+        }
+    }
+}
+
 #[derive(PartialEq)]
 pub enum TrackState {
     Unstarted,
@@ -175,3 +496,194 @@ impl std::fmt::Display for TrackState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::replay_gain::ReplayGain;
+    use crate::app::LibraryPathId;
+    use std::path::PathBuf;
+    use std::sync::mpsc::channel;
+
+    fn player() -> Player {
+        // Keep the receiver alive, sending to the audio thread fails once it's gone.
+        let (tx, rx) = channel();
+        std::mem::forget(rx);
+        Player::new(tx, Arc::new(AtomicU32::new(0)))
+    }
+
+    fn playlist(albums: &[&str]) -> Playlist {
+        let mut playlist = Playlist::new();
+        for (i, album) in albums.iter().enumerate() {
+            playlist.add(
+                LibraryItem::new(PathBuf::from(format!("{}.mp3", i)), LibraryPathId::new(0))
+                    .set_album(Some(album)),
+            );
+        }
+        playlist
+    }
+
+    fn play_through(player: &mut Player, playlist: &Playlist, count: usize) -> Vec<usize> {
+        (0..count)
+            .map(|_| {
+                player.next(playlist);
+                playlist
+                    .get_pos(player.selected_track.as_ref().unwrap())
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn repeat_modes() {
+        let playlist = playlist(&["a", "a", "a"]);
+        let mut player = player();
+        player.select_track(Some(playlist.tracks[1].clone()));
+
+        player.set_playback_mode(PlaybackMode::RepeatPlaylist);
+        assert_eq!(play_through(&mut player, &playlist, 3), [2, 0, 1]);
+
+        player.set_playback_mode(PlaybackMode::RepeatTrack);
+        assert_eq!(play_through(&mut player, &playlist, 2), [1, 1]);
+
+        player.set_playback_mode(PlaybackMode::Default);
+        assert_eq!(play_through(&mut player, &playlist, 3), [2, 2, 2]);
+    }
+
+    #[test]
+    fn shuffle_plays_every_track_once_per_cycle() {
+        let playlist = playlist(&["a"; 20]);
+        let mut player = player();
+        player.set_playback_mode(PlaybackMode::ShuffleTracks);
+        player.select_track(Some(playlist.tracks[0].clone()));
+
+        let mut played = play_through(&mut player, &playlist, 19);
+        played.sort();
+        assert_eq!(played, (1..20).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn shuffle_albums_keeps_album_tracks_together() {
+        let playlist = playlist(&["a", "a", "b", "b", "b", "c"]);
+        let mut player = player();
+        player.set_playback_mode(PlaybackMode::ShuffleAlbums);
+        player.select_track(Some(playlist.tracks[2].clone()));
+
+        let played = play_through(&mut player, &playlist, 5);
+        assert_eq!(played[..2], [3, 4]);
+        assert!(played[2..] == [5, 0, 1] || played[2..] == [0, 1, 5]);
+    }
+
+    #[test]
+    fn previous_goes_back_through_history_when_shuffled() {
+        let playlist = playlist(&["a"; 10]);
+        let mut player = player();
+        player.set_playback_mode(PlaybackMode::Random);
+        player.select_track(Some(playlist.tracks[0].clone()));
+
+        let played = play_through(&mut player, &playlist, 5);
+
+        for expected in played.iter().rev().skip(1).chain([0].iter()) {
+            player.previous(&playlist);
+            let position = playlist.get_pos(player.selected_track.as_ref().unwrap());
+            assert_eq!(position, Some(*expected));
+        }
+    }
+
+    #[test]
+    fn random_pick_is_the_queued_track() {
+        let playlist = playlist(&["a"; 10]);
+        let mut player = player();
+        player.set_playback_mode(PlaybackMode::Random);
+        player.select_track(Some(playlist.tracks[0].clone()));
+
+        player.queue_next(&playlist);
+        let queued = player.queued_track.clone().unwrap();
+        player.advance_to_queued(&queued.path());
+
+        assert_eq!(player.selected_track, Some(queued));
+    }
+
+    #[test]
+    fn queue_plays_before_the_playlist() {
+        let playlist = playlist(&["a"; 5]);
+        let mut player = player();
+        player.select_track(Some(playlist.tracks[0].clone()));
+
+        player.add_to_queue(playlist.tracks[3].clone());
+        player.add_to_queue(playlist.tracks[2].clone());
+        player.play_next(playlist.tracks[4].clone());
+        player.move_in_queue(2, 1);
+
+        assert_eq!(play_through(&mut player, &playlist, 4), [4, 2, 3, 1]);
+        assert!(player.queue.is_empty());
+    }
+
+    #[test]
+    fn queue_resumes_after_the_interrupted_track() {
+        let playlist = playlist(&["a"; 4]);
+        let mut player = player();
+        player.select_track(Some(playlist.tracks[1].clone()));
+
+        // Queued tracks from outside the playlist have no position to go on from.
+        let outside = LibraryItem::new(PathBuf::from("outside.mp3"), LibraryPathId::new(1));
+        player.add_to_queue(outside.clone());
+        player.add_to_queue(outside.clone());
+        player.next(&playlist);
+        assert_eq!(player.selected_track, Some(outside));
+
+        player.queue_next(&playlist);
+        let queued = player.queued_track.clone().unwrap();
+        player.advance_to_queued(&queued.path());
+        assert!(player.queue.is_empty());
+
+        player.queue_next(&playlist);
+        let queued = player.queued_track.clone().unwrap();
+        player.advance_to_queued(&queued.path());
+        assert_eq!(player.selected_track, Some(playlist.tracks[2].clone()));
+        assert_eq!(play_through(&mut player, &playlist, 1), [3]);
+    }
+
+    #[test]
+    fn sends_changed_replay_gain() {
+        let (tx, rx) = channel();
+        let mut player = Player::new(tx, Arc::new(AtomicU32::new(0)));
+        let playlist = playlist(&["a"; 2]);
+        player.select_track(Some(playlist.tracks[0].clone()));
+        player.queue_next(&playlist);
+
+        let gain = ReplayGain {
+            track_gain: Some(-3.0),
+            ..Default::default()
+        };
+        let mut scanned = playlist.clone();
+        for track in &playlist.tracks {
+            let track = track.clone().set_replay_gain(gain);
+            scanned.update_track(&track);
+            player.update_selected_track(&track);
+        }
+        player.queue_next(&scanned);
+
+        let sent = rx.try_iter().collect::<Vec<_>>();
+        assert!(matches!(sent[..], [
+            AudioCommand::LoadFile(..),
+            AudioCommand::QueueNext { .. },
+            AudioCommand::SetTrackReplayGain(playing),
+            AudioCommand::QueueNext { replay_gain: queued, .. },
+        ] if playing == gain && queued == gain));
+    }
+
+    #[test]
+    fn queue_works_without_a_playlist() {
+        let playlist = playlist(&["a"; 2]);
+        let mut player = player();
+        player.add_to_queue(playlist.tracks[1].clone());
+
+        player.queue_next(&Playlist::new());
+        let queued = player.queued_track.clone().unwrap();
+        player.advance_to_queued(&queued.path());
+
+        assert_eq!(player.selected_track, Some(playlist.tracks[1].clone()));
+        assert!(player.queue.is_empty());
+    }
+}
@@ -1,170 +1,665 @@
-use crate::app::LibraryItem;
-use crate::AudioCommand;
-use serde::{Deserialize, Serialize};
-use std::sync::mpsc::Sender;
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Playlist {
-    name: Option<String>,
-    // Note: Using a vec seems good, until I want to re-order and drag/drop songs.
-    // ex: What if the playlist was 100,000 songs long and I moved the last item to the beginning.
-    // Then all of the 99k tracks need to be shifted back by 1. Maybe a linked list is the right choice?
-    pub tracks: Vec<LibraryItem>,
-    pub selected: Option<LibraryItem>,
-    pub is_editing_name: bool,
-}
-
-impl Playlist {
-    pub fn new() -> Self {
-        Self {
-            name: None,
-            tracks: vec![],
-            selected: None,
-            is_editing_name: false,
-        }
-    }
-
-    pub fn set_name(&mut self, name: String) {
-        self.name = Some(name);
-    }
-
-    pub fn get_name(&self) -> Option<String> {
-        self.name.clone()
-    }
-
-    pub fn add(&mut self, track: LibraryItem) {
-        self.tracks.push(track);
-    }
-
-    // TODO - should probably return a Result
-    pub fn remove(&mut self, idx: usize) {
-        self.tracks.remove(idx);
-    }
-
-    // TODO - should probably return a Result
-    pub fn reorder(&mut self, current_pos: usize, destination_pos: usize) {
-        let track = self.tracks.remove(current_pos);
-        self.tracks.insert(destination_pos, track);
-    }
-
-    // TODO - should probably return a Result
-    pub fn select(&mut self, idx: usize, audio_cmd_tx: &Sender<AudioCommand>) {
-        tracing::info!("SELECTED");
-        let track = self.tracks[idx].clone();
-        let path = &track.path();
-        audio_cmd_tx
-            .send(AudioCommand::LoadFile((*path).clone()))
-            .expect("Failed to send to audio thread");
-
-        self.selected = Some(track);
-    }
-
-    pub fn get_pos(&self, track: &LibraryItem) -> Option<usize> {
-        self.tracks.iter().position(|t| t == track)
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::path::PathBuf;
-
-    #[test]
-    fn create_playlist() {
-        let playlist = Playlist::new();
-
-        assert_eq!(playlist.name, None);
-        assert_eq!(playlist.tracks.len(), 0);
-        assert_eq!(playlist.selected, None);
-    }
-
-    #[test]
-    fn set_name() {
-        let mut playlist = Playlist::new();
-        playlist.set_name("Test".to_string());
-
-        assert_eq!(playlist.name, playlist.get_name());
-        assert_eq!(playlist.tracks.len(), 0);
-        assert_eq!(playlist.selected, None);
-    }
-
-    #[test]
-    fn add_track_to_playlist() {
-        let track = LibraryItem::new(PathBuf::from(r"C:\music\song.mp3"));
-
-        let mut playlist = Playlist::new();
-        playlist.add(track);
-
-        assert_eq!(playlist.tracks.len(), 1);
-    }
-
-    #[test]
-    fn remove_track_from_playlist() {
-        let path1 = PathBuf::from(r"C:\music\song1.mp3");
-        let path2 = PathBuf::from(r"C:\music\song2.mp3");
-        let path3 = PathBuf::from(r"C:\music\song3.mp3");
-
-        let mut playlist = Playlist {
-            name: Some("test".to_string()),
-            tracks: vec![
-                LibraryItem::new(path1.clone()),
-                LibraryItem::new(path2.clone()),
-                LibraryItem::new(path3.clone()),
-            ],
-            selected: None,
-        };
-
-        assert_eq!(playlist.tracks.len(), 3);
-
-        playlist.remove(1);
-
-        assert_eq!(playlist.tracks.len(), 2);
-        assert_eq!(playlist.tracks.first().unwrap().path(), path1);
-        assert_eq!(playlist.tracks.last().unwrap().path(), path3);
-    }
-
-    #[test]
-    fn reorder_track_in_playlist() {
-        let path1 = PathBuf::from(r"C:\music\song1.mp3");
-        let path2 = PathBuf::from(r"C:\music\song2.mp3");
-        let path3 = PathBuf::from(r"C:\music\song3.mp3");
-
-        let mut playlist = Playlist {
-            name: Some("test".to_string()),
-            tracks: vec![
-                LibraryItem::new(path1.clone()),
-                LibraryItem::new(path2.clone()),
-                LibraryItem::new(path3.clone()),
-            ],
-            selected: None,
-        };
-
-        assert_eq!(playlist.tracks.len(), 3);
-
-        playlist.reorder(0, 2);
-
-        assert_eq!(playlist.tracks.len(), 3);
-        assert_eq!(playlist.tracks[0].path(), path2);
-        assert_eq!(playlist.tracks[1].path(), path3);
-        assert_eq!(playlist.tracks[2].path(), path1);
-    }
-
-    // #[test]
-    // fn select_track() {
-    //     let track1 = LibraryItem::new(PathBuf::from(r"C:\music\song1.mp3"));
-    //     let track2 = LibraryItem::new(PathBuf::from(r"C:\music\song2.mp3"));
-    //     let track3 = LibraryItem::new(PathBuf::from(r"C:\music\song3.mp3"));
-
-    //     let mut playlist = Playlist {
-    //         name: Some("test".to_string()),
-    //         tracks: vec![track1, track2, track3.clone()],
-    //         selected: None,
-    //     };
-
-    //     assert_eq!(playlist.tracks.len(), 3);
-
-    //     playlist.select(2);
-
-    //     assert_eq!(playlist.selected, Some(track3));
-    // }
-}
+use crate::app::library::{Library, LibraryPathId};
+use crate::app::playlist_columns::{PlaylistColumns, SortKey};
+use crate::app::smart_playlist::SmartPlaylist;
+use crate::app::LibraryItem;
+use crate::AudioCommand;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+
+// How often a smart playlist with rules about dates picks its tracks again, so tracks leave
+// "added in the last 7 days" without the library changing.
+const TIME_RULES_REFRESH_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Playlist {
+    name: Option<String>,
+    // Note: Using a vec seems good, until I want to re-order and drag/drop songs.
+    // ex: What if the playlist was 100,000 songs long and I moved the last item to the beginning.
+    // Then all of the 99k tracks need to be shifted back by 1. Maybe a linked list is the right choice?
+    #[serde(with = "track_refs")]
+    pub tracks: Vec<LibraryItem>,
+    #[serde(with = "track_ref")]
+    pub selected: Option<LibraryItem>,
+    pub is_editing_name: bool,
+    #[serde(default)]
+    pub kind: PlaylistKind,
+    // Columns of its own, instead of the ones the playlists share.
+    #[serde(default)]
+    pub columns: Option<PlaylistColumns>,
+    // What the tracks were last sorted by, until they're changed otherwise.
+    #[serde(skip_serializing, skip_deserializing)]
+    pub sort_keys: Vec<SortKey>,
+    // The library revision the tracks of a smart playlist were picked at.
+    #[serde(skip_serializing, skip_deserializing)]
+    library_revision: Option<u64>,
+    // When the tracks were picked, in seconds since the Unix epoch.
+    #[serde(skip_serializing, skip_deserializing)]
+    picked_at: u64,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub selection: TrackSelection,
+    #[serde(skip_serializing, skip_deserializing)]
+    name_before_edit: Option<String>,
+}
+
+// Where the tracks of a playlist come from.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub enum PlaylistKind {
+    // Tracks added by hand.
+    #[default]
+    Manual,
+    // Tracks picked from the library by rules, picked again whenever the library changes.
+    Smart(SmartPlaylist),
+}
+
+impl Playlist {
+    pub fn new() -> Self {
+        Self {
+            name: None,
+            tracks: vec![],
+            selected: None,
+            is_editing_name: false,
+            kind: PlaylistKind::Manual,
+            columns: None,
+            sort_keys: Vec::new(),
+            library_revision: None,
+            picked_at: 0,
+            selection: TrackSelection::default(),
+            name_before_edit: None,
+        }
+    }
+
+    pub fn new_smart(smart_playlist: SmartPlaylist) -> Self {
+        let mut playlist = Self::new();
+        playlist.set_smart_playlist(smart_playlist);
+        playlist
+    }
+
+    pub fn smart_playlist(&self) -> Option<&SmartPlaylist> {
+        match &self.kind {
+            PlaylistKind::Smart(smart_playlist) => Some(smart_playlist),
+            PlaylistKind::Manual => None,
+        }
+    }
+
+    pub fn is_smart(&self) -> bool {
+        self.smart_playlist().is_some()
+    }
+
+    // Replaces the rules, picking the tracks again on the next refresh.
+    pub fn set_smart_playlist(&mut self, smart_playlist: SmartPlaylist) {
+        self.kind = PlaylistKind::Smart(smart_playlist);
+        self.library_revision = None;
+    }
+
+    // Picks the tracks of a smart playlist again if the library changed since they were picked,
+    // or a while went by for rules like "added in the last 7 days".
+    pub fn refresh(&mut self, library: &Library, now: u64) {
+        let PlaylistKind::Smart(smart_playlist) = &self.kind else {
+            return;
+        };
+
+        let is_outdated = smart_playlist.depends_on_time()
+            && now.abs_diff(self.picked_at) >= TIME_RULES_REFRESH_SECS;
+        if self.library_revision == Some(library.revision()) && !is_outdated {
+            return;
+        }
+
+        self.tracks = smart_playlist.tracks(library.items(), now);
+        self.library_revision = Some(library.revision());
+        self.picked_at = now;
+        self.selection.clear();
+    }
+
+    pub fn set_name(&mut self, name: String) {
+        self.name = Some(name);
+    }
+
+    pub fn get_name(&self) -> Option<String> {
+        self.name.clone()
+    }
+
+    // The tracks of a smart playlist only come from its rules, so it can't be changed by hand.
+    pub fn add(&mut self, track: LibraryItem) {
+        if !self.is_smart() {
+            self.tracks.push(track);
+        }
+    }
+
+    // TODO - should probably return a Result
+    pub fn remove(&mut self, idx: usize) {
+        if !self.is_smart() {
+            self.tracks.remove(idx);
+        }
+    }
+
+    // TODO - should probably return a Result
+    pub fn reorder(&mut self, current_pos: usize, destination_pos: usize) {
+        if self.is_smart() {
+            return;
+        }
+
+        let track = self.tracks.remove(current_pos);
+        self.tracks.insert(destination_pos, track);
+    }
+
+    // Removes the tracks at the positions, ex. the selected rows.
+    pub fn remove_tracks(&mut self, indices: &[usize]) {
+        if self.is_smart() {
+            return;
+        }
+
+        let indices = indices.iter().collect::<BTreeSet<&usize>>();
+        let mut idx = 0;
+
+        self.tracks.retain(|_| {
+            let is_kept = !indices.contains(&idx);
+            idx += 1;
+            is_kept
+        });
+
+        self.selection.clear();
+        self.sort_keys.clear();
+    }
+
+    // Moves the tracks at the positions in front of the track at `destination`, or to the end
+    // when it's the length of the playlist. They keep their order and stay selected.
+    pub fn move_tracks(&mut self, indices: &[usize], destination: usize) {
+        if self.is_smart() {
+            return;
+        }
+
+        let indices = indices
+            .iter()
+            .copied()
+            .filter(|idx| *idx < self.tracks.len())
+            .collect::<BTreeSet<usize>>();
+
+        let insert_at = self.moved_to(&indices, destination);
+
+        let moved = indices
+            .iter()
+            .rev()
+            .map(|idx| self.tracks.remove(*idx))
+            .collect::<Vec<LibraryItem>>();
+
+        let moved_count = moved.len();
+        for track in moved {
+            self.tracks.insert(insert_at, track);
+        }
+
+        self.selection.clear();
+        for idx in insert_at..insert_at + moved_count {
+            self.selection.toggle(idx);
+        }
+
+        self.sort_keys.clear();
+    }
+
+    // Puts tracks moved by `move_tracks` back where they were.
+    pub fn unmove_tracks(&mut self, indices: &[usize], destination: usize) {
+        if self.is_smart() {
+            return;
+        }
+
+        let indices = indices.iter().copied().collect::<BTreeSet<usize>>();
+        let insert_at = self.moved_to(&indices, destination);
+        let moved = self
+            .tracks
+            .drain(insert_at..(insert_at + indices.len()).min(self.tracks.len()))
+            .collect::<Vec<LibraryItem>>();
+
+        self.selection.clear();
+        for (idx, track) in indices.into_iter().zip(moved) {
+            self.tracks.insert(idx, track);
+            self.selection.toggle(idx);
+        }
+
+        self.sort_keys.clear();
+    }
+
+    // Where the first of the tracks at `indices` ends up when they're moved to `destination`.
+    fn moved_to(&self, indices: &BTreeSet<usize>, destination: usize) -> usize {
+        // The destination shifts up by each moved track that was above it.
+        destination.min(self.tracks.len())
+            - indices.iter().filter(|idx| **idx < destination).count()
+    }
+
+    // Puts removed tracks back at their positions, which are in order.
+    pub fn restore_tracks(&mut self, removed: Vec<(usize, LibraryItem)>) {
+        if self.is_smart() {
+            return;
+        }
+
+        self.selection.clear();
+        for (idx, track) in removed {
+            let idx = idx.min(self.tracks.len());
+            self.tracks.insert(idx, track);
+            self.selection.toggle(idx);
+        }
+
+        self.sort_keys.clear();
+    }
+
+    // Starts renaming the playlist, remembering the name it had.
+    pub fn start_editing_name(&mut self) {
+        self.is_editing_name = true;
+        self.name_before_edit = self.name.clone();
+    }
+
+    // Stops renaming the playlist. Returns the old and new name if it changed.
+    pub fn finish_editing_name(&mut self) -> Option<(String, String)> {
+        self.is_editing_name = false;
+        let from = self.name_before_edit.take().unwrap_or_default();
+        let to = self.name.clone().unwrap_or_default();
+
+        (from != to).then_some((from, to))
+    }
+
+    // Inserts copies of tracks from another playlist at `at`, selecting them.
+    pub fn insert_tracks(&mut self, at: usize, tracks: Vec<LibraryItem>) {
+        if self.is_smart() {
+            return;
+        }
+
+        let at = at.min(self.tracks.len());
+        let count = tracks.len();
+        self.tracks.splice(at..at, tracks);
+
+        self.selection.clear();
+        for idx in at..at + count {
+            self.selection.toggle(idx);
+        }
+
+        self.sort_keys.clear();
+    }
+
+    // Puts the tracks in a new order, given as their current positions, ex. after sorting.
+    pub fn arrange_tracks(&mut self, order: &[usize], sort_keys: Vec<SortKey>) {
+        if self.is_smart() || order.len() != self.tracks.len() {
+            return;
+        }
+
+        self.tracks = order.iter().map(|idx| self.tracks[*idx].clone()).collect();
+        self.selection.clear();
+        self.sort_keys = sort_keys;
+    }
+
+    // TODO - should probably return a Result
+    pub fn select(&mut self, idx: usize, audio_cmd_tx: &Sender<AudioCommand>) {
+        tracing::info!("SELECTED");
+        let track = self.tracks[idx].clone();
+        let path = &track.path();
+        audio_cmd_tx
+            .send(AudioCommand::LoadFile((*path).clone(), track.replay_gain()))
+            .expect("Failed to send to audio thread");
+
+        self.selected = Some(track);
+    }
+
+    // Replaces the tracks that are the same library item, ex. after its tags changed.
+    pub fn update_track(&mut self, library_item: &LibraryItem) {
+        let selected = self.selected.iter_mut();
+
+        for track in self.tracks.iter_mut().chain(selected) {
+            if track.key() == library_item.key() {
+                *track = library_item.clone();
+            }
+        }
+    }
+
+    pub fn get_pos(&self, track: &LibraryItem) -> Option<usize> {
+        self.tracks.iter().position(|t| t == track)
+    }
+}
+
+/// What the app state keeps of a playlist track: enough to find it in the library again, even after
+/// its file was moved. The rest of the track is filled in from the library on startup.
+#[derive(Serialize, Deserialize)]
+struct TrackRef {
+    key: usize,
+    library_id: LibraryPathId,
+    path: PathBuf,
+    #[serde(default)]
+    fingerprint: Option<u64>,
+}
+
+impl From<&LibraryItem> for TrackRef {
+    fn from(track: &LibraryItem) -> Self {
+        Self {
+            key: track.key(),
+            library_id: track.library_id(),
+            path: track.path(),
+            fingerprint: track.fingerprint(),
+        }
+    }
+}
+
+impl From<TrackRef> for LibraryItem {
+    fn from(track_ref: TrackRef) -> Self {
+        LibraryItem::new(track_ref.path, track_ref.library_id)
+            .set_key(track_ref.key)
+            .set_fingerprint(track_ref.fingerprint)
+    }
+}
+
+// App states saved before playlists kept references hold whole tracks, which read as references
+// just as well.
+mod track_refs {
+    use super::{LibraryItem, TrackRef};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        tracks: &[LibraryItem],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(tracks.iter().map(TrackRef::from))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<LibraryItem>, D::Error> {
+        let track_refs = Vec::<TrackRef>::deserialize(deserializer)?;
+        Ok(track_refs.into_iter().map(LibraryItem::from).collect())
+    }
+}
+
+mod track_ref {
+    use super::{LibraryItem, TrackRef};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        track: &Option<LibraryItem>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        track.as_ref().map(TrackRef::from).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<LibraryItem>, D::Error> {
+        let track_ref = Option::<TrackRef>::deserialize(deserializer)?;
+        Ok(track_ref.map(LibraryItem::from))
+    }
+}
+
+/// The rows selected in the playlist table, which aren't necessarily the track that's playing.
+/// Rows are kept by position, since the same track can be in a playlist more than once.
+#[derive(Debug, Clone, Default)]
+pub struct TrackSelection {
+    indices: BTreeSet<usize>,
+    // The row a shift-click selects from.
+    anchor: Option<usize>,
+}
+
+impl TrackSelection {
+    // A plain click.
+    pub fn select_only(&mut self, idx: usize) {
+        self.indices.clear();
+        self.indices.insert(idx);
+        self.anchor = Some(idx);
+    }
+
+    // A ctrl-click.
+    pub fn toggle(&mut self, idx: usize) {
+        if !self.indices.remove(&idx) {
+            self.indices.insert(idx);
+        }
+
+        self.anchor = Some(idx);
+    }
+
+    // A shift-click, which selects every row between the anchor and this one.
+    pub fn extend_to(&mut self, idx: usize) {
+        let anchor = self.anchor.unwrap_or(idx);
+
+        self.indices = (anchor.min(idx)..=anchor.max(idx)).collect();
+        self.anchor = Some(anchor);
+    }
+
+    pub fn contains(&self, idx: usize) -> bool {
+        self.indices.contains(&idx)
+    }
+
+    // The selected rows, top to bottom.
+    pub fn indices(&self) -> Vec<usize> {
+        self.indices.iter().copied().collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.indices.clear();
+        self.anchor = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::LibraryPathId;
+    use std::path::PathBuf;
+
+    #[test]
+    fn create_playlist() {
+        let playlist = Playlist::new();
+
+        assert_eq!(playlist.name, None);
+        assert_eq!(playlist.tracks.len(), 0);
+        assert_eq!(playlist.selected, None);
+    }
+
+    #[test]
+    fn set_name() {
+        let mut playlist = Playlist::new();
+        playlist.set_name("Test".to_string());
+
+        assert_eq!(playlist.name, playlist.get_name());
+        assert_eq!(playlist.tracks.len(), 0);
+        assert_eq!(playlist.selected, None);
+    }
+
+    #[test]
+    fn add_track_to_playlist() {
+        let track = LibraryItem::new(PathBuf::from(r"C:\music\song.mp3"), LibraryPathId::new(0));
+
+        let mut playlist = Playlist::new();
+        playlist.add(track);
+
+        assert_eq!(playlist.tracks.len(), 1);
+    }
+
+    #[test]
+    fn remove_track_from_playlist() {
+        let path1 = PathBuf::from(r"C:\music\song1.mp3");
+        let path2 = PathBuf::from(r"C:\music\song2.mp3");
+        let path3 = PathBuf::from(r"C:\music\song3.mp3");
+
+        let mut playlist = Playlist {
+            name: Some("test".to_string()),
+            tracks: vec![
+                LibraryItem::new(path1.clone(), LibraryPathId::new(0)),
+                LibraryItem::new(path2.clone(), LibraryPathId::new(0)),
+                LibraryItem::new(path3.clone(), LibraryPathId::new(0)),
+            ],
+            selected: None,
+            is_editing_name: false,
+            kind: PlaylistKind::Manual,
+            columns: None,
+            sort_keys: Vec::new(),
+            library_revision: None,
+            picked_at: 0,
+            selection: TrackSelection::default(),
+            name_before_edit: None,
+        };
+
+        assert_eq!(playlist.tracks.len(), 3);
+
+        playlist.remove(1);
+
+        assert_eq!(playlist.tracks.len(), 2);
+        assert_eq!(playlist.tracks.first().unwrap().path(), path1);
+        assert_eq!(playlist.tracks.last().unwrap().path(), path3);
+    }
+
+    #[test]
+    fn reorder_track_in_playlist() {
+        let path1 = PathBuf::from(r"C:\music\song1.mp3");
+        let path2 = PathBuf::from(r"C:\music\song2.mp3");
+        let path3 = PathBuf::from(r"C:\music\song3.mp3");
+
+        let mut playlist = Playlist {
+            name: Some("test".to_string()),
+            tracks: vec![
+                LibraryItem::new(path1.clone(), LibraryPathId::new(0)),
+                LibraryItem::new(path2.clone(), LibraryPathId::new(0)),
+                LibraryItem::new(path3.clone(), LibraryPathId::new(0)),
+            ],
+            selected: None,
+            is_editing_name: false,
+            kind: PlaylistKind::Manual,
+            columns: None,
+            sort_keys: Vec::new(),
+            library_revision: None,
+            picked_at: 0,
+            selection: TrackSelection::default(),
+            name_before_edit: None,
+        };
+
+        assert_eq!(playlist.tracks.len(), 3);
+
+        playlist.reorder(0, 2);
+
+        assert_eq!(playlist.tracks.len(), 3);
+        assert_eq!(playlist.tracks[0].path(), path2);
+        assert_eq!(playlist.tracks[1].path(), path3);
+        assert_eq!(playlist.tracks[2].path(), path1);
+    }
+
+    #[test]
+    fn moves_and_removes_selected_tracks() {
+        let mut playlist = Playlist::new();
+        for n in 0..5 {
+            let path = PathBuf::from(format!("/music/{}.mp3", n));
+            playlist.add(LibraryItem::new(path, LibraryPathId::new(0)));
+        }
+        let order = |playlist: &Playlist| {
+            playlist
+                .tracks
+                .iter()
+                .map(|track| {
+                    track
+                        .path()
+                        .file_stem()
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string()
+                })
+                .collect::<Vec<String>>()
+                .join("")
+        };
+
+        playlist.selection.select_only(1);
+        playlist.selection.toggle(3);
+        playlist.move_tracks(&playlist.selection.indices(), 0);
+        assert_eq!(order(&playlist), "13024");
+        assert_eq!(playlist.selection.indices(), [0, 1]);
+
+        playlist.move_tracks(&playlist.selection.indices(), 5);
+        assert_eq!(order(&playlist), "02413");
+        assert_eq!(playlist.selection.indices(), [3, 4]);
+
+        playlist.selection.select_only(3);
+        playlist.selection.extend_to(1);
+        assert_eq!(playlist.selection.indices(), [1, 2, 3]);
+
+        playlist.remove_tracks(&playlist.selection.indices());
+        assert_eq!(order(&playlist), "03");
+        assert!(playlist.selection.is_empty());
+    }
+
+    #[test]
+    fn smart_playlist_follows_the_library() {
+        use crate::app::smart_playlist::{Rule, RuleField, RuleOperator};
+
+        let track = |name: &str, genre: &str| {
+            LibraryItem::new(PathBuf::from(name), LibraryPathId::new(0)).set_genre(Some(genre))
+        };
+
+        let mut library = Library::new();
+        library.add_items(vec![track("/music/so-what.mp3", "Jazz")]);
+
+        let mut playlist = Playlist::new_smart(SmartPlaylist {
+            rules: vec![Rule {
+                field: RuleField::Genre,
+                operator: RuleOperator::Is,
+                value: "jazz".to_string(),
+            }],
+            ..Default::default()
+        });
+        playlist.refresh(&library, 0);
+        assert_eq!(playlist.tracks.len(), 1);
+
+        let rock = track("/music/paranoid.mp3", "Rock");
+        library.add_items(vec![
+            track("/music/blue-in-green.mp3", "Jazz"),
+            rock.clone(),
+        ]);
+        playlist.refresh(&library, 0);
+        assert_eq!(playlist.tracks.len(), 2);
+        assert!(playlist.tracks[0].date_added().is_some());
+
+        // Tracks can't be added by hand, they'd be gone on the next refresh anyway.
+        playlist.add(rock);
+        playlist.remove(0);
+        assert_eq!(playlist.tracks.len(), 2);
+    }
+
+    #[test]
+    fn smart_playlist_with_dates_follows_the_time() {
+        use crate::app::smart_playlist::{Rule, RuleField, RuleOperator};
+
+        let now = crate::app::library::unix_now();
+        let mut library = Library::new();
+        library.add_items(vec![LibraryItem::new(
+            PathBuf::from("/music/new.mp3"),
+            LibraryPathId::new(0),
+        )]);
+
+        let mut playlist = Playlist::new_smart(SmartPlaylist {
+            rules: vec![Rule {
+                field: RuleField::DateAdded,
+                operator: RuleOperator::InTheLast,
+                value: "1".to_string(),
+            }],
+            ..Default::default()
+        });
+        playlist.refresh(&library, now);
+        assert_eq!(playlist.tracks.len(), 1);
+
+        playlist.refresh(&library, now + 2 * 24 * 60 * 60);
+        assert!(playlist.tracks.is_empty());
+    }
+
+    // #[test]
+    // fn select_track() {
+    //     let track1 = LibraryItem::new(PathBuf::from(r"C:\music\song1.mp3"), LibraryPathId::new(0));
+    //     let track2 = LibraryItem::new(PathBuf::from(r"C:\music\song2.mp3"), LibraryPathId::new(0));
+    //     let track3 = LibraryItem::new(PathBuf::from(r"C:\music\song3.mp3"), LibraryPathId::new(0));
+
+    //     let mut playlist = Playlist {
+    //         name: Some("test".to_string()),
+    //         tracks: vec![track1, track2, track3.clone()],
+    //         selected: None,
+    //     };
+
+    //     assert_eq!(playlist.tracks.len(), 3);
+
+    //     playlist.select(2);
+
+    //     assert_eq!(playlist.selected, Some(track3));
+    // }
+}
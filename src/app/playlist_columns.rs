@@ -0,0 +1,490 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+
+use super::library::format_duration;
+use super::title_format::TitleFormat;
+use super::LibraryItem;
+
+// The script a new custom column starts with.
+pub const DEFAULT_CUSTOM_FORMAT: &str = "[%artist% - ]%title%";
+
+/// The columns of the playlist table, in order. They're shared by the playlists, unless a
+/// playlist has columns of its own.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlaylistColumns {
+    pub columns: Vec<PlaylistColumn>,
+}
+
+impl Default for PlaylistColumns {
+    fn default() -> Self {
+        Self {
+            columns: [
+                ColumnField::Playing,
+                ColumnField::TrackNumber,
+                ColumnField::Artist,
+                ColumnField::Album,
+                ColumnField::Title,
+                ColumnField::Genre,
+            ]
+            .into_iter()
+            .map(PlaylistColumn::new)
+            .collect(),
+        }
+    }
+}
+
+impl PlaylistColumns {
+    pub fn contains(&self, field: ColumnField) -> bool {
+        self.columns.iter().any(|column| column.field == field)
+    }
+
+    // Shows the field at the end, or hides it. The last column can't be hidden.
+    pub fn toggle(&mut self, field: ColumnField) {
+        if !self.contains(field) {
+            self.columns.push(PlaylistColumn::new(field));
+        } else if self.columns.len() > 1 {
+            self.columns.retain(|column| column.field != field);
+        }
+    }
+
+    pub fn remove_column(&mut self, idx: usize) {
+        if idx < self.columns.len() && self.columns.len() > 1 {
+            self.columns.remove(idx);
+        }
+    }
+
+    // Swaps the column with the one next to it, a step to the left or right.
+    pub fn move_column(&mut self, idx: usize, to_right: bool) {
+        let other_idx = match to_right {
+            true => idx + 1,
+            false => idx.wrapping_sub(1),
+        };
+
+        if idx < self.columns.len() && other_idx < self.columns.len() {
+            self.columns.swap(idx, other_idx);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlaylistColumn {
+    pub field: ColumnField,
+    // Without a width, the column shares the space left over with the others.
+    pub width: Option<f32>,
+    pub align: ColumnAlign,
+    // The header and title formatting script of a custom column.
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub format: String,
+}
+
+impl PlaylistColumn {
+    pub fn new(field: ColumnField) -> Self {
+        Self {
+            field,
+            width: None,
+            align: field.default_align(),
+            name: String::new(),
+            format: String::new(),
+        }
+    }
+
+    pub fn custom(name: &str, format: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            format: format.to_string(),
+            ..Self::new(ColumnField::Custom)
+        }
+    }
+
+    pub fn title(&self) -> String {
+        match self.field {
+            ColumnField::Custom => self.name.clone(),
+            field => field.to_string(),
+        }
+    }
+
+    // The script of a custom column, or `None` for other columns and scripts that don't parse.
+    pub fn title_format(&self) -> Option<TitleFormat> {
+        custom_title_format(self.field, &self.format)
+    }
+}
+
+fn custom_title_format(field: ColumnField, format: &str) -> Option<TitleFormat> {
+    match field {
+        ColumnField::Custom => TitleFormat::parse(format).ok(),
+        _ => None,
+    }
+}
+
+/// A field of a track that can be shown as a column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ColumnField {
+    // Marks the track that's playing.
+    Playing,
+    TrackNumber,
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Year,
+    PlayCount,
+    Path,
+    Duration,
+    Bitrate,
+    SampleRate,
+    Channels,
+    BitDepth,
+    Codec,
+    // The text of a title formatting script, see `PlaylistColumn::format`.
+    Custom,
+}
+
+impl ColumnField {
+    pub const ALL: [ColumnField; 15] = [
+        ColumnField::Playing,
+        ColumnField::TrackNumber,
+        ColumnField::Title,
+        ColumnField::Artist,
+        ColumnField::Album,
+        ColumnField::Genre,
+        ColumnField::Year,
+        ColumnField::PlayCount,
+        ColumnField::Path,
+        ColumnField::Duration,
+        ColumnField::Bitrate,
+        ColumnField::SampleRate,
+        ColumnField::Channels,
+        ColumnField::BitDepth,
+        ColumnField::Codec,
+    ];
+
+    pub fn is_sortable(&self) -> bool {
+        *self != ColumnField::Playing
+    }
+
+    fn default_align(&self) -> ColumnAlign {
+        match self {
+            ColumnField::Playing => ColumnAlign::Center,
+            ColumnField::TrackNumber
+            | ColumnField::Year
+            | ColumnField::PlayCount
+            | ColumnField::Duration
+            | ColumnField::Bitrate
+            | ColumnField::SampleRate
+            | ColumnField::BitDepth => ColumnAlign::Right,
+            _ => ColumnAlign::Left,
+        }
+    }
+
+    // The text of the cell, empty when the track doesn't have the field. Custom columns format
+    // their own text.
+    pub fn text(&self, item: &LibraryItem) -> String {
+        let properties = item.properties();
+
+        match self {
+            ColumnField::Playing | ColumnField::Custom => String::new(),
+            ColumnField::TrackNumber => item
+                .track_number()
+                .map(|n| n.to_string())
+                .unwrap_or_default(),
+            ColumnField::Title => item.title().unwrap_or("?".to_string()),
+            ColumnField::Artist => item.artist().unwrap_or("?".to_string()),
+            ColumnField::Album => item.album().unwrap_or("?".to_string()),
+            ColumnField::Genre => item.genre().unwrap_or("?".to_string()),
+            ColumnField::Year => item.year().map(|n| n.to_string()).unwrap_or_default(),
+            ColumnField::PlayCount => item.play_count().to_string(),
+            ColumnField::Path => item.path().display().to_string(),
+            ColumnField::Duration => properties
+                .duration_millis
+                .map(format_duration)
+                .unwrap_or_default(),
+            ColumnField::Bitrate => properties
+                .bitrate
+                .map(|kbps| format!("{} kbps", kbps))
+                .unwrap_or_default(),
+            ColumnField::SampleRate => properties
+                .sample_rate
+                .map(|hz| format!("{} Hz", hz))
+                .unwrap_or_default(),
+            ColumnField::Channels => properties.channels_text().unwrap_or_default(),
+            ColumnField::BitDepth => properties
+                .bits_per_sample
+                .map(|bits| format!("{} bit", bits))
+                .unwrap_or_default(),
+            ColumnField::Codec => properties.codec.clone().unwrap_or_default(),
+        }
+    }
+
+    // Tracks without the field come first. Text is compared ignoring case.
+    pub fn compare(&self, a: &LibraryItem, b: &LibraryItem) -> Ordering {
+        let lowercase = |text: Option<String>| text.map(|text| text.to_lowercase());
+        let (a_properties, b_properties) = (a.properties(), b.properties());
+
+        match self {
+            ColumnField::Playing | ColumnField::Custom => Ordering::Equal,
+            ColumnField::TrackNumber => a.track_number().cmp(&b.track_number()),
+            ColumnField::Title => lowercase(a.title()).cmp(&lowercase(b.title())),
+            ColumnField::Artist => lowercase(a.artist()).cmp(&lowercase(b.artist())),
+            ColumnField::Album => lowercase(a.album()).cmp(&lowercase(b.album())),
+            ColumnField::Genre => lowercase(a.genre()).cmp(&lowercase(b.genre())),
+            ColumnField::Year => a.year().cmp(&b.year()),
+            ColumnField::PlayCount => a.play_count().cmp(&b.play_count()),
+            ColumnField::Path => a.path().cmp(&b.path()),
+            ColumnField::Duration => a_properties
+                .duration_millis
+                .cmp(&b_properties.duration_millis),
+            ColumnField::Bitrate => a_properties.bitrate.cmp(&b_properties.bitrate),
+            ColumnField::SampleRate => a_properties.sample_rate.cmp(&b_properties.sample_rate),
+            ColumnField::Channels => a_properties.channels.cmp(&b_properties.channels),
+            ColumnField::BitDepth => a_properties
+                .bits_per_sample
+                .cmp(&b_properties.bits_per_sample),
+            ColumnField::Codec => {
+                lowercase(a_properties.codec.clone()).cmp(&lowercase(b_properties.codec.clone()))
+            }
+        }
+    }
+}
+
+impl fmt::Display for ColumnField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColumnField::Playing => write!(f, "Playing"),
+            ColumnField::TrackNumber => write!(f, "#"),
+            ColumnField::Title => write!(f, "Title"),
+            ColumnField::Artist => write!(f, "Artist"),
+            ColumnField::Album => write!(f, "Album"),
+            ColumnField::Genre => write!(f, "Genre"),
+            ColumnField::Year => write!(f, "Year"),
+            ColumnField::PlayCount => write!(f, "Plays"),
+            ColumnField::Path => write!(f, "Path"),
+            ColumnField::Duration => write!(f, "Length"),
+            ColumnField::Bitrate => write!(f, "Bitrate"),
+            ColumnField::SampleRate => write!(f, "Sample Rate"),
+            ColumnField::Channels => write!(f, "Channels"),
+            ColumnField::BitDepth => write!(f, "Bit Depth"),
+            ColumnField::Codec => write!(f, "Codec"),
+            ColumnField::Custom => write!(f, "Custom"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum ColumnAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+impl ColumnAlign {
+    pub const ALL: [ColumnAlign; 3] = [ColumnAlign::Left, ColumnAlign::Center, ColumnAlign::Right];
+
+    pub fn layout(&self) -> egui::Layout {
+        match self {
+            ColumnAlign::Left => egui::Layout::left_to_right(egui::Align::Center),
+            ColumnAlign::Center => {
+                egui::Layout::centered_and_justified(egui::Direction::LeftToRight)
+            }
+            ColumnAlign::Right => egui::Layout::right_to_left(egui::Align::Center),
+        }
+    }
+}
+
+impl fmt::Display for ColumnAlign {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColumnAlign::Left => write!(f, "Left"),
+            ColumnAlign::Center => write!(f, "Center"),
+            ColumnAlign::Right => write!(f, "Right"),
+        }
+    }
+}
+
+/// A field the tracks of a playlist are sorted by. Later keys order the tracks the earlier ones
+/// consider equal.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SortKey {
+    pub field: ColumnField,
+    // The script of a custom column.
+    #[serde(default)]
+    pub format: String,
+    pub descending: bool,
+}
+
+impl SortKey {
+    pub fn sorts_by(&self, column: &PlaylistColumn) -> bool {
+        self.field == column.field && self.format == column.format
+    }
+}
+
+// The sort keys after a header is clicked. A click sorts by the field alone, or reverses it when
+// it's already the only key. A shift-click adds the field as the next key, or reverses it when
+// it's already one of them.
+pub fn clicked_sort_keys(
+    keys: &[SortKey],
+    column: &PlaylistColumn,
+    is_added: bool,
+) -> Vec<SortKey> {
+    let mut keys = match is_added {
+        true => keys.to_vec(),
+        false => keys
+            .iter()
+            .filter(|key| keys.len() == 1 && key.sorts_by(column))
+            .cloned()
+            .collect(),
+    };
+
+    match keys.iter_mut().find(|key| key.sorts_by(column)) {
+        Some(key) => key.descending = !key.descending,
+        None => keys.push(SortKey {
+            field: column.field,
+            format: column.format.clone(),
+            descending: false,
+        }),
+    }
+
+    keys
+}
+
+// The positions of the tracks in the order the keys sort them in. Tracks the keys consider equal
+// keep their order.
+pub fn sorted_order(tracks: &[LibraryItem], keys: &[SortKey]) -> Vec<usize> {
+    let mut order = (0..tracks.len()).collect::<Vec<usize>>();
+
+    // The text of custom columns is formatted once for each track, not on every comparison.
+    let custom_texts = keys
+        .iter()
+        .map(|key| {
+            custom_title_format(key.field, &key.format).map(|title_format| {
+                tracks
+                    .iter()
+                    .map(|track| title_format.format(track).to_lowercase())
+                    .collect::<Vec<String>>()
+            })
+        })
+        .collect::<Vec<Option<Vec<String>>>>();
+
+    order.sort_by(|a, b| {
+        keys.iter()
+            .zip(&custom_texts)
+            .map(|(key, custom_texts)| {
+                let ordering = match custom_texts {
+                    Some(texts) => texts[*a].cmp(&texts[*b]),
+                    None => key.field.compare(&tracks[*a], &tracks[*b]),
+                };
+
+                match key.descending {
+                    true => ordering.reverse(),
+                    false => ordering,
+                }
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::LibraryPathId;
+    use std::path::PathBuf;
+
+    fn track(artist: &str, album: &str, track_number: u32) -> LibraryItem {
+        LibraryItem::new(
+            PathBuf::from(format!("{}-{}-{}", artist, album, track_number)),
+            LibraryPathId::new(0),
+        )
+        .set_artist(Some(artist))
+        .set_album(Some(album))
+        .set_track_number(Some(track_number))
+    }
+
+    #[test]
+    fn sorts_by_several_keys() {
+        let tracks = vec![
+            track("b", "x", 2),
+            track("A", "y", 1),
+            track("b", "x", 1),
+            track("a", "x", 3),
+        ];
+
+        let artist = PlaylistColumn::new(ColumnField::Artist);
+        let album = PlaylistColumn::new(ColumnField::Album);
+        let track_number = PlaylistColumn::new(ColumnField::TrackNumber);
+
+        let keys = clicked_sort_keys(&[], &artist, false);
+        let keys = clicked_sort_keys(&keys, &album, true);
+        let keys = clicked_sort_keys(&keys, &track_number, true);
+        assert_eq!(sorted_order(&tracks, &keys), vec![3, 1, 2, 0]);
+
+        // Reversing the album still keeps the artists in order.
+        let keys = clicked_sort_keys(&keys, &album, true);
+        assert_eq!(sorted_order(&tracks, &keys), vec![1, 3, 2, 0]);
+
+        // A plain click starts over with the one field, then reverses it.
+        let keys = clicked_sort_keys(&keys, &track_number, false);
+        assert_eq!(keys.len(), 1);
+        assert_eq!(sorted_order(&tracks, &keys), vec![1, 2, 0, 3]);
+
+        let keys = clicked_sort_keys(&keys, &track_number, false);
+        assert!(keys[0].descending);
+        assert_eq!(sorted_order(&tracks, &keys), vec![3, 0, 1, 2]);
+    }
+
+    #[test]
+    fn sorts_by_custom_columns() {
+        let tracks = vec![track("b", "x", 2), track("a", "y", 10), track("c", "x", 1)];
+
+        let album_track = PlaylistColumn::custom("Album #", "%album%$num(%tracknumber%,3)");
+        let artist = PlaylistColumn::custom("Artist", "%artist%");
+        assert_eq!(
+            album_track.title_format().unwrap().format(&tracks[1]),
+            "y010"
+        );
+
+        let keys = clicked_sort_keys(&[], &album_track, false);
+        assert_eq!(sorted_order(&tracks, &keys), vec![2, 0, 1]);
+
+        // Custom columns with different scripts are different keys.
+        let keys = clicked_sort_keys(&keys, &artist, true);
+        assert_eq!(keys.len(), 2);
+        let keys = clicked_sort_keys(&keys, &artist, false);
+        assert_eq!(sorted_order(&tracks, &keys), vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn shows_hides_and_moves_columns() {
+        let mut columns = PlaylistColumns::default();
+
+        columns.toggle(ColumnField::Year);
+        columns.toggle(ColumnField::Playing);
+        columns.move_column(0, false);
+        columns.move_column(0, true);
+
+        let fields = columns
+            .columns
+            .iter()
+            .map(|column| column.field)
+            .collect::<Vec<ColumnField>>();
+        assert_eq!(
+            fields,
+            vec![
+                ColumnField::Artist,
+                ColumnField::TrackNumber,
+                ColumnField::Album,
+                ColumnField::Title,
+                ColumnField::Genre,
+                ColumnField::Year,
+            ]
+        );
+        assert_eq!(columns.columns[5].align, ColumnAlign::Right);
+    }
+}
@@ -0,0 +1,513 @@
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use super::{LibraryItem, LibraryPathId, Playlist};
+
+// Characters that can't appear as-is in the path of a file URI.
+const PATH_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'[')
+    .add(b']')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}');
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    M3u,
+    Pls,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    pub const EXTENSIONS: [&'static str; 4] = ["m3u", "m3u8", "pls", "xspf"];
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+
+        match extension.as_str() {
+            "m3u" | "m3u8" => Some(Self::M3u),
+            "pls" => Some(Self::Pls),
+            "xspf" => Some(Self::Xspf),
+            _ => None,
+        }
+    }
+}
+
+// A track as it's written in a playlist file, before it's matched to the library.
+#[derive(Debug, Default, PartialEq)]
+struct Entry {
+    location: String,
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    track_number: Option<u32>,
+}
+
+/// Reads a playlist file. Entries are matched to the library items with the same path. Anything
+/// else becomes a placeholder with whatever the playlist knows about it, so no track file is read
+/// here; the caller reads the tags of the ones that exist.
+pub fn load(path: &Path, library_items: &[LibraryItem]) -> io::Result<Playlist> {
+    let format = PlaylistFormat::from_path(path).ok_or_else(unsupported_format)?;
+    let bytes = std::fs::read(path)?;
+    let text = decode_text(&bytes);
+
+    let (name, entries) = match format {
+        PlaylistFormat::M3u => parse_m3u(&text),
+        PlaylistFormat::Pls => parse_pls(&text),
+        PlaylistFormat::Xspf => parse_xspf(&text)?,
+    };
+
+    let base = path.parent().unwrap_or(Path::new(""));
+    let by_path = library_items
+        .iter()
+        .map(|item| (item.path(), item))
+        .collect::<HashMap<PathBuf, &LibraryItem>>();
+
+    let mut playlist = Playlist::new();
+    playlist.set_name(name.unwrap_or_else(|| {
+        path.file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }));
+
+    for entry in entries {
+        let track_path = resolve(base, &entry.location);
+
+        let track = match by_path.get(&track_path) {
+            Some(item) => (*item).clone(),
+            None => {
+                let library_id = LibraryPathId::from_path(track_path.parent().unwrap_or(base));
+
+                LibraryItem::new(track_path, library_id)
+                    .set_title(entry.title.as_deref())
+                    .set_artist(entry.artist.as_deref())
+                    .set_album(entry.album.as_deref())
+                    .set_track_number(entry.track_number)
+            }
+        };
+
+        playlist.add(track);
+    }
+
+    Ok(playlist)
+}
+
+/// Writes the playlist in the format of the file's extension. Tracks inside the playlist's folder
+/// are written relative to it, so the folder can be moved around as a whole.
+pub fn save(playlist: &Playlist, path: &Path) -> io::Result<()> {
+    let format = PlaylistFormat::from_path(path).ok_or_else(unsupported_format)?;
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    let entries = playlist
+        .tracks
+        .iter()
+        .map(|track| Entry {
+            location: relative_location(base, &track.path()),
+            title: track.title(),
+            artist: track.artist(),
+            album: track.album(),
+            track_number: track.track_number(),
+        })
+        .collect::<Vec<Entry>>();
+
+    let name = playlist.get_name();
+    let text = match format {
+        PlaylistFormat::M3u => write_m3u(name.as_deref(), &entries),
+        PlaylistFormat::Pls => write_pls(&entries),
+        PlaylistFormat::Xspf => write_xspf(name.as_deref(), &entries),
+    };
+
+    std::fs::write(path, text)
+}
+
+fn unsupported_format() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "unsupported playlist format")
+}
+
+// Playlists are UTF-8 nowadays, but older .m3u files are usually Latin-1.
+fn decode_text(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+fn parse_m3u(text: &str) -> (Option<String>, Vec<Entry>) {
+    let mut name = None;
+    let mut entries = Vec::new();
+    let mut info = Entry::default();
+
+    for line in text.lines().map(str::trim) {
+        if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            // The duration can be followed by attributes, the display title comes after the comma.
+            if let Some((_, display)) = extinf.split_once(',') {
+                (info.artist, info.title) = split_display_title(display);
+            }
+        } else if let Some(playlist_name) = line.strip_prefix("#PLAYLIST:") {
+            name = Some(playlist_name.trim().to_string());
+        } else if let Some(album) = line.strip_prefix("#EXTALB:") {
+            info.album = Some(album.trim().to_string());
+        } else if !line.is_empty() && !line.starts_with('#') {
+            info.location = line.to_string();
+            entries.push(std::mem::take(&mut info));
+        }
+    }
+
+    (name, entries)
+}
+
+fn write_m3u(name: Option<&str>, entries: &[Entry]) -> String {
+    let mut text = String::from("#EXTM3U\n");
+
+    if let Some(name) = name {
+        let _ = writeln!(text, "#PLAYLIST:{}", name);
+    }
+
+    for entry in entries {
+        // The duration isn't known without decoding the file, -1 tells players to find out.
+        let _ = writeln!(text, "#EXTINF:-1,{}", display_title(entry));
+
+        if let Some(album) = &entry.album {
+            let _ = writeln!(text, "#EXTALB:{}", album);
+        }
+
+        let _ = writeln!(text, "{}", entry.location);
+    }
+
+    text
+}
+
+fn parse_pls(text: &str) -> (Option<String>, Vec<Entry>) {
+    let mut entries = std::collections::BTreeMap::<u32, Entry>::new();
+
+    for line in text.lines().map(str::trim) {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+
+        let key = key.trim().to_lowercase();
+        let (field, number) = key.split_at(key.find(|c: char| c.is_ascii_digit()).unwrap_or(0));
+        let Ok(number) = number.parse::<u32>() else {
+            continue;
+        };
+
+        let entry = entries.entry(number).or_default();
+        match field {
+            "file" => entry.location = value.trim().to_string(),
+            "title" => (entry.artist, entry.title) = split_display_title(value),
+            _ => (),
+        }
+    }
+
+    let entries = entries
+        .into_values()
+        .filter(|entry| !entry.location.is_empty())
+        .collect();
+
+    (None, entries)
+}
+
+fn write_pls(entries: &[Entry]) -> String {
+    let mut text = String::from("[playlist]\n");
+
+    for (idx, entry) in entries.iter().enumerate() {
+        let number = idx + 1;
+        let _ = writeln!(text, "File{}={}", number, entry.location);
+        let _ = writeln!(text, "Title{}={}", number, display_title(entry));
+        let _ = writeln!(text, "Length{}=-1", number);
+    }
+
+    let _ = writeln!(text, "NumberOfEntries={}", entries.len());
+    let _ = writeln!(text, "Version=2");
+
+    text
+}
+
+fn parse_xspf(text: &str) -> io::Result<(Option<String>, Vec<Entry>)> {
+    let document = roxmltree::Document::parse(text)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    let child_text = |node: roxmltree::Node, name: &str| {
+        node.children()
+            .find(|child| child.tag_name().name() == name)
+            .and_then(|child| child.text())
+            .map(|text| text.trim().to_string())
+    };
+
+    let root = document.root_element();
+    let name = child_text(root, "title");
+
+    let entries = root
+        .descendants()
+        .filter(|node| node.tag_name().name() == "track")
+        .filter_map(|track| {
+            Some(Entry {
+                location: uri_to_location(&child_text(track, "location")?),
+                title: child_text(track, "title"),
+                artist: child_text(track, "creator"),
+                album: child_text(track, "album"),
+                track_number: child_text(track, "trackNum").and_then(|n| n.parse().ok()),
+            })
+        })
+        .collect();
+
+    Ok((name, entries))
+}
+
+fn write_xspf(name: Option<&str>, entries: &[Entry]) -> String {
+    let mut text = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    text.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+
+    if let Some(name) = name {
+        let _ = writeln!(text, "  <title>{}</title>", escape_xml(name));
+    }
+
+    text.push_str("  <trackList>\n");
+    for entry in entries {
+        text.push_str("    <track>\n");
+        let _ = writeln!(
+            text,
+            "      <location>{}</location>",
+            escape_xml(&location_to_uri(&entry.location))
+        );
+
+        let fields = [
+            ("title", entry.title.clone()),
+            ("creator", entry.artist.clone()),
+            ("album", entry.album.clone()),
+            ("trackNum", entry.track_number.map(|n| n.to_string())),
+        ];
+        for (tag, value) in fields {
+            if let Some(value) = value {
+                let _ = writeln!(text, "      <{tag}>{}</{tag}>", escape_xml(&value));
+            }
+        }
+
+        text.push_str("    </track>\n");
+    }
+    text.push_str("  </trackList>\n");
+    text.push_str("</playlist>\n");
+
+    text
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// Splits "Artist - Title", the usual display title of EXTINF and PLS entries.
+fn split_display_title(display: &str) -> (Option<String>, Option<String>) {
+    let display = display.trim();
+
+    match display.split_once(" - ") {
+        Some((artist, title)) => (Some(artist.to_string()), Some(title.to_string())),
+        None if display.is_empty() => (None, None),
+        None => (None, Some(display.to_string())),
+    }
+}
+
+fn display_title(entry: &Entry) -> String {
+    match (&entry.artist, &entry.title) {
+        (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+        (None, Some(title)) => title.clone(),
+        (Some(artist), None) => artist.clone(),
+        (None, None) => String::new(),
+    }
+}
+
+// Turns a location from a playlist into a path, relative locations being relative to the folder
+// of the playlist.
+fn resolve(base: &Path, location: &str) -> PathBuf {
+    let location = match location.strip_prefix("file://") {
+        Some(uri_path) => uri_to_path(uri_path),
+        None => location.to_string(),
+    };
+
+    // Playlists made on Windows use backslashes, which are part of the file name elsewhere.
+    let location = if cfg!(windows) {
+        location
+    } else {
+        location.replace('\\', "/")
+    };
+
+    normalize(&base.join(location))
+}
+
+// XSPF locations are URIs, relative ones being relative to the playlist like plain paths.
+fn uri_to_location(uri: &str) -> String {
+    match uri.strip_prefix("file://") {
+        Some(uri_path) => uri_to_path(uri_path),
+        None => percent_decode_str(uri).decode_utf8_lossy().to_string(),
+    }
+}
+
+fn uri_to_path(uri_path: &str) -> String {
+    let uri_path = uri_path.strip_prefix("localhost").unwrap_or(uri_path);
+    let decoded = percent_decode_str(uri_path).decode_utf8_lossy().to_string();
+
+    // file:///C:/Music is C:/Music, not /C:/Music.
+    match decoded.strip_prefix('/') {
+        Some(rest) if rest.as_bytes().get(1) == Some(&b':') => rest.to_string(),
+        _ => decoded,
+    }
+}
+
+fn location_to_uri(location: &str) -> String {
+    let location = location.replace('\\', "/");
+    let encoded = utf8_percent_encode(&location, PATH_ENCODE_SET).to_string();
+
+    if location.starts_with('/') {
+        format!("file://{}", encoded)
+    } else if location.as_bytes().get(1) == Some(&b':') {
+        format!("file:///{}", encoded)
+    } else {
+        encoded
+    }
+}
+
+// Removes the `.` and `..` in a path without touching the file system, which fails for missing
+// files.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push(component);
+                }
+            }
+            _ => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+fn relative_location(base: &Path, path: &Path) -> String {
+    path.strip_prefix(base)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "music-player-playlist-{}-{}",
+            name,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn reads_m3u_relative_to_the_playlist() {
+        let dir = temp_dir("m3u");
+        let playlist_path = dir.join("lists/mix.m3u");
+        std::fs::create_dir_all(dir.join("lists")).unwrap();
+
+        // Latin-1, with an EXTINF title and a path that goes up a folder.
+        let mut bytes = b"#EXTM3U\n#EXTINF:215,Bj\xF6rk - Joga\n..\\music\\joga.mp3\n".to_vec();
+        bytes.extend_from_slice(b"\n# a comment\n/elsewhere/gone.flac\n");
+        std::fs::write(&playlist_path, bytes).unwrap();
+
+        let known = LibraryItem::new(dir.join("music/joga.mp3"), LibraryPathId::new(7))
+            .set_title(Some("Jóga"))
+            .set_artist(Some("Björk"));
+        let playlist = load(&playlist_path, std::slice::from_ref(&known)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(playlist.get_name(), Some("mix".to_string()));
+        assert_eq!(playlist.tracks.len(), 2);
+        assert_eq!(playlist.tracks[0], known);
+        assert_eq!(playlist.tracks[0].library_id(), LibraryPathId::new(7));
+        assert_eq!(
+            playlist.tracks[1].path(),
+            PathBuf::from("/elsewhere/gone.flac")
+        );
+        assert_eq!(playlist.tracks[1].title(), None);
+    }
+
+    #[test]
+    fn keeps_missing_files_across_formats() {
+        let dir = temp_dir("round-trip");
+
+        let mut playlist = Playlist::new();
+        playlist.set_name("Road & Trip".to_string());
+        playlist.add(
+            LibraryItem::new(dir.join("sub dir/one #1.flac"), LibraryPathId::new(0))
+                .set_title(Some("One"))
+                .set_artist(Some("Band"))
+                .set_album(Some("First")),
+        );
+        playlist.add(
+            LibraryItem::new(PathBuf::from("/other/two.ogg"), LibraryPathId::new(0))
+                .set_title(Some("Two")),
+        );
+
+        for extension in PlaylistFormat::EXTENSIONS {
+            let path = dir.join(format!("trip.{}", extension));
+            save(&playlist, &path).unwrap();
+            let loaded = load(&path, &[]).unwrap();
+
+            let paths = loaded.tracks.iter().map(|t| t.path()).collect::<Vec<_>>();
+            assert_eq!(
+                paths,
+                vec![
+                    dir.join("sub dir/one #1.flac"),
+                    PathBuf::from("/other/two.ogg")
+                ],
+                "{}",
+                extension
+            );
+            assert_eq!(loaded.tracks[0].title(), Some("One".to_string()));
+            assert_eq!(loaded.tracks[0].artist(), Some("Band".to_string()));
+            assert_eq!(loaded.tracks[1].title(), Some("Two".to_string()));
+        }
+
+        let xspf = std::fs::read_to_string(dir.join("trip.xspf")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(xspf.contains("<title>Road &amp; Trip</title>"));
+        assert!(xspf.contains("<location>sub%20dir/one%20%231.flac</location>"));
+        assert!(xspf.contains("<location>file:///other/two.ogg</location>"));
+    }
+
+    #[test]
+    fn reads_pls_entries_in_order() {
+        let text = "[playlist]\nfile2=b.mp3\nFile1=a.mp3\nTitle1=A Song\nLength1=60\n\
+                    NumberOfEntries=2\nVersion=2\n";
+        let (_, entries) = parse_pls(text);
+
+        let locations = entries
+            .iter()
+            .map(|e| e.location.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(locations, vec!["a.mp3", "b.mp3"]);
+        assert_eq!(entries[0].title, Some("A Song".to_string()));
+    }
+}
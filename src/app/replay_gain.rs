@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+
+/// ReplayGain information of a track. Gains are in dB and peaks are linear sample amplitudes
+/// where 1.0 is full scale.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ReplayGain {
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+// Values are only ever parsed from tags or measured, and non-finite ones are rejected, so
+// equality is always reflexive.
+impl Eq for ReplayGain {}
+
+impl ReplayGain {
+    /// The linear scale to apply to the samples of this track for the given settings. Tracks
+    /// without any ReplayGain information play unchanged.
+    pub fn scale(&self, settings: &ReplayGainSettings) -> f32 {
+        let (gain, peak) = match settings.mode {
+            ReplayGainMode::Off => return 1.0,
+            ReplayGainMode::Track => (
+                self.track_gain.or(self.album_gain),
+                self.track_peak.or(self.album_peak),
+            ),
+            ReplayGainMode::Album => (
+                self.album_gain.or(self.track_gain),
+                self.album_peak.or(self.track_peak),
+            ),
+        };
+
+        let Some(gain) = gain else {
+            return 1.0;
+        };
+
+        let scale = db_to_linear(gain + settings.preamp_db);
+
+        match peak {
+            Some(peak) if settings.prevent_clipping && peak > 0.0 => scale.min(1.0 / peak),
+            _ => scale,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReplayGainMode {
+    #[default]
+    Off,
+    Track,
+    Album,
+}
+
+impl std::fmt::Display for ReplayGainMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ReplayGainMode::Off => write!(f, "Off"),
+            ReplayGainMode::Track => write!(f, "Track"),
+            ReplayGainMode::Album => write!(f, "Album"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ReplayGainSettings {
+    pub mode: ReplayGainMode,
+    // Extra gain in dB on top of the tagged gain.
+    pub preamp_db: f32,
+    // Lowers the gain of tracks whose peak would otherwise go over full scale.
+    pub prevent_clipping: bool,
+}
+
+impl Default for ReplayGainSettings {
+    fn default() -> Self {
+        Self {
+            mode: ReplayGainMode::Off,
+            preamp_db: 0.0,
+            prevent_clipping: true,
+        }
+    }
+}
+
+fn db_to_linear(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0)
+}
+
+/// Parses a gain tag like "-6.54 dB" or a peak tag like "0.988831".
+pub fn parse_tag_value(value: &str) -> Option<f32> {
+    let number = value.split_whitespace().next()?;
+    let number = number
+        .strip_suffix("dB")
+        .or_else(|| number.strip_suffix("db"))
+        .unwrap_or(number);
+
+    number.parse::<f32>().ok().filter(|value| value.is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(mode: ReplayGainMode) -> ReplayGainSettings {
+        ReplayGainSettings {
+            mode,
+            preamp_db: 0.0,
+            prevent_clipping: false,
+        }
+    }
+
+    #[test]
+    fn parses_tag_values() {
+        assert_eq!(parse_tag_value("-6.54 dB"), Some(-6.54));
+        assert_eq!(parse_tag_value("+2.10dB"), Some(2.1));
+        assert_eq!(parse_tag_value(" 0.988831 "), Some(0.988831));
+        assert_eq!(parse_tag_value("NaN"), None);
+        assert_eq!(parse_tag_value(""), None);
+    }
+
+    #[test]
+    fn scales_by_mode_with_fallback() {
+        let gain = ReplayGain {
+            track_gain: Some(-6.0),
+            track_peak: None,
+            album_gain: Some(-20.0),
+            album_peak: None,
+        };
+
+        assert_eq!(gain.scale(&settings(ReplayGainMode::Off)), 1.0);
+        assert!((gain.scale(&settings(ReplayGainMode::Track)) - 0.501187).abs() < 1e-5);
+        assert!((gain.scale(&settings(ReplayGainMode::Album)) - 0.1).abs() < 1e-6);
+
+        let track_only = ReplayGain {
+            album_gain: None,
+            ..gain
+        };
+        assert_eq!(
+            track_only.scale(&settings(ReplayGainMode::Album)),
+            track_only.scale(&settings(ReplayGainMode::Track))
+        );
+
+        assert_eq!(
+            ReplayGain::default().scale(&settings(ReplayGainMode::Track)),
+            1.0
+        );
+    }
+
+    #[test]
+    fn prevents_clipping_using_the_peak() {
+        let gain = ReplayGain {
+            track_gain: Some(6.0),
+            track_peak: Some(0.8),
+            ..Default::default()
+        };
+
+        let mut settings = settings(ReplayGainMode::Track);
+        assert!(gain.scale(&settings) > 1.25);
+
+        settings.prevent_clipping = true;
+        assert_eq!(gain.scale(&settings), 1.25);
+
+        // The preamp is applied before the peak limit.
+        settings.preamp_db = -12.0;
+        assert!((gain.scale(&settings) - 0.501187).abs() < 1e-5);
+    }
+}
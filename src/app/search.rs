@@ -0,0 +1,545 @@
+use std::collections::{BTreeMap, HashSet};
+
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+use super::{Library, LibraryItem};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Field {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Year,
+    Path,
+    Length,
+    Bitrate,
+    SampleRate,
+    Channels,
+    BitDepth,
+    Codec,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "title" => Some(Field::Title),
+            "artist" => Some(Field::Artist),
+            "album" => Some(Field::Album),
+            "genre" => Some(Field::Genre),
+            "year" | "date" => Some(Field::Year),
+            "path" | "file" => Some(Field::Path),
+            "length" | "duration" => Some(Field::Length),
+            "bitrate" => Some(Field::Bitrate),
+            "samplerate" => Some(Field::SampleRate),
+            "channels" => Some(Field::Channels),
+            "bitdepth" | "bits" => Some(Field::BitDepth),
+            "codec" => Some(Field::Codec),
+            _ => None,
+        }
+    }
+
+    // The number a term on the field is compared to, ex. 245 seconds for "length>4:05".
+    fn parse_number(&self, value: &str) -> Option<i32> {
+        match self {
+            Field::Length => value.split(':').try_fold(0, |seconds, part| {
+                Some(seconds * 60 + part.parse::<i32>().ok()?)
+            }),
+            Field::Bitrate | Field::SampleRate | Field::Channels | Field::BitDepth => {
+                value.parse().ok()
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Lower cases the text and strips diacritics, so "Sigur Rós" and "sigur ros" are the same.
+pub fn normalize(text: &str) -> String {
+    text.nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn tokenize(normalized: &str) -> impl Iterator<Item = &str> {
+    normalized
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    fn matches(&self, value: i32, target: i32) -> bool {
+        match self {
+            Comparison::Less => value < target,
+            Comparison::LessOrEqual => value <= target,
+            Comparison::Equal => value == target,
+            Comparison::GreaterOrEqual => value >= target,
+            Comparison::Greater => value > target,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Term {
+    /// Words that must all appear, in any field when `field` is `None`. The last word of a
+    /// phrase and every word outside one also match as prefixes, to search while typing.
+    Text {
+        field: Option<Field>,
+        words: Vec<String>,
+        is_phrase: bool,
+    },
+    Year(Comparison, i32),
+    /// A technical property of the audio compared to a number, ex. `bitrate>=320`. Lengths are
+    /// in seconds.
+    Number(Field, Comparison, i32),
+}
+
+/// A search like `artist:"boards of canada" year>1998 genre:ambient`. Every term has to match.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    pub terms: Vec<Term>,
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Self {
+        let terms = split_terms(input)
+            .iter()
+            .filter_map(|raw| parse_term(raw))
+            .collect();
+
+        Self { terms }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+}
+
+// Splits on whitespace outside of double quotes.
+fn split_terms(input: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+
+    for c in input.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                current.push(c);
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    terms.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+
+    if !current.is_empty() {
+        terms.push(current);
+    }
+
+    terms
+}
+
+fn parse_term(raw: &str) -> Option<Term> {
+    // A field name is only recognised before any quote, ex. `"re:member"` is plain text.
+    let operator_start = raw
+        .find([':', '<', '>', '=', '"'])
+        .filter(|idx| !raw[*idx..].starts_with('"'));
+
+    let (field, comparison, value) = match operator_start {
+        Some(idx) => match Field::from_name(&raw[..idx]) {
+            Some(field) => {
+                let rest = &raw[idx..];
+                let (comparison, len) = [
+                    ("<=", Comparison::LessOrEqual),
+                    (">=", Comparison::GreaterOrEqual),
+                    ("<", Comparison::Less),
+                    (">", Comparison::Greater),
+                    ("=", Comparison::Equal),
+                    (":", Comparison::Equal),
+                ]
+                .into_iter()
+                .find(|(operator, _)| rest.starts_with(operator))
+                .map(|(operator, comparison)| (comparison, operator.len()))?;
+
+                (Some(field), comparison, &rest[len..])
+            }
+            None => (None, Comparison::Equal, raw),
+        },
+        None => (None, Comparison::Equal, raw),
+    };
+
+    let is_phrase = value.starts_with('"');
+    let value = value.trim_matches('"');
+
+    if field == Some(Field::Year) {
+        if let Ok(year) = value.trim().parse::<i32>() {
+            return Some(Term::Year(comparison, year));
+        }
+    }
+
+    if let Some(field) = field {
+        if let Some(number) = field.parse_number(value.trim()) {
+            return Some(Term::Number(field, comparison, number));
+        }
+    }
+
+    let normalized = normalize(value);
+    let words = tokenize(&normalized)
+        .map(str::to_string)
+        .collect::<Vec<String>>();
+
+    if words.is_empty() {
+        return None;
+    }
+
+    Some(Term::Text {
+        field,
+        words,
+        is_phrase,
+    })
+}
+
+#[derive(Debug, Clone)]
+struct Document {
+    // The normalized words of each searchable field.
+    fields: Vec<(Field, Vec<String>)>,
+    // The fields that are compared as numbers.
+    numbers: Vec<(Field, i32)>,
+}
+
+impl Document {
+    fn new(item: &LibraryItem) -> Self {
+        let text_fields = [
+            (Field::Title, item.title()),
+            (Field::Artist, item.artist()),
+            (Field::Album, item.album()),
+            (Field::Genre, item.genre()),
+            (Field::Year, item.year().map(|year| year.to_string())),
+            (
+                Field::Path,
+                Some(item.path().to_string_lossy().into_owned()),
+            ),
+            (Field::Codec, item.properties().codec.clone()),
+        ];
+
+        let fields = text_fields
+            .into_iter()
+            .filter_map(|(field, text)| {
+                let normalized = normalize(&text?);
+                let words = tokenize(&normalized).map(str::to_string).collect();
+                Some((field, words))
+            })
+            .collect();
+
+        let properties = item.properties();
+        let numbers = [
+            (Field::Year, item.year()),
+            (
+                Field::Length,
+                properties
+                    .duration_millis
+                    .map(|millis| (millis / 1000) as i32),
+            ),
+            (Field::Bitrate, properties.bitrate.map(|kbps| kbps as i32)),
+            (
+                Field::SampleRate,
+                properties.sample_rate.map(|hz| hz as i32),
+            ),
+            (Field::Channels, properties.channels.map(|n| n as i32)),
+            (
+                Field::BitDepth,
+                properties.bits_per_sample.map(|bits| bits as i32),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(field, number)| Some((field, number?)))
+        .collect();
+
+        Self { fields, numbers }
+    }
+
+    fn number(&self, field: Field) -> Option<i32> {
+        self.numbers
+            .iter()
+            .find(|(f, _)| *f == field)
+            .map(|(_, number)| *number)
+    }
+
+    // Whether the phrase appears as consecutive words, the last one possibly unfinished.
+    fn contains_phrase(&self, field: Option<Field>, phrase: &[String]) -> bool {
+        self.fields
+            .iter()
+            .filter(|(f, _)| field.is_none_or(|field| field == *f))
+            .any(|(_, words)| {
+                words.windows(phrase.len()).any(|window| {
+                    let (last, rest) = phrase.split_last().unwrap();
+                    window[..rest.len()] == *rest && window[rest.len()].starts_with(last.as_str())
+                })
+            })
+    }
+}
+
+/// An inverted index from every word of the searchable fields to the items containing it.
+/// Items are identified by their position in `Library::items`.
+#[derive(Debug, Clone, Default)]
+pub struct SearchIndex {
+    documents: Vec<Document>,
+    postings: BTreeMap<String, Vec<(usize, Field)>>,
+}
+
+impl SearchIndex {
+    pub fn new(items: &[LibraryItem]) -> Self {
+        let mut index = Self::default();
+        for item in items {
+            index.add(item);
+        }
+        index
+    }
+
+    pub fn add(&mut self, item: &LibraryItem) {
+        let id = self.documents.len();
+        let document = Document::new(item);
+
+        for (field, words) in &document.fields {
+            for word in words {
+                let postings = self.postings.entry(word.clone()).or_default();
+                if postings.last() != Some(&(id, *field)) {
+                    postings.push((id, *field));
+                }
+            }
+        }
+
+        self.documents.push(document);
+    }
+
+    /// Positions of the matching items, in library order.
+    pub fn search(&self, query: &Query) -> Vec<usize> {
+        let mut matches: Option<HashSet<usize>> = None;
+
+        for term in &query.terms {
+            if let Term::Text {
+                field,
+                words,
+                is_phrase,
+            } = term
+            {
+                for word in words {
+                    let with_word = self.documents_with_prefix(word, *field);
+                    matches = Some(match matches {
+                        Some(matches) => matches.intersection(&with_word).copied().collect(),
+                        None => with_word,
+                    });
+                }
+
+                if *is_phrase && words.len() > 1 {
+                    if let Some(matches) = matches.as_mut() {
+                        matches.retain(|id| self.documents[*id].contains_phrase(*field, words));
+                    }
+                }
+            }
+        }
+
+        let mut results = match matches {
+            Some(matches) => matches.into_iter().collect::<Vec<usize>>(),
+            None => (0..self.documents.len()).collect(),
+        };
+
+        for term in &query.terms {
+            let (field, comparison, target) = match term {
+                Term::Year(comparison, year) => (Field::Year, comparison, year),
+                Term::Number(field, comparison, number) => (*field, comparison, number),
+                Term::Text { .. } => continue,
+            };
+
+            results.retain(|id| {
+                self.documents[*id]
+                    .number(field)
+                    .is_some_and(|number| comparison.matches(number, *target))
+            });
+        }
+
+        results.sort_unstable();
+        results
+    }
+
+    fn documents_with_prefix(&self, prefix: &str, field: Option<Field>) -> HashSet<usize> {
+        self.postings
+            .range(prefix.to_string()..)
+            .take_while(|(word, _)| word.starts_with(prefix))
+            .flat_map(|(_, postings)| postings.iter())
+            .filter(|(_, f)| field.is_none_or(|field| field == *f))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+}
+
+/// The search box of the library panel. Results are only searched again when the query or the
+/// library changes.
+#[derive(Debug, Default)]
+pub struct LibrarySearch {
+    pub query: String,
+    pub results: Vec<LibraryItem>,
+    searched: Option<(String, u64)>,
+}
+
+impl LibrarySearch {
+    pub fn is_active(&self) -> bool {
+        !self.query.trim().is_empty()
+    }
+
+    pub fn update(&mut self, library: &mut Library) {
+        let current = (self.query.clone(), library.revision());
+
+        if self.searched.as_ref() != Some(&current) {
+            self.results = library.search(&Query::parse(&self.query));
+            self.searched = Some(current);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::library::AudioProperties;
+    use crate::app::LibraryPathId;
+    use std::path::PathBuf;
+
+    fn item(title: &str, artist: &str, album: &str, year: i32, genre: &str) -> LibraryItem {
+        let properties = AudioProperties {
+            duration_millis: Some(title.chars().count() as u64 * 30_000),
+            bitrate: Some(year as u32 / 2),
+            codec: Some("FLAC".to_string()),
+            ..AudioProperties::default()
+        };
+
+        LibraryItem::new(
+            PathBuf::from(format!("/music/{}/{}.flac", artist, title)),
+            LibraryPathId::new(0),
+        )
+        .set_title(Some(title))
+        .set_artist(Some(artist))
+        .set_album(Some(album))
+        .set_year(Some(year))
+        .set_genre(Some(genre))
+        .set_properties(properties)
+    }
+
+    fn index() -> SearchIndex {
+        SearchIndex::new(&[
+            item(
+                "Roygbiv",
+                "Boards of Canada",
+                "Music Has the Right to Children",
+                1998,
+                "Electronic",
+            ),
+            item(
+                "Dayvan Cowboy",
+                "Boards of Canada",
+                "The Campfire Headphase",
+                2005,
+                "Ambient",
+            ),
+            item("Hoppípolla", "Sigur Rós", "Takk...", 2005, "Post-Rock"),
+            item("An Ending (Ascent)", "Brian Eno", "Apollo", 1983, "Ambient"),
+            item("Canada", "Low", "Trust", 2002, "Slowcore"),
+        ])
+    }
+
+    fn search(query: &str) -> Vec<usize> {
+        index().search(&Query::parse(query))
+    }
+
+    #[test]
+    fn normalizes_case_and_diacritics() {
+        assert_eq!(
+            normalize("Sigur Rós - HOPPÍPOLLA"),
+            "sigur ros - hoppipolla"
+        );
+        assert_eq!(search("sigur ros"), [2]);
+        assert_eq!(search("HOPPÍP"), [2]);
+    }
+
+    #[test]
+    fn parses_field_queries() {
+        let query = Query::parse(r#"artist:"boards of canada" year>1998 genre:ambient eno"#);
+
+        assert_eq!(
+            query.terms,
+            [
+                Term::Text {
+                    field: Some(Field::Artist),
+                    words: vec!["boards".into(), "of".into(), "canada".into()],
+                    is_phrase: true,
+                },
+                Term::Year(Comparison::Greater, 1998),
+                Term::Text {
+                    field: Some(Field::Genre),
+                    words: vec!["ambient".into()],
+                    is_phrase: false,
+                },
+                Term::Text {
+                    field: None,
+                    words: vec!["eno".into()],
+                    is_phrase: false,
+                },
+            ]
+        );
+
+        // Unknown fields are searched as plain text.
+        assert_eq!(
+            Query::parse("re:member").terms,
+            [Term::Text {
+                field: None,
+                words: vec!["re".into(), "member".into()],
+                is_phrase: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn matches_fields_phrases_and_years() {
+        assert_eq!(
+            search(r#"artist:"boards of canada" year>1998 genre:ambient"#),
+            [1]
+        );
+        assert_eq!(search("canada"), [0, 1, 4]);
+        assert_eq!(search("title:canada"), [4]);
+        assert_eq!(search(r#""of canada""#), [0, 1]);
+        assert_eq!(search(r#""canada of""#), Vec::<usize>::new());
+        assert_eq!(search("year<=1998"), [0, 3]);
+        assert_eq!(search("year:2005 genre:post"), [2]);
+        assert_eq!(search("path:eno"), [3]);
+        assert_eq!(search("").len(), 5);
+    }
+
+    #[test]
+    fn matches_audio_properties() {
+        assert_eq!(
+            Query::parse("length>=4:30 bitrate<1000").terms,
+            [
+                Term::Number(Field::Length, Comparison::GreaterOrEqual, 270),
+                Term::Number(Field::Bitrate, Comparison::Less, 1000),
+            ]
+        );
+
+        // Every character of the title is 30 seconds of the track.
+        assert_eq!(search("length>=5:00"), [1, 2, 3]);
+        assert_eq!(search("duration<300 canada"), [0, 4]);
+        assert_eq!(search("bitrate<1000 codec:flac"), [0, 3]);
+        assert_eq!(search("codec:mp3"), Vec::<usize>::new());
+    }
+}
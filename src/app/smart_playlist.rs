@@ -0,0 +1,437 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+use super::library::LibraryItem;
+use super::search::normalize;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// The rules a smart playlist picks its tracks from the library with, ex. "genre is Jazz and
+/// year < 1970", and how the tracks are sorted and limited.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SmartPlaylist {
+    pub rules: Vec<Rule>,
+    // Whether a track has to match every rule or only one of them.
+    pub match_all: bool,
+    pub sort_by: Option<RuleField>,
+    pub descending: bool,
+    pub limit: Option<usize>,
+}
+
+impl Default for SmartPlaylist {
+    fn default() -> Self {
+        Self {
+            rules: vec![Rule::new(RuleField::Genre)],
+            match_all: true,
+            sort_by: None,
+            descending: false,
+            limit: None,
+        }
+    }
+}
+
+impl SmartPlaylist {
+    /// The library items matching the rules, sorted and limited. `now` is in seconds since the
+    /// Unix epoch, for the rules about dates.
+    pub fn tracks(&self, items: &[LibraryItem], now: u64) -> Vec<LibraryItem> {
+        let mut tracks = items
+            .iter()
+            .filter(|item| self.matches(item, now))
+            .cloned()
+            .collect::<Vec<LibraryItem>>();
+
+        if let Some(sort_by) = self.sort_by {
+            tracks.sort_by(|a, b| match self.descending {
+                true => sort_by.compare(b, a),
+                false => sort_by.compare(a, b),
+            });
+        }
+
+        if let Some(limit) = self.limit {
+            tracks.truncate(limit);
+        }
+
+        tracks
+    }
+
+    /// Whether the tracks change with time alone, through a rule like "added in the last 7 days".
+    pub fn depends_on_time(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.field.kind() == FieldKind::Date)
+    }
+
+    fn matches(&self, item: &LibraryItem, now: u64) -> bool {
+        if self.match_all {
+            self.rules.iter().all(|rule| rule.matches(item, now))
+        } else {
+            self.rules.iter().any(|rule| rule.matches(item, now))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    pub field: RuleField,
+    pub operator: RuleOperator,
+    // Text, a number, or a number of days depending on the field. Rules with a value that
+    // isn't a number when it should be match nothing.
+    pub value: String,
+}
+
+impl Rule {
+    pub fn new(field: RuleField) -> Self {
+        Self {
+            field,
+            operator: field.operators()[0],
+            value: String::new(),
+        }
+    }
+
+    // Keeps the operator when the new field has it, ex. "is" going from genre to year.
+    pub fn set_field(&mut self, field: RuleField) {
+        self.field = field;
+
+        if !field.operators().contains(&self.operator) {
+            self.operator = field.operators()[0];
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        match self.field.kind() {
+            FieldKind::Text => true,
+            FieldKind::Number => self.value.trim().parse::<i64>().is_ok(),
+            FieldKind::Date => self.value.trim().parse::<u64>().is_ok(),
+        }
+    }
+
+    fn matches(&self, item: &LibraryItem, now: u64) -> bool {
+        match self.field.kind() {
+            FieldKind::Text => {
+                let text = self.field.text(item).map(|t| normalize(&t));
+                let value = normalize(self.value.trim());
+
+                match self.operator {
+                    RuleOperator::Is => text.is_some_and(|text| text == value),
+                    RuleOperator::IsNot => text.is_none_or(|text| text != value),
+                    RuleOperator::Contains => text.is_some_and(|text| text.contains(&value)),
+                    RuleOperator::DoesNotContain => text.is_none_or(|text| !text.contains(&value)),
+                    _ => false,
+                }
+            }
+            FieldKind::Number => {
+                let Ok(value) = self.value.trim().parse::<i64>() else {
+                    return false;
+                };
+                let number = self.field.number(item);
+
+                match self.operator {
+                    RuleOperator::Is => number == Some(value),
+                    RuleOperator::IsNot => number != Some(value),
+                    RuleOperator::LessThan => number.is_some_and(|number| number < value),
+                    RuleOperator::GreaterThan => number.is_some_and(|number| number > value),
+                    _ => false,
+                }
+            }
+            FieldKind::Date => {
+                let Ok(days) = self.value.trim().parse::<u64>() else {
+                    return false;
+                };
+                let since = now.saturating_sub(days.saturating_mul(SECONDS_PER_DAY)) as i64;
+                let is_recent = self.field.number(item).is_some_and(|date| date >= since);
+
+                match self.operator {
+                    RuleOperator::InTheLast => is_recent,
+                    RuleOperator::NotInTheLast => !is_recent,
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FieldKind {
+    Text,
+    Number,
+    Date,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RuleField {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Year,
+    TrackNumber,
+    PlayCount,
+    DateAdded,
+    LastPlayed,
+}
+
+impl RuleField {
+    pub const ALL: [RuleField; 9] = [
+        RuleField::Title,
+        RuleField::Artist,
+        RuleField::Album,
+        RuleField::Genre,
+        RuleField::Year,
+        RuleField::TrackNumber,
+        RuleField::PlayCount,
+        RuleField::DateAdded,
+        RuleField::LastPlayed,
+    ];
+
+    pub fn operators(&self) -> &'static [RuleOperator] {
+        match self.kind() {
+            FieldKind::Text => &[
+                RuleOperator::Is,
+                RuleOperator::IsNot,
+                RuleOperator::Contains,
+                RuleOperator::DoesNotContain,
+            ],
+            FieldKind::Number => &[
+                RuleOperator::Is,
+                RuleOperator::IsNot,
+                RuleOperator::LessThan,
+                RuleOperator::GreaterThan,
+            ],
+            FieldKind::Date => &[RuleOperator::InTheLast, RuleOperator::NotInTheLast],
+        }
+    }
+
+    // What the value of a rule on this field is, to show next to it.
+    pub fn unit(&self) -> Option<&'static str> {
+        match self.kind() {
+            FieldKind::Date => Some("days"),
+            _ => None,
+        }
+    }
+
+    fn kind(&self) -> FieldKind {
+        match self {
+            RuleField::Title | RuleField::Artist | RuleField::Album | RuleField::Genre => {
+                FieldKind::Text
+            }
+            RuleField::Year | RuleField::TrackNumber | RuleField::PlayCount => FieldKind::Number,
+            RuleField::DateAdded | RuleField::LastPlayed => FieldKind::Date,
+        }
+    }
+
+    fn text(&self, item: &LibraryItem) -> Option<String> {
+        match self {
+            RuleField::Title => item.title(),
+            RuleField::Artist => item.artist(),
+            RuleField::Album => item.album(),
+            RuleField::Genre => item.genre(),
+            _ => None,
+        }
+    }
+
+    fn number(&self, item: &LibraryItem) -> Option<i64> {
+        match self {
+            RuleField::Year => item.year().map(i64::from),
+            RuleField::TrackNumber => item.track_number().map(i64::from),
+            RuleField::PlayCount => Some(i64::from(item.play_count())),
+            RuleField::DateAdded => item.date_added().map(|date| date as i64),
+            RuleField::LastPlayed => item.last_played().map(|date| date as i64),
+            _ => None,
+        }
+    }
+
+    // Tracks without a value go first, the same as the empty string or the lowest number.
+    fn compare(&self, a: &LibraryItem, b: &LibraryItem) -> Ordering {
+        match self.kind() {
+            FieldKind::Text => self
+                .text(a)
+                .map(|t| normalize(&t))
+                .cmp(&self.text(b).map(|t| normalize(&t))),
+            FieldKind::Number | FieldKind::Date => self.number(a).cmp(&self.number(b)),
+        }
+    }
+}
+
+impl std::fmt::Display for RuleField {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RuleField::Title => write!(f, "Title"),
+            RuleField::Artist => write!(f, "Artist"),
+            RuleField::Album => write!(f, "Album"),
+            RuleField::Genre => write!(f, "Genre"),
+            RuleField::Year => write!(f, "Year"),
+            RuleField::TrackNumber => write!(f, "Track Number"),
+            RuleField::PlayCount => write!(f, "Play Count"),
+            RuleField::DateAdded => write!(f, "Date Added"),
+            RuleField::LastPlayed => write!(f, "Last Played"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RuleOperator {
+    Is,
+    IsNot,
+    Contains,
+    DoesNotContain,
+    LessThan,
+    GreaterThan,
+    InTheLast,
+    NotInTheLast,
+}
+
+impl std::fmt::Display for RuleOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RuleOperator::Is => write!(f, "is"),
+            RuleOperator::IsNot => write!(f, "is not"),
+            RuleOperator::Contains => write!(f, "contains"),
+            RuleOperator::DoesNotContain => write!(f, "does not contain"),
+            RuleOperator::LessThan => write!(f, "is less than"),
+            RuleOperator::GreaterThan => write!(f, "is greater than"),
+            RuleOperator::InTheLast => write!(f, "is in the last"),
+            RuleOperator::NotInTheLast => write!(f, "is not in the last"),
+        }
+    }
+}
+
+/// A smart playlist being edited in the rule builder, which only replaces the playlist's rules
+/// once it's saved.
+#[derive(Debug, Clone)]
+pub struct SmartPlaylistDraft {
+    // The playlist being edited, or `None` for a new one.
+    pub playlist_idx: Option<usize>,
+    pub name: String,
+    pub smart_playlist: SmartPlaylist,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::LibraryPathId;
+    use std::path::PathBuf;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn item(name: &str, genre: &str, year: i32) -> LibraryItem {
+        LibraryItem::new(
+            PathBuf::from(format!("/music/{}.flac", name)),
+            LibraryPathId::new(0),
+        )
+        .set_title(Some(name))
+        .set_genre(Some(genre))
+        .set_year(Some(year))
+    }
+
+    fn rule(field: RuleField, operator: RuleOperator, value: &str) -> Rule {
+        Rule {
+            field,
+            operator,
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn matches_all_or_any_rule() {
+        let items = [
+            item("So What", "Jazz", 1959),
+            item("Giant Steps", "jazz", 1960),
+            item("Bitches Brew", "Jazz", 1970),
+            item("Paranoid", "Metal", 1970),
+        ];
+
+        let mut smart_playlist = SmartPlaylist {
+            rules: vec![
+                rule(RuleField::Genre, RuleOperator::Is, "Jazz"),
+                rule(RuleField::Year, RuleOperator::LessThan, "1970"),
+            ],
+            ..Default::default()
+        };
+
+        let titles = |tracks: Vec<LibraryItem>| {
+            tracks
+                .iter()
+                .map(|track| track.title().unwrap())
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(
+            titles(smart_playlist.tracks(&items, NOW)),
+            ["So What", "Giant Steps"]
+        );
+
+        smart_playlist.match_all = false;
+        smart_playlist.sort_by = Some(RuleField::Year);
+        smart_playlist.descending = true;
+        smart_playlist.limit = Some(2);
+        assert_eq!(
+            titles(smart_playlist.tracks(&items, NOW)),
+            ["Bitches Brew", "Giant Steps"]
+        );
+
+        smart_playlist.rules[1].value = "not a year".to_string();
+        smart_playlist.match_all = true;
+        assert!(!smart_playlist.rules[1].is_valid());
+        assert!(smart_playlist.tracks(&items, NOW).is_empty());
+    }
+
+    #[test]
+    fn matches_play_counts_and_dates() {
+        let days_ago = |days: u64| NOW - days * SECONDS_PER_DAY;
+
+        let recent = item("Recent", "Pop", 2020).set_date_added(Some(days_ago(3)));
+        let old = item("Old", "Pop", 2020)
+            .set_date_added(Some(days_ago(90)))
+            .played(days_ago(1));
+        let unknown = item("Unknown", "Pop", 2020);
+        let items = [recent.clone(), old.clone(), unknown.clone()];
+
+        let added_recently = SmartPlaylist {
+            rules: vec![rule(RuleField::DateAdded, RuleOperator::InTheLast, "30")],
+            ..Default::default()
+        };
+        assert_eq!(
+            added_recently.tracks(&items, NOW),
+            std::slice::from_ref(&recent)
+        );
+
+        let never_played = SmartPlaylist {
+            rules: vec![rule(RuleField::PlayCount, RuleOperator::Is, "0")],
+            ..Default::default()
+        };
+        assert_eq!(
+            never_played.tracks(&items, NOW),
+            [recent.clone(), unknown.clone()]
+        );
+
+        let not_played_lately = SmartPlaylist {
+            rules: vec![rule(RuleField::LastPlayed, RuleOperator::NotInTheLast, "7")],
+            ..Default::default()
+        };
+        assert!(!not_played_lately.tracks(&items, NOW).contains(&old));
+        assert!(not_played_lately.depends_on_time());
+        assert!(!never_played.depends_on_time());
+
+        // Far more days than there are seconds since the epoch.
+        let added_ever = SmartPlaylist {
+            rules: vec![rule(
+                RuleField::DateAdded,
+                RuleOperator::InTheLast,
+                &u64::MAX.to_string(),
+            )],
+            ..Default::default()
+        };
+        assert_eq!(added_ever.tracks(&items, NOW), [recent, old]);
+    }
+
+    #[test]
+    fn keeps_the_operator_when_it_fits_the_new_field() {
+        let mut rule = rule(RuleField::Genre, RuleOperator::Is, "Jazz");
+
+        rule.set_field(RuleField::Year);
+        assert_eq!(rule.operator, RuleOperator::Is);
+
+        rule.set_field(RuleField::DateAdded);
+        assert_eq!(rule.operator, RuleOperator::InTheLast);
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use super::{media, LibraryItem};
+
+/// Shown for a tag the tracks being edited don't agree on. Leaving it in place keeps each track's
+/// own value.
+pub const MULTIPLE_VALUES: &str = "<multiple>";
+
+/// The tracks open in the tag editor, and the text of each of their tags.
+pub struct TagEditDraft {
+    pub tracks: Vec<LibraryItem>,
+    pub values: Vec<(TagField, String)>,
+    // The tracks as they were opened, to tell which ones were edited.
+    originals: Vec<LibraryItem>,
+}
+
+impl TagEditDraft {
+    pub fn new(tracks: Vec<LibraryItem>) -> Self {
+        let mut draft = Self {
+            originals: tracks.clone(),
+            tracks,
+            values: Vec::new(),
+        };
+
+        draft.refresh_values();
+        draft
+    }
+
+    // Sets the tags the text was entered for on every track.
+    pub fn apply_values(&mut self) {
+        for (field, value) in &self.values {
+            if value == MULTIPLE_VALUES {
+                continue;
+            }
+
+            for track in self.tracks.iter_mut() {
+                field.set(track, value);
+            }
+        }
+    }
+
+    // Shows the tags of the tracks again, after they were changed all at once.
+    fn refresh_values(&mut self) {
+        self.values = TagField::ALL
+            .iter()
+            .map(|field| {
+                let mut values = self.tracks.iter().map(|track| field.get(track));
+                let first = values.next().unwrap_or_default();
+
+                match values.all(|value| value == first) {
+                    true => (*field, first),
+                    false => (*field, MULTIPLE_VALUES.to_string()),
+                }
+            })
+            .collect();
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.values
+            .iter()
+            .all(|(field, value)| value == MULTIPLE_VALUES || field.is_valid(value))
+    }
+
+    // Numbers the tracks in the order they are listed, starting again from 1 for each album.
+    pub fn number_tracks(&mut self) {
+        self.apply_values();
+
+        let mut next_numbers = HashMap::new();
+        for track in self.tracks.iter_mut() {
+            let next_number = next_numbers.entry(track.album()).or_insert(1);
+            track.set_track_number(Some(*next_number));
+            *next_number += 1;
+        }
+
+        self.refresh_values();
+    }
+
+    pub fn fix_case(&mut self, case_style: CaseStyle) {
+        self.apply_values();
+
+        for track in self.tracks.iter_mut() {
+            for field in TagField::ALL.iter().filter(|field| !field.is_numeric()) {
+                let value = case_style.apply(&field.get(track));
+                field.set(track, &value);
+            }
+        }
+
+        self.refresh_values();
+    }
+
+    // How many of the tracks are in files their tags can't be written to.
+    pub fn unwritable_count(&self) -> usize {
+        self.tracks
+            .iter()
+            .filter(|track| !media::supports_tag_writing(&track.path()))
+            .count()
+    }
+
+    // The edited tracks whose tags can be written to their files.
+    pub fn edited_tracks(mut self) -> Vec<LibraryItem> {
+        self.apply_values();
+
+        self.tracks
+            .into_iter()
+            .zip(self.originals)
+            .filter(|(track, original)| {
+                TagField::ALL
+                    .iter()
+                    .any(|field| field.get(track) != field.get(original))
+            })
+            .map(|(track, _)| track)
+            .filter(|track| media::supports_tag_writing(&track.path()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TagField {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Year,
+    TrackNumber,
+}
+
+impl TagField {
+    pub const ALL: [TagField; 6] = [
+        TagField::Title,
+        TagField::Artist,
+        TagField::Album,
+        TagField::Genre,
+        TagField::Year,
+        TagField::TrackNumber,
+    ];
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, TagField::Year | TagField::TrackNumber)
+    }
+
+    // The tag as text, empty when the track doesn't have it.
+    pub fn get(&self, item: &LibraryItem) -> String {
+        match self {
+            TagField::Title => item.title().unwrap_or_default(),
+            TagField::Artist => item.artist().unwrap_or_default(),
+            TagField::Album => item.album().unwrap_or_default(),
+            TagField::Genre => item.genre().unwrap_or_default(),
+            TagField::Year => item.year().map(|n| n.to_string()).unwrap_or_default(),
+            TagField::TrackNumber => item
+                .track_number()
+                .map(|n| n.to_string())
+                .unwrap_or_default(),
+        }
+    }
+
+    // Sets the tag from its text. Empty text removes the tag, and numbers that don't parse are
+    // ignored.
+    pub fn set(&self, item: &mut LibraryItem, value: &str) {
+        if !self.is_valid(value) {
+            return;
+        }
+
+        let value = Some(value.trim()).filter(|value| !value.is_empty());
+
+        match self {
+            TagField::Title => item.set_title(value),
+            TagField::Artist => item.set_artist(value),
+            TagField::Album => item.set_album(value),
+            TagField::Genre => item.set_genre(value),
+            TagField::Year => item.set_year(value.and_then(|value| value.parse().ok())),
+            TagField::TrackNumber => {
+                item.set_track_number(value.and_then(|value| value.parse().ok()))
+            }
+        };
+    }
+
+    pub fn is_valid(&self, value: &str) -> bool {
+        let value = value.trim();
+
+        match self {
+            TagField::Year => value.is_empty() || value.parse::<i32>().is_ok(),
+            TagField::TrackNumber => value.is_empty() || value.parse::<u32>().is_ok(),
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for TagField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TagField::Title => write!(f, "Title"),
+            TagField::Artist => write!(f, "Artist"),
+            TagField::Album => write!(f, "Album"),
+            TagField::Genre => write!(f, "Genre"),
+            TagField::Year => write!(f, "Year"),
+            TagField::TrackNumber => write!(f, "Track No."),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseStyle {
+    Title,
+    Sentence,
+    Upper,
+    Lower,
+}
+
+impl CaseStyle {
+    pub const ALL: [CaseStyle; 4] = [
+        CaseStyle::Title,
+        CaseStyle::Sentence,
+        CaseStyle::Upper,
+        CaseStyle::Lower,
+    ];
+
+    pub fn apply(&self, text: &str) -> String {
+        match self {
+            CaseStyle::Title => capitalize(text, true),
+            CaseStyle::Sentence => capitalize(text, false),
+            CaseStyle::Upper => text.to_uppercase(),
+            CaseStyle::Lower => text.to_lowercase(),
+        }
+    }
+}
+
+impl fmt::Display for CaseStyle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CaseStyle::Title => write!(f, "Title Case"),
+            CaseStyle::Sentence => write!(f, "Sentence case"),
+            CaseStyle::Upper => write!(f, "UPPER CASE"),
+            CaseStyle::Lower => write!(f, "lower case"),
+        }
+    }
+}
+
+// Lower cases the text and upper cases the first letter, and the first letter of every word when
+// `every_word` is set. Words start after a space or an opening bracket, quote, dash or slash, so
+// an apostrophe doesn't start one, ex. "don't".
+fn capitalize(text: &str, every_word: bool) -> String {
+    let mut capitalized = String::with_capacity(text.len());
+    let mut is_word_start = true;
+    let mut is_first_word = true;
+
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            if is_word_start && (every_word || is_first_word) {
+                capitalized.extend(c.to_uppercase());
+            } else {
+                capitalized.extend(c.to_lowercase());
+            }
+
+            is_word_start = false;
+            is_first_word = false;
+        } else {
+            capitalized.push(c);
+            is_word_start =
+                c.is_whitespace() || "([{\"-/".contains(c) || (is_word_start && c != '\'');
+        }
+    }
+
+    capitalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::LibraryPathId;
+    use std::path::PathBuf;
+
+    fn track(title: &str, album: &str) -> LibraryItem {
+        LibraryItem::new(
+            PathBuf::from(format!("{}.mp3", title)),
+            LibraryPathId::new(0),
+        )
+        .set_title(Some(title))
+        .set_album(Some(album))
+        .set_genre(Some("Electronic"))
+    }
+
+    fn value(draft: &TagEditDraft, field: TagField) -> &str {
+        let (_, value) = draft.values.iter().find(|(f, _)| *f == field).unwrap();
+        value
+    }
+
+    #[test]
+    fn edits_shared_tags_and_keeps_mixed_ones() {
+        let mut draft = TagEditDraft::new(vec![track("a", "x"), track("b", "x")]);
+
+        assert_eq!(value(&draft, TagField::Title), MULTIPLE_VALUES);
+        assert_eq!(value(&draft, TagField::Album), "x");
+        assert_eq!(value(&draft, TagField::Year), "");
+
+        for (field, value) in draft.values.iter_mut() {
+            match field {
+                TagField::Genre => *value = " Ambient ".to_string(),
+                TagField::Year => *value = "1998".to_string(),
+                _ => (),
+            }
+        }
+
+        let edited = draft.edited_tracks();
+        assert_eq!(edited.len(), 2);
+        assert_eq!(edited[0].title(), Some("a".to_string()));
+        assert_eq!(edited[1].title(), Some("b".to_string()));
+        assert!(edited.iter().all(
+            |track| track.genre() == Some("Ambient".to_string()) && track.year() == Some(1998)
+        ));
+
+        let mut draft = TagEditDraft::new(vec![track("a", "x")]);
+        for (field, value) in draft.values.iter_mut() {
+            if *field == TagField::TrackNumber {
+                *value = "3/12".to_string();
+            }
+        }
+        assert!(!draft.is_valid());
+        assert!(draft.edited_tracks().is_empty());
+    }
+
+    #[test]
+    fn numbers_tracks_per_album() {
+        let mut draft = TagEditDraft::new(vec![
+            track("a", "x"),
+            track("b", "y"),
+            track("c", "x"),
+            track("d", "x"),
+        ]);
+
+        draft.number_tracks();
+
+        let numbers = draft
+            .tracks
+            .iter()
+            .map(|track| track.track_number().unwrap())
+            .collect::<Vec<u32>>();
+        assert_eq!(numbers, vec![1, 1, 2, 3]);
+        assert_eq!(value(&draft, TagField::TrackNumber), MULTIPLE_VALUES);
+    }
+
+    #[test]
+    fn fixes_case() {
+        let text = "don't STOP (the music) - live/remix";
+
+        assert_eq!(
+            CaseStyle::Title.apply(text),
+            "Don't Stop (The Music) - Live/Remix"
+        );
+        assert_eq!(
+            CaseStyle::Sentence.apply(text),
+            "Don't stop (the music) - live/remix"
+        );
+        assert_eq!(CaseStyle::Lower.apply("AC/DC"), "ac/dc");
+
+        let mut draft = TagEditDraft::new(vec![track("hello world", "x")]);
+        draft.fix_case(CaseStyle::Upper);
+        assert_eq!(value(&draft, TagField::Title), "HELLO WORLD");
+        assert_eq!(value(&draft, TagField::Genre), "ELECTRONIC");
+    }
+}
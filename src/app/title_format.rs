@@ -0,0 +1,668 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+
+use serde::{Deserialize, Serialize};
+
+use super::library::format_duration;
+use super::tag_edit::CaseStyle;
+use super::LibraryItem;
+
+pub const DEFAULT_WINDOW_TITLE: &str = "%artist% - %title% '[ Music Player ]'";
+pub const DEFAULT_FOOTER: &str = "%filename_ext%";
+// The levels of a custom library grouping are separated by "|".
+pub const DEFAULT_GROUPING: &str = "%artist%|[%date% - ]%album%";
+
+// Shown for a field the track doesn't have.
+const MISSING_FIELD: &str = "?";
+// The most a function repeats or pads its text by, so a script like `$repeat(a,999999999)` can't
+// build a huge string on every frame.
+const MAX_GENERATED_COUNT: usize = 1000;
+
+/// A title formatting script, in the style of foobar2000, which turns the tags of a track into
+/// text. `%artist%` is replaced by the track's artist, `[...]` is left out unless a field in it
+/// was found, `'...'` is text as it is, ex. `'['`, and `$name(arg,...)` calls a function, ex.
+/// `$if(%album%,%album%,Singles)`.
+///
+/// Every part of a script has a truth value besides its text. Fields are true when the track has
+/// them, plain text is false, and a sequence of parts is true when any of them is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TitleFormat {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Field(String),
+    Conditional(Vec<Node>),
+    Function { name: String, args: Vec<Vec<Node>> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl TitleFormat {
+    pub fn parse(script: &str) -> Result<Self, ParseError> {
+        let mut chars = script.chars().peekable();
+        let (nodes, end) = parse_nodes(&mut chars, Scope::Top)?;
+        debug_assert!(end.is_none());
+
+        Ok(Self { nodes })
+    }
+
+    pub fn format(&self, item: &LibraryItem) -> String {
+        eval_nodes(&self.nodes, item).0
+    }
+}
+
+// The text of the track in the script, or in the default script when the script doesn't parse.
+pub fn format_or_default(script: &str, default_script: &str, item: &LibraryItem) -> String {
+    TitleFormat::parse(script)
+        .or_else(|_| TitleFormat::parse(default_script))
+        .map(|title_format| title_format.format(item))
+        .unwrap_or_default()
+}
+
+/// The scripts for the text shown about the selected track.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplayFormats {
+    pub window_title: String,
+    pub footer: String,
+}
+
+impl Default for DisplayFormats {
+    fn default() -> Self {
+        Self {
+            window_title: DEFAULT_WINDOW_TITLE.to_string(),
+            footer: DEFAULT_FOOTER.to_string(),
+        }
+    }
+}
+
+impl DisplayFormats {
+    pub fn window_title(&self, item: &LibraryItem) -> String {
+        format_or_default(&self.window_title, DEFAULT_WINDOW_TITLE, item)
+    }
+
+    pub fn footer(&self, item: &LibraryItem) -> String {
+        format_or_default(&self.footer, DEFAULT_FOOTER, item)
+    }
+}
+
+// Where a run of nodes is being parsed, which decides the characters that end it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scope {
+    Top,
+    Conditional,
+    Argument,
+}
+
+// Parses nodes until the end of the scope. Returns the character that ended it, which is `None`
+// at the end of the script.
+fn parse_nodes(
+    chars: &mut Peekable<Chars>,
+    scope: Scope,
+) -> Result<(Vec<Node>, Option<char>), ParseError> {
+    let mut nodes = Vec::new();
+    let mut text = String::new();
+
+    let end = loop {
+        let Some(c) = chars.next() else {
+            break None;
+        };
+
+        match (c, scope) {
+            (']', Scope::Conditional) | (',' | ')', Scope::Argument) => break Some(c),
+            (']', _) => return Err(ParseError("Unexpected ']'".to_string())),
+            ('\'', _) => match chars.peek() {
+                // Two quotes in a row are a quote.
+                Some('\'') => {
+                    chars.next();
+                    text.push('\'');
+                }
+                _ => text.push_str(&take_until(chars, '\'', "quote")?),
+            },
+            ('%', _) | ('[', _) | ('$', _) => {
+                if !text.is_empty() {
+                    nodes.push(Node::Text(std::mem::take(&mut text)));
+                }
+
+                nodes.push(match c {
+                    '%' => match take_until(chars, '%', "field")? {
+                        // Two percent signs in a row are a percent sign.
+                        name if name.is_empty() => Node::Text("%".to_string()),
+                        name => Node::Field(name.to_lowercase()),
+                    },
+                    '[' => match parse_nodes(chars, Scope::Conditional)? {
+                        (nodes, Some(']')) => Node::Conditional(nodes),
+                        _ => return Err(ParseError("Unclosed '['".to_string())),
+                    },
+                    _ => parse_function(chars)?,
+                });
+            }
+            _ => text.push(c),
+        }
+    };
+
+    if !text.is_empty() {
+        nodes.push(Node::Text(text));
+    }
+
+    Ok((nodes, end))
+}
+
+fn take_until(chars: &mut Peekable<Chars>, end: char, name: &str) -> Result<String, ParseError> {
+    let mut text = String::new();
+
+    for c in chars.by_ref() {
+        if c == end {
+            return Ok(text);
+        }
+        text.push(c);
+    }
+
+    Err(ParseError(format!("Unclosed {} '{}{}'", name, end, text)))
+}
+
+fn parse_function(chars: &mut Peekable<Chars>) -> Result<Node, ParseError> {
+    let mut name = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+        name.push(c);
+    }
+    let name = name.to_lowercase();
+
+    if chars.next() != Some('(') {
+        return Err(ParseError(format!("Expected '(' after ${}", name)));
+    }
+
+    let Some((min_args, max_args)) = function_arity(&name) else {
+        return Err(ParseError(format!("Unknown function ${}", name)));
+    };
+
+    let mut args = Vec::new();
+    loop {
+        match parse_nodes(chars, Scope::Argument)? {
+            (arg, Some(',')) => args.push(arg),
+            (arg, Some(')')) => {
+                args.push(arg);
+                break;
+            }
+            _ => return Err(ParseError(format!("Unclosed ${}(", name))),
+        }
+    }
+
+    // A call without arguments is parsed as one empty argument.
+    if args.len() == 1 && args[0].is_empty() {
+        args.clear();
+    }
+
+    if args.len() < min_args || args.len() > max_args {
+        let expected = match (min_args, max_args) {
+            (min, max) if min == max => min.to_string(),
+            (min, usize::MAX) => format!("at least {}", min),
+            (min, max) => format!("{} to {}", min, max),
+        };
+        return Err(ParseError(format!(
+            "${} takes {} arguments, not {}",
+            name,
+            expected,
+            args.len()
+        )));
+    }
+
+    Ok(Node::Function { name, args })
+}
+
+// How many arguments the function takes, at least and at most, or `None` when there's no such
+// function.
+fn function_arity(name: &str) -> Option<(usize, usize)> {
+    let many = usize::MAX;
+
+    Some(match name {
+        "if" => (2, 3),
+        "if2" | "strcmp" | "stricmp" | "greater" | "left" | "cut" | "right" | "num" | "repeat"
+        | "strstr" | "padcut" => (2, 2),
+        "if3" | "select" => (2, many),
+        "ifequal" | "ifgreater" | "iflonger" => (4, 4),
+        "and" | "or" | "xor" | "add" | "mul" | "min" | "max" => (1, many),
+        "sub" | "div" | "mod" => (2, many),
+        "not" | "upper" | "lower" | "caps" | "caps2" | "trim" | "len" | "abbr" | "filename"
+        | "ext" | "directory_path" => (1, 1),
+        "pad" | "pad_right" => (2, 3),
+        "directory" => (1, 2),
+        "substr" | "insert" => (3, 3),
+        "replace" => (3, many),
+        _ => return None,
+    })
+}
+
+fn eval_nodes(nodes: &[Node], item: &LibraryItem) -> (String, bool) {
+    let mut text = String::new();
+    let mut truth = false;
+
+    for node in nodes {
+        let (node_text, node_truth) = eval_node(node, item);
+        text.push_str(&node_text);
+        truth |= node_truth;
+    }
+
+    (text, truth)
+}
+
+fn eval_node(node: &Node, item: &LibraryItem) -> (String, bool) {
+    match node {
+        Node::Text(text) => (text.clone(), false),
+        Node::Field(name) => match field(name, item) {
+            Some(value) => (value, true),
+            None => (MISSING_FIELD.to_string(), false),
+        },
+        Node::Conditional(nodes) => match eval_nodes(nodes, item) {
+            (text, true) => (text, true),
+            (_, false) => (String::new(), false),
+        },
+        Node::Function { name, args } => eval_function(name, args, item),
+    }
+}
+
+// The value of the field, or `None` when the track doesn't have it. The title falls back to the
+// file name, like it does in the playlists.
+fn field(name: &str, item: &LibraryItem) -> Option<String> {
+    let path = item.path();
+    let properties = item.properties();
+    let file_stem = || Some(path.file_stem()?.to_string_lossy().into_owned());
+
+    match name {
+        "title" => item.title().or_else(file_stem),
+        "artist" => item.artist(),
+        "album" => item.album(),
+        "genre" => item.genre(),
+        "date" | "year" => item.year().map(|year| year.to_string()),
+        "tracknumber" => item.track_number().map(|n| format!("{:02}", n)),
+        "play_count" => Some(item.play_count().to_string()),
+        "path" => Some(path.display().to_string()),
+        "filename" => file_stem(),
+        "filename_ext" => Some(path.file_name()?.to_string_lossy().into_owned()),
+        "directory" => Some(path.parent()?.file_name()?.to_string_lossy().into_owned()),
+        "ext" => Some(path.extension()?.to_string_lossy().into_owned()),
+        "length" => properties.duration_millis.map(format_duration),
+        "length_seconds" => properties
+            .duration_millis
+            .map(|millis| (millis / 1000).to_string()),
+        "bitrate" => properties.bitrate.map(|kbps| kbps.to_string()),
+        "samplerate" => properties.sample_rate.map(|hz| hz.to_string()),
+        "channels" => properties.channels_text(),
+        "bitspersample" => properties.bits_per_sample.map(|bits| bits.to_string()),
+        "codec" => properties.codec.clone(),
+        _ => None,
+    }
+}
+
+// Functions that change text keep the truth of the text they change. Functions that compare
+// are true or false, with no text.
+fn eval_function(name: &str, args: &[Vec<Node>], item: &LibraryItem) -> (String, bool) {
+    let arg = |idx: usize| -> (String, bool) {
+        args.get(idx)
+            .map(|arg| eval_nodes(arg, item))
+            .unwrap_or_default()
+    };
+    let text = |idx: usize| arg(idx).0;
+    let number = |idx: usize| to_number(&text(idx));
+    let numbers = || (0..args.len()).map(number);
+    let boolean = |truth: bool| (String::new(), truth);
+    let integer = |n: i64| (n.to_string(), false);
+    // Changes the text of the first argument, keeping its truth.
+    let map_first = |f: &dyn Fn(&str) -> String| {
+        let (text, truth) = arg(0);
+        (f(&text), truth)
+    };
+
+    match name {
+        "if" => match arg(0).1 {
+            true => arg(1),
+            false => arg(2),
+        },
+        "if2" => match arg(0) {
+            (text, true) => (text, true),
+            _ => arg(1),
+        },
+        "if3" => (0..args.len() - 1)
+            .map(arg)
+            .find(|(_, truth)| *truth)
+            .unwrap_or_else(|| arg(args.len() - 1)),
+        "ifequal" => match number(0) == number(1) {
+            true => arg(2),
+            false => arg(3),
+        },
+        "ifgreater" => match number(0) > number(1) {
+            true => arg(2),
+            false => arg(3),
+        },
+        "iflonger" => match text(0).chars().count() as i64 > number(1) {
+            true => arg(2),
+            false => arg(3),
+        },
+        "select" => match usize::try_from(number(0)) {
+            Ok(n) if n >= 1 && n < args.len() => arg(n),
+            _ => (String::new(), false),
+        },
+
+        "and" => boolean((0..args.len()).all(|idx| arg(idx).1)),
+        "or" => boolean((0..args.len()).any(|idx| arg(idx).1)),
+        "xor" => boolean((0..args.len()).filter(|idx| arg(*idx).1).count() % 2 == 1),
+        "not" => boolean(!arg(0).1),
+        "greater" => boolean(number(0) > number(1)),
+        "strcmp" => boolean(text(0) == text(1)),
+        "stricmp" => boolean(text(0).to_lowercase() == text(1).to_lowercase()),
+
+        "add" => integer(numbers().fold(0, i64::wrapping_add)),
+        "mul" => integer(numbers().fold(1, i64::wrapping_mul)),
+        "sub" => integer(numbers().reduce(i64::wrapping_sub).unwrap_or_default()),
+        // Dividing by zero leaves the number as it is.
+        "div" => integer(
+            numbers()
+                .reduce(|a, b| a.checked_div(b).unwrap_or(a))
+                .unwrap_or_default(),
+        ),
+        "mod" => integer(
+            numbers()
+                .reduce(|a, b| a.checked_rem(b).unwrap_or(a))
+                .unwrap_or_default(),
+        ),
+        "min" => integer(numbers().min().unwrap_or_default()),
+        "max" => integer(numbers().max().unwrap_or_default()),
+
+        "num" => {
+            let width = generated_count(number(1));
+            // The minus sign counts towards the width.
+            let digits = width.saturating_sub(1);
+            map_first(&|text| {
+                let n = to_number(text);
+                match n < 0 {
+                    true => format!("-{:0digits$}", n.unsigned_abs()),
+                    false => format!("{:0width$}", n, width = width),
+                }
+            })
+        }
+        "len" => {
+            let (text, truth) = arg(0);
+            (text.chars().count().to_string(), truth)
+        }
+        "left" | "cut" => {
+            let count = char_count(number(1));
+            map_first(&|text| text.chars().take(count).collect())
+        }
+        "right" => {
+            let count = char_count(number(1));
+            map_first(&|text| {
+                let skipped = text.chars().count().saturating_sub(count);
+                text.chars().skip(skipped).collect()
+            })
+        }
+        "substr" => {
+            // From and to are counted from 1, and both are included.
+            let from = char_count(number(1)).max(1);
+            let to = char_count(number(2));
+            map_first(&|text| {
+                text.chars()
+                    .skip(from - 1)
+                    .take((to + 1).saturating_sub(from))
+                    .collect()
+            })
+        }
+        "insert" => {
+            let inserted = text(1);
+            let at = char_count(number(2));
+            map_first(&|text| {
+                let mut chars = text.chars().collect::<Vec<char>>();
+                let at = at.min(chars.len());
+                chars.splice(at..at, inserted.chars());
+                chars.into_iter().collect()
+            })
+        }
+        "strstr" => {
+            let (haystack, needle) = (text(0), text(1));
+            let position = match needle.is_empty() {
+                true => None,
+                false => haystack.find(&needle),
+            };
+            integer(position.map_or(0, |idx| haystack[..idx].chars().count() as i64 + 1))
+        }
+        "repeat" => {
+            let count = generated_count(number(1));
+            map_first(&|text| text.repeat(count))
+        }
+        // Pads on the right, so the text lines up on the left.
+        "pad" | "pad_right" | "padcut" => {
+            let width = generated_count(number(1));
+            let padding = text(2).chars().next().unwrap_or(' ');
+            map_first(&|text| {
+                let text = match name {
+                    "padcut" => text.chars().take(width).collect(),
+                    _ => text.to_string(),
+                };
+                let padding =
+                    std::iter::repeat_n(padding, width.saturating_sub(text.chars().count()))
+                        .collect::<String>();
+
+                match name {
+                    "pad_right" => padding + &text,
+                    _ => text + &padding,
+                }
+            })
+        }
+        "trim" => map_first(&|text| text.trim().to_string()),
+        "upper" => map_first(&|text| text.to_uppercase()),
+        "lower" => map_first(&|text| text.to_lowercase()),
+        "caps" => map_first(&|text| CaseStyle::Title.apply(text)),
+        "caps2" => map_first(&capitalize_words),
+        "abbr" => map_first(&|text| {
+            text.split_whitespace()
+                .filter_map(|word| word.chars().find(|c| c.is_alphanumeric()))
+                .collect()
+        }),
+        "replace" => {
+            // Pairs of text to find and what to replace it with.
+            let replacements = (1..args.len() - 1)
+                .step_by(2)
+                .map(|idx| (text(idx), text(idx + 1)))
+                .filter(|(from, _)| !from.is_empty())
+                .collect::<Vec<(String, String)>>();
+            map_first(&|text| {
+                replacements
+                    .iter()
+                    .fold(text.to_string(), |text, (from, to)| text.replace(from, to))
+            })
+        }
+
+        "filename" => map_first(&|text| path_part(text, Path::file_stem)),
+        "ext" => map_first(&|text| path_part(text, Path::extension)),
+        "directory_path" => map_first(&|text| {
+            Path::new(text)
+                .parent()
+                .map(|parent| parent.display().to_string())
+                .unwrap_or_default()
+        }),
+        // The name of the folder the path is in, or of the folder that one is in and so on.
+        "directory" => {
+            let levels = match args.len() {
+                1 => 1,
+                _ => char_count(number(1)).max(1),
+            };
+            map_first(&|text| {
+                Path::new(text)
+                    .ancestors()
+                    .nth(levels)
+                    .and_then(Path::file_name)
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            })
+        }
+
+        _ => (String::new(), false),
+    }
+}
+
+// The number at the start of the text, ex. 3 for "3/12", or 0 when there's none.
+fn to_number(text: &str) -> i64 {
+    let text = text.trim_start();
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(digits) => (-1, digits),
+        None => (1, text),
+    };
+
+    digits
+        .chars()
+        .map_while(|c| c.to_digit(10))
+        .fold(0i64, |n, digit| {
+            n.saturating_mul(10).saturating_add(digit as i64)
+        })
+        * sign
+}
+
+// A number used as a count of characters, where a negative number counts as none.
+fn char_count(n: i64) -> usize {
+    usize::try_from(n).unwrap_or(0)
+}
+
+fn generated_count(n: i64) -> usize {
+    char_count(n).min(MAX_GENERATED_COUNT)
+}
+
+// Upper cases the first letter of every word, leaving the others as they are.
+fn capitalize_words(text: &str) -> String {
+    let mut capitalized = String::with_capacity(text.len());
+    let mut is_word_start = true;
+
+    for c in text.chars() {
+        match is_word_start {
+            true => capitalized.extend(c.to_uppercase()),
+            false => capitalized.push(c),
+        }
+        is_word_start = c.is_whitespace();
+    }
+
+    capitalized
+}
+
+fn path_part(path: &str, part: fn(&Path) -> Option<&std::ffi::OsStr>) -> String {
+    part(Path::new(path))
+        .map(|part| part.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::library::AudioProperties;
+    use crate::app::LibraryPathId;
+    use std::path::PathBuf;
+
+    fn track() -> LibraryItem {
+        LibraryItem::new(
+            PathBuf::from("/music/Low/Trust/03 Candy Girl.flac"),
+            LibraryPathId::new(0),
+        )
+        .set_artist(Some("Low"))
+        .set_album(Some("Trust"))
+        .set_track_number(Some(3))
+        .set_properties(AudioProperties {
+            duration_millis: Some(245_900),
+            codec: Some("FLAC".to_string()),
+            ..AudioProperties::default()
+        })
+    }
+
+    fn format(script: &str) -> String {
+        TitleFormat::parse(script).unwrap().format(&track())
+    }
+
+    #[test]
+    fn formats_fields_and_conditional_sections() {
+        assert_eq!(format("%artist% - %title%"), "Low - 03 Candy Girl");
+        assert_eq!(format("%tracknumber%. %genre%"), "03. ?");
+        assert_eq!(format("%album%[ (%date%)]"), "Trust");
+        assert_eq!(format("[%album% ][(%date%)]"), "Trust ");
+        assert_eq!(
+            format("'[%artist%]' it''s 100%% (live), ok"),
+            "[%artist%] it's 100% (live), ok"
+        );
+        assert_eq!(
+            format("%directory%/%filename_ext% %EXT%"),
+            "Trust/03 Candy Girl.flac flac"
+        );
+        assert_eq!(format("%codec% %length%[ %bitrate% kbps]"), "FLAC 4:05");
+    }
+
+    #[test]
+    fn calls_functions() {
+        assert_eq!(format("$if(%genre%,%genre%,No genre)"), "No genre");
+        assert_eq!(format("$if2(%genre%,$upper(%artist%))"), "LOW");
+        assert_eq!(format("$if3(%genre%,%date%,%album%,none)"), "Trust");
+        assert_eq!(format("$num(%tracknumber%,3) $num(-4,3)"), "003 -04");
+        assert_eq!(
+            format("$left(%filename%,2)|$right(%album%,3)|$cut(ab,5)"),
+            "03|ust|ab"
+        );
+        assert_eq!(
+            format("$add(1,2,3) $sub(10,4) $div(7,0) $mod(7,3)"),
+            "6 6 7 1"
+        );
+        assert_eq!(format("$ifgreater(%tracknumber%,2,late,early)"), "late");
+        assert_eq!(format("$if($and(%artist%,$not(%genre%)),yes,no)"), "yes");
+        assert_eq!(format("$if($strcmp(%artist%,low),yes,no)"), "no");
+        assert_eq!(format("$if($stricmp(%artist%,low),yes,no)"), "yes");
+        assert_eq!(format("[$len(%genre%)]$len(%album%)"), "5");
+        assert_eq!(format("$pad(%artist%,5,.)|$pad_right(7,3,0)"), "Low..|007");
+        assert_eq!(
+            format("$caps(hELLO wORLD) $caps2(hELLO wORLD)"),
+            "Hello World HELLO WORLD"
+        );
+        assert_eq!(format("$replace(%album%,T,D,u,o)"), "Drost");
+        assert_eq!(format("$trim(  a b ) $abbr(Boards of Canada)"), "a b BoC");
+        assert_eq!(format("$directory(%path%,2)$ext(%path%)"), "Lowflac");
+        assert_eq!(
+            format("$substr(Geogaddi,2,4) $strstr(Geogaddi,gad)"),
+            "eog 4"
+        );
+        assert_eq!(format("$select(2,a,b,c)$insert(ac,b,1)"), "babc");
+    }
+
+    #[test]
+    fn bounds_generated_text() {
+        assert_eq!(format("$num(-4,0) $num(-4,-1)"), "-4 -4");
+        assert_eq!(
+            format("$repeat(ab,999999999)").len(),
+            2 * MAX_GENERATED_COUNT
+        );
+        assert_eq!(format("$pad(a,999999999)").len(), MAX_GENERATED_COUNT);
+        assert_eq!(format("$padcut(a,999999999)").len(), MAX_GENERATED_COUNT);
+        assert_eq!(format("$num(1,999999999)").len(), MAX_GENERATED_COUNT);
+    }
+
+    #[test]
+    fn reports_errors() {
+        let error = |script| TitleFormat::parse(script).unwrap_err().to_string();
+
+        assert_eq!(error("%artist"), "Unclosed field '%artist'");
+        assert_eq!(error("[%artist% - "), "Unclosed '['");
+        assert_eq!(error("a]"), "Unexpected ']'");
+        assert_eq!(error("'a"), "Unclosed quote ''a'");
+        assert_eq!(error("$foo(a)"), "Unknown function $foo");
+        assert_eq!(error("$upper"), "Expected '(' after $upper");
+        assert_eq!(error("$upper(a"), "Unclosed $upper(");
+        assert_eq!(error("$if(a)"), "$if takes 2 to 3 arguments, not 1");
+        assert_eq!(error("$num(1)"), "$num takes 2 arguments, not 1");
+
+        let item = track();
+        assert_eq!(format_or_default("$bad(", "%album%", &item), "Trust");
+    }
+}
@@ -0,0 +1,231 @@
+use notify_debouncer_full::notify::event::{ModifyKind, RenameMode};
+use notify_debouncer_full::notify::{self, EventKind, RecommendedWatcher, RecursiveMode};
+use notify_debouncer_full::{new_debouncer, DebounceEventResult, Debouncer, RecommendedCache};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::{media, LibraryItem, LibraryPath, UiCommand};
+
+// Editors and downloads write a file in several steps, so wait for them to settle.
+const DEBOUNCE_TIMEOUT: Duration = Duration::from_millis(750);
+
+/// Watches the library paths and sends the files that were created, changed, renamed or deleted
+/// to the UI thread.
+pub struct LibraryWatcher {
+    debouncer: Debouncer<RecommendedWatcher, RecommendedCache>,
+    // Shared with the event handler, which needs to know the library path new files belong to.
+    watched: Arc<Mutex<Vec<LibraryPath>>>,
+}
+
+impl LibraryWatcher {
+    pub fn new(ui_tx: Sender<UiCommand>) -> notify::Result<Self> {
+        let watched = Arc::new(Mutex::new(Vec::<LibraryPath>::new()));
+        let handler_watched = watched.clone();
+
+        let debouncer = new_debouncer(
+            DEBOUNCE_TIMEOUT,
+            None,
+            move |result: DebounceEventResult| match result {
+                Ok(events) => {
+                    let library_paths = handler_watched.lock().unwrap().clone();
+                    let events = events
+                        .iter()
+                        .map(|event| (event.kind, event.paths.as_slice()))
+                        .collect::<Vec<(EventKind, &[PathBuf])>>();
+
+                    for cmd in commands(&events, &library_paths) {
+                        if ui_tx.send(cmd).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(errors) => {
+                    for err in errors {
+                        tracing::warn!("Library watcher error: {}", err);
+                    }
+                }
+            },
+        )?;
+
+        Ok(Self { debouncer, watched })
+    }
+
+    // Starts watching the paths that aren't watched yet and stops watching the ones that are
+    // gone.
+    pub fn sync(&mut self, library_paths: &[LibraryPath]) {
+        let mut watched = self.watched.lock().unwrap();
+
+        for library_path in watched.iter() {
+            if !library_paths.iter().any(|p| p.id() == library_path.id()) {
+                tracing::info!("Stopped watching {:?}", library_path.path());
+                let _ = self.debouncer.unwatch(library_path.path());
+            }
+        }
+        watched.retain(|watched_path| library_paths.iter().any(|p| p.id() == watched_path.id()));
+
+        for library_path in library_paths {
+            if watched.iter().any(|p| p.id() == library_path.id()) {
+                continue;
+            }
+
+            match self
+                .debouncer
+                .watch(library_path.path(), RecursiveMode::Recursive)
+            {
+                Ok(_) => tracing::info!("Watching {:?}", library_path.path()),
+                Err(err) => tracing::warn!("Can't watch {:?}: {}", library_path.path(), err),
+            }
+
+            // Also remembered when it couldn't be watched, so it isn't retried every frame.
+            watched.push(library_path.clone());
+        }
+    }
+}
+
+// Turns a batch of debounced events into library updates. They are sent in the order they
+// happened, only runs of the same kind are merged into one command, so a file that was removed
+// and created again isn't dropped, and a rename doesn't move an item that was removed after it.
+fn commands(events: &[(EventKind, &[PathBuf])], library_paths: &[LibraryPath]) -> Vec<UiCommand> {
+    let mut commands = Vec::new();
+
+    for (kind, paths) in events {
+        match kind {
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if paths.len() == 2 => {
+                commands.push(UiCommand::LibraryFileRenamed {
+                    from: paths[0].clone(),
+                    to: paths[1].clone(),
+                });
+            }
+            EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                match commands.last_mut() {
+                    Some(UiCommand::LibraryFilesRemoved(removed)) => {
+                        removed.extend(paths.iter().cloned())
+                    }
+                    _ => commands.push(UiCommand::LibraryFilesRemoved(paths.to_vec())),
+                }
+            }
+            EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                let items = paths
+                    .iter()
+                    .flat_map(|path| read_items(path, true, library_paths));
+                push_changed(&mut commands, items.collect());
+            }
+            // A folder is modified whenever a file in it is, which is already its own event.
+            EventKind::Modify(_) => {
+                let items = paths
+                    .iter()
+                    .flat_map(|path| read_items(path, false, library_paths));
+                push_changed(&mut commands, items.collect());
+            }
+            _ => (),
+        }
+    }
+
+    commands
+}
+
+fn push_changed(commands: &mut Vec<UiCommand>, items: Vec<LibraryItem>) {
+    if items.is_empty() {
+        return;
+    }
+
+    match commands.last_mut() {
+        Some(UiCommand::LibraryFilesChanged(changed)) => changed.extend(items),
+        _ => commands.push(UiCommand::LibraryFilesChanged(items)),
+    }
+}
+
+// Reads the file, or every file in the folder if it was just created or moved in.
+fn read_items(path: &Path, is_new: bool, library_paths: &[LibraryPath]) -> Vec<LibraryItem> {
+    let Some(library_path) = library_paths
+        .iter()
+        .find(|library_path| path.starts_with(library_path.path()))
+    else {
+        return Vec::new();
+    };
+
+    if path.is_file() {
+        return media::read_library_item(path, library_path.id())
+            .into_iter()
+            .collect();
+    }
+
+    if !is_new {
+        return Vec::new();
+    }
+
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| media::read_library_item(entry.path(), library_path.id()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::wav::TestDir;
+    use notify::event::{CreateKind, RemoveKind};
+
+    #[test]
+    fn turns_events_into_library_updates() {
+        let dir = TestDir::new("watch");
+        std::fs::create_dir_all(dir.join("album")).unwrap();
+        std::fs::write(dir.join("album/notes.txt"), "not audio").unwrap();
+        let library_path = LibraryPath::new(dir.to_path_buf());
+
+        let renamed = [dir.join("old.flac"), dir.join("new.flac")];
+        let removed = [dir.join("gone.flac")];
+        let created = [dir.join("album")];
+        let outside = [PathBuf::from("/elsewhere/song.flac")];
+        let events: [(EventKind, &[PathBuf]); 4] = [
+            (EventKind::Create(CreateKind::Folder), &created),
+            (EventKind::Remove(RemoveKind::File), &removed),
+            (
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                &renamed,
+            ),
+            (EventKind::Create(CreateKind::File), &outside),
+        ];
+
+        let commands = commands(&events, &[library_path]);
+
+        assert_eq!(commands.len(), 2);
+        assert!(matches!(
+            &commands[0],
+            UiCommand::LibraryFilesRemoved(paths) if paths == &removed
+        ));
+        assert!(matches!(
+            &commands[1],
+            UiCommand::LibraryFileRenamed { from, to } if from == &renamed[0] && to == &renamed[1]
+        ));
+    }
+
+    #[test]
+    fn keeps_the_order_of_events() {
+        let dir = TestDir::new("order");
+        let library_path = LibraryPath::new(dir.to_path_buf());
+        let track = [dir.write_silence("track.wav", 100)];
+
+        // The file was replaced, so it has to be removed before it's read again.
+        let events: [(EventKind, &[PathBuf]); 2] = [
+            (EventKind::Remove(RemoveKind::File), &track),
+            (EventKind::Create(CreateKind::File), &track),
+        ];
+
+        let commands = commands(&events, &[library_path]);
+
+        assert_eq!(commands.len(), 2);
+        assert!(matches!(
+            &commands[0],
+            UiCommand::LibraryFilesRemoved(paths) if paths == &track
+        ));
+        assert!(matches!(
+            &commands[1],
+            UiCommand::LibraryFilesChanged(items) if items.len() == 1 && items[0].path() == track[0]
+        ));
+    }
+}
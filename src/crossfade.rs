@@ -0,0 +1,168 @@
+use std::borrow::Cow;
+use std::f32::consts::FRAC_PI_2;
+
+use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Signal, SignalSpec};
+
+use crate::resampler::convert_samples_any;
+
+/// Mixes the tail of the outgoing track with the head of the incoming one using equal-power
+/// curves, so the combined loudness stays constant through the overlap.
+pub struct Crossfade {
+    spec: SignalSpec,
+    // Length of the overlap and how far into it we are, in frames.
+    length: u64,
+    position: u64,
+    // Decoded samples of the incoming track that haven't been mixed yet.
+    incoming: Vec<Vec<f32>>,
+    outgoing: Vec<Vec<f32>>,
+    mixed: AudioBuffer<f32>,
+}
+
+impl Crossfade {
+    pub fn new(spec: SignalSpec, length: u64) -> Self {
+        let channels = spec.channels.count();
+
+        Self {
+            spec,
+            length: length.max(1),
+            position: 0,
+            incoming: vec![Vec::new(); channels],
+            outgoing: vec![Vec::new(); channels],
+            mixed: AudioBuffer::new(0, spec),
+        }
+    }
+
+    pub fn spec(&self) -> SignalSpec {
+        self.spec
+    }
+
+    /// Number of incoming frames waiting to be mixed.
+    pub fn buffered(&self) -> usize {
+        self.incoming[0].len()
+    }
+
+    /// Buffers decoded incoming samples, multiplied by `scale`.
+    pub fn push_incoming(&mut self, decoded: &AudioBufferRef<'_>, scale: f32) {
+        let start = self.buffered();
+        convert_samples_any(decoded, &mut self.incoming);
+
+        for channel in self.incoming.iter_mut() {
+            channel[start..]
+                .iter_mut()
+                .for_each(|sample| *sample *= scale);
+        }
+    }
+
+    /// Mixes a buffer of the outgoing track, multiplied by `scale`, with as many frames of the
+    /// incoming track. Missing incoming frames are treated as silence.
+    pub fn mix(&mut self, outgoing: &AudioBufferRef<'_>, scale: f32) -> AudioBufferRef<'_> {
+        let frames = outgoing.frames();
+
+        for channel in self.outgoing.iter_mut() {
+            channel.clear();
+        }
+        convert_samples_any(outgoing, &mut self.outgoing);
+
+        self.prepare_mixed(frames);
+
+        for (c, (out_chan, in_chan)) in self.outgoing.iter().zip(&self.incoming).enumerate() {
+            let dst = self.mixed.chan_mut(c);
+
+            for (i, sample) in dst.iter_mut().enumerate() {
+                let (out_gain, in_gain) =
+                    gains((self.position + i as u64) as f32 / self.length as f32);
+                let incoming = in_chan.get(i).copied().unwrap_or(0.0);
+                *sample = out_chan[i] * scale * out_gain + incoming * in_gain;
+            }
+        }
+
+        for channel in self.incoming.iter_mut() {
+            channel.drain(..frames.min(channel.len()));
+        }
+        self.position += frames as u64;
+
+        AudioBufferRef::F32(Cow::Borrowed(&self.mixed))
+    }
+
+    /// The incoming frames decoded past the end of the outgoing track, at full gain.
+    pub fn take_remaining(&mut self) -> AudioBufferRef<'_> {
+        let frames = self.buffered();
+        self.prepare_mixed(frames);
+
+        for (c, channel) in self.incoming.iter_mut().enumerate() {
+            self.mixed.chan_mut(c).copy_from_slice(channel);
+            channel.clear();
+        }
+
+        AudioBufferRef::F32(Cow::Borrowed(&self.mixed))
+    }
+
+    fn prepare_mixed(&mut self, frames: usize) {
+        if self.mixed.capacity() < frames {
+            self.mixed = AudioBuffer::new(frames as u64, self.spec);
+        }
+
+        self.mixed.clear();
+        self.mixed.render_reserved(Some(frames));
+    }
+}
+
+/// Gains of the outgoing and incoming track `progress` of the way through the overlap.
+fn gains(progress: f32) -> (f32, f32) {
+    let angle = progress.clamp(0.0, 1.0) * FRAC_PI_2;
+    (angle.cos(), angle.sin())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use symphonia::core::audio::Channels;
+
+    fn mono_buffer(samples: &[f32]) -> AudioBuffer<f32> {
+        let spec = SignalSpec::new(44100, Channels::FRONT_LEFT);
+        let mut buffer = AudioBuffer::new(samples.len() as u64, spec);
+        buffer.render_reserved(Some(samples.len()));
+        buffer.chan_mut(0).copy_from_slice(samples);
+        buffer
+    }
+
+    #[test]
+    fn gains_keep_constant_power() {
+        for step in 0..=10 {
+            let (out_gain, in_gain) = gains(step as f32 / 10.0);
+            assert!((out_gain.powi(2) + in_gain.powi(2) - 1.0).abs() < 1e-6);
+        }
+
+        assert_eq!(gains(0.0), (1.0, 0.0));
+        assert!(gains(1.0).0.abs() < 1e-6);
+    }
+
+    #[test]
+    fn mixes_outgoing_into_incoming() {
+        let outgoing = mono_buffer(&[1.0; 4]);
+        let incoming = mono_buffer(&[1.0; 6]);
+        let mut crossfade = Crossfade::new(*outgoing.spec(), 4);
+
+        crossfade.push_incoming(&AudioBufferRef::F32(Cow::Borrowed(&incoming)), 0.5);
+        let mixed = match crossfade.mix(&AudioBufferRef::F32(Cow::Borrowed(&outgoing)), 2.0) {
+            AudioBufferRef::F32(mixed) => mixed.chan(0).to_vec(),
+            _ => unreachable!(),
+        };
+
+        let expected: Vec<f32> = (0..4)
+            .map(|i| {
+                let (out_gain, in_gain) = gains(i as f32 / 4.0);
+                2.0 * out_gain + 0.5 * in_gain
+            })
+            .collect();
+        assert_eq!(mixed, expected);
+
+        // The two frames decoded past the overlap come out at full gain.
+        assert_eq!(crossfade.buffered(), 2);
+        match crossfade.take_remaining() {
+            AudioBufferRef::F32(remaining) => assert_eq!(remaining.chan(0), &[0.5, 0.5]),
+            _ => unreachable!(),
+        }
+        assert_eq!(crossfade.buffered(), 0);
+    }
+}
@@ -6,13 +6,18 @@ pub use crate::app::*;
 
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
-use std::sync::mpsc::{channel, Receiver};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
 use std::sync::Arc;
 use std::thread;
 
+use app::album_art::AlbumArt;
+use app::replay_gain::{ReplayGain, ReplayGainSettings};
+use crossfade::Crossfade;
 use eframe::egui;
+use output::AudioOutputDevice;
 use rb::*;
-use symphonia::core::codecs::{DecoderOptions, FinalizeResult, CODEC_TYPE_NULL};
+use symphonia::core::audio::SignalSpec;
+use symphonia::core::codecs::{Decoder, DecoderOptions, FinalizeResult, CODEC_TYPE_NULL};
 use symphonia::core::errors::{Error, Result};
 use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo, Track};
 use symphonia::core::io::MediaSourceStream;
@@ -21,6 +26,7 @@ use symphonia::core::probe::Hint;
 use symphonia::core::units::Time;
 
 mod app;
+mod crossfade;
 mod output;
 mod resampler;
 
@@ -44,16 +50,16 @@ fn main() {
     // App setup
     let is_processing_ui_change = Arc::new(AtomicBool::new(false));
     let process_gui_samples = Arc::new(AtomicBool::new(false));
-    let thread_pool = Arc::new(rayon::ThreadPoolBuilder::new()
-        .num_threads(32)
-        .build()
-        .unwrap());
+    let thread_pool = Arc::new(
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(32)
+            .build()
+            .unwrap(),
+    );
 
     let mut app = App::load().unwrap_or_default();
     app.scope = Some(Scope::new());
     app.player = Some(player);
-    app.ui_tx = Some(ui_tx.clone());
-    app.ui_rx = Some(ui_rx);
     app.rms_meter = [f32::NEG_INFINITY, f32::NEG_INFINITY];
     app.played_audio_buffer = Some(gui_ring_buf_consumer);
     app.ui_audio_buffer = vec![0.0f32; 4096];
@@ -61,223 +67,423 @@ fn main() {
     app.process_gui_samples = process_gui_samples.clone();
     app.rms_calc_left = RmsCalculator::new(5000);
     app.rms_calc_right = RmsCalculator::new(5000);
-    app.thread_pool = Some(thread_pool);
+    app.album_art = AlbumArt::new(thread_pool.clone());
+    app.start(
+        ui_tx.clone(),
+        ui_rx,
+        thread_pool,
+        App::open_library_database,
+    );
+
+    if let Some(player) = app.player.as_mut() {
+        player.set_crossfade(app.crossfade_millis);
+        player.set_replay_gain(app.replay_gain);
+        player.set_playback_mode(app.playback_mode);
+    }
 
     // Audio output setup
+    let output_device = AudioOutputDevice::from_env();
     let _audio_thread = thread::spawn(move || {
-        let mut state = PlayerState::Unstarted;
+        run_audio_engine(
+            audio_rx,
+            ui_tx,
+            gui_ring_buf_producer,
+            process_gui_samples,
+            is_processing_ui_change,
+            output_device,
+        )
+    });
 
-        let mut audio_engine_state = AudioEngineState {
-            reader: None,
-            audio_output: None,
-            track_num: None,
-            seek: None,
-            decode_opts: None,
-            track_info: None,
-            duration: 0,
-            sample_rate: 44100.0,
-        };
+    let native_options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([1920.0, 960.0]),
+        ..Default::default()
+    };
 
-        let mut decoder: Option<Box<dyn symphonia::core::codecs::Decoder>> = None;
-        let mut volume = 1.0;
-        let mut current_track_path: Option<PathBuf> = None;
-        let mut timer = std::time::Instant::now();
+    eframe::run_native(
+        "Music Player",
+        native_options,
+        Box::new(|_| Ok(Box::new(app))),
+    )
+    .expect("eframe failed: I should change main to return a result and use anyhow");
+}
 
-        loop {
-            process_audio_cmd(&audio_rx, &mut state, &mut volume, &is_processing_ui_change);
-
-            match state {
-                PlayerState::Playing => {
-                    // decode the next packet.
-                    let result: std::result::Result<(), symphonia::core::errors::Error> = 'once: {
-                        if state != PlayerState::Playing {
-                            tracing::info!("AudioThread Playing - Got a different state, bailing");
-                            break 'once Ok(());
-                        }
+/// Runs the audio engine until the `AudioCommand` sender is dropped.
+fn run_audio_engine(
+    audio_rx: Receiver<AudioCommand>,
+    ui_tx: Sender<UiCommand>,
+    gui_ring_buf_producer: rb::Producer<f32>,
+    process_gui_samples: Arc<AtomicBool>,
+    is_processing_ui_change: Arc<AtomicBool>,
+    output_device: AudioOutputDevice,
+) {
+    let mut state = PlayerState::Unstarted;
+
+    let mut audio_engine_state = AudioEngineState::new();
+    let mut decoder: Option<Box<dyn Decoder>> = None;
+    let mut audio_output: Option<Box<dyn output::AudioOutput>> = None;
+    // The signal spec and buffer capacity the output was opened with.
+    let mut output_spec: Option<(SignalSpec, u64)> = None;
+    let mut next_track: Option<PreloadedTrack> = None;
+    let mut pending_track: Option<PendingTrack> = None;
+    let mut crossfade: Option<Crossfade> = None;
+    let mut crossfade_millis = 0;
+    let mut replay_gain = ReplayGainSettings::default();
+    let mut volume = 1.0;
+    let mut current_track_path: Option<PathBuf> = None;
+    let mut timer = std::time::Instant::now();
+
+    loop {
+        if !process_audio_cmd(
+            &audio_rx,
+            &mut state,
+            &mut volume,
+            &mut crossfade_millis,
+            &mut replay_gain,
+            &mut audio_engine_state.replay_gain,
+            &mut next_track,
+            &mut pending_track,
+            &is_processing_ui_change,
+        ) {
+            tracing::info!("Audio command channel closed, stopping the audio thread");
+            break;
+        }
 
-                        let reader = audio_engine_state.reader.as_mut().unwrap();
-                        let play_opts = audio_engine_state.track_info.unwrap();
-                        let audio_output = &mut audio_engine_state.audio_output;
-                        // Get the next packet from the format reader.
-                        let packet = match reader.next_packet() {
-                            Ok(packet) => packet,
-                            Err(err) => {
-                                tracing::warn!("couldn't decode next packet");
-                                // Track is over.. update the state to stopped and send message to
-                                // UI to play next track
-                                state = PlayerState::Stopped;
-                                ui_tx
-                                    .send(UiCommand::AudioFinished)
-                                    .expect("Failed to send play to ui thread");
-                                break 'once Err(err);
-                            }
-                        };
+        if let Some(pending) = pending_track.take() {
+            match pending.try_finish() {
+                Ok(loaded) => next_track = loaded,
+                Err(pending) => pending_track = Some(pending),
+            }
+        }
 
-                        // If the packet does not belong to the selected track, skip it.
-                        if packet.track_id() != play_opts.track_id {
-                            tracing::warn!("packet track id doesn't match track id");
-                            break 'once Ok(());
-                        }
+        // The track being mixed in was replaced or dropped.
+        if next_track.is_none() {
+            crossfade = None;
+        }
 
-                        if timer.elapsed() > std::time::Duration::from_millis(250) {
-                            // Sending the timestamp every possible read spams the UI queue.
-                            // We only need to send this data twice a second or so...
-                            ui_tx
-                                .send(UiCommand::CurrentTimestamp(packet.ts))
-                                .expect("Failed to send play to ui thread");
+        match state {
+            PlayerState::Playing => {
+                // decode the next packet.
+                let result: std::result::Result<(), symphonia::core::errors::Error> = 'once: {
+                    if state != PlayerState::Playing {
+                        tracing::info!("AudioThread Playing - Got a different state, bailing");
+                        break 'once Ok(());
+                    }
 
-                            timer = std::time::Instant::now();
-                        }
+                    let reader = audio_engine_state.reader.as_mut().unwrap();
+                    let play_opts = audio_engine_state.track_info.unwrap();
+                    // Get the next packet from the format reader.
+                    let packet = match reader.next_packet() {
+                        Ok(packet) => packet,
+                        Err(err) => {
+                            // Track is over. If the next track is still loading, waiting for it
+                            // is shorter than the gap of loading it after the UI asks for it.
+                            if let Some(pending) = pending_track.take() {
+                                next_track = pending.finish();
+                            }
 
-                        // Decode the packet into audio samples.
-                        match decoder.as_mut().unwrap().decode(&packet) {
-                            Ok(decoded) => {
-                                // If the audio output is not open, try to open it.
-                                if audio_output.is_none() {
-                                    // Get the audio buffer specification. This is a description of the decoded
-                                    // audio buffer's sample format and sample rate.
-                                    let spec = *decoded.spec();
-
-                                    // Get the capacity of the decoded buffer. Note that this is capacity, not
-                                    // length! The capacity of the decoded buffer is constant for the life of the
-                                    // decoder, but the length is not.
-                                    let duration = decoded.capacity() as u64;
-
-                                    // Try to open the audio output.
-                                    audio_output.replace(output::try_open(spec, duration).unwrap());
-                                } else {
-                                    // TODO: Check the audio spec. and duration hasn't changed.
-                                }
+                            // If the next track was preloaded, keep decoding straight into it so
+                            // there is no gap in the output.
+                            if let Some(next) = next_track.take() {
+                                tracing::info!("AudioThread advancing to {:?}", &next.path);
+                                audio_engine_state = next.engine_state;
+                                decoder = next.decoder;
+                                current_track_path = Some(next.path.clone());
 
-                                // Write the decoded audio samples to the audio output if the presentation timestamp
-                                // for the packet is >= the seeked position (0 if not seeking).
-                                if packet.ts() >= play_opts.seek_ts {
-                                    if let Some(audio_output) = audio_output {
-                                        audio_output
-                                            .write(
-                                                decoded,
-                                                &gui_ring_buf_producer,
-                                                &process_gui_samples,
-                                                volume,
-                                            )
-                                            .unwrap();
+                                ui_tx
+                                    .send(UiCommand::TrackAdvanced(next.path))
+                                    .expect("Failed to send track advanced to ui thread");
+                                ui_tx
+                                    .send(UiCommand::TotalTrackDuration(
+                                        audio_engine_state.duration,
+                                    ))
+                                    .expect("Failed to send duration to ui thread");
+                                ui_tx
+                                    .send(UiCommand::SampleRate(audio_engine_state.sample_rate))
+                                    .expect("Failed to send sample rate to ui thread");
+
+                                // Whatever the next track decoded past the overlap still has to
+                                // be played.
+                                if let Some(mut fade) =
+                                    crossfade.take().filter(|fade| fade.buffered() > 0)
+                                {
+                                    if let Err(err) = ensure_output(
+                                        &mut audio_output,
+                                        &mut output_spec,
+                                        &output_device,
+                                        fade.spec(),
+                                        fade.buffered() as u64,
+                                    ) {
+                                        stop_on_output_error(err, &mut state, &ui_tx);
+                                        break 'once Ok(());
+                                    }
+
+                                    if let Some(audio_output) = audio_output.as_mut() {
+                                        if let Err(err) = audio_output.write(
+                                            fade.take_remaining(),
+                                            &gui_ring_buf_producer,
+                                            &process_gui_samples,
+                                            volume,
+                                        ) {
+                                            stop_on_output_error(err, &mut state, &ui_tx);
+                                            break 'once Ok(());
+                                        }
                                     }
                                 }
 
-                                Ok(())
-                            }
-                            Err(Error::DecodeError(err)) => {
-                                // Decode errors are not fatal. Print the error message and try to decode the next
-                                // packet as usual.
-                                tracing::warn!("decode error: {}", err);
                                 break 'once Ok(());
                             }
-                            Err(err) => break 'once Err(err),
+
+                            tracing::warn!("couldn't decode next packet");
+                            // Nothing queued, update the state to stopped and send message to
+                            // UI to play next track
+                            state = PlayerState::Stopped;
+                            ui_tx
+                                .send(UiCommand::AudioFinished)
+                                .expect("Failed to send play to ui thread");
+                            break 'once Err(err);
                         }
                     };
 
-                    // Return if a fatal error occured.
-                    ignore_end_of_stream_error(result)
-                        .expect("Encountered some other error than EoF");
+                    // If the packet does not belong to the selected track, skip it.
+                    if packet.track_id() != play_opts.track_id {
+                        tracing::warn!("packet track id doesn't match track id");
+                        break 'once Ok(());
+                    }
 
-                    // Finalize the decoder and return the verification result if it's been enabled.
-                    _ = do_verification(decoder.as_mut().unwrap().finalize());
-                }
-                PlayerState::Stopped => {
-                    // This is kind of a hack to get stopping to work. Flush the buffer so there is
-                    // nothing left in the resampler, but the decoder needs to be reset. This is as
-                    // simple as reloading the current track so the next time it plays from the
-                    // beginning.
-                    if let Some(audio_output) = audio_engine_state.audio_output.as_mut() {
-                        tracing::info!("Audio Thread Stopped - flushing output");
-                        audio_output.flush()
+                    if timer.elapsed() > std::time::Duration::from_millis(250) {
+                        // Sending the timestamp every possible read spams the UI queue.
+                        // We only need to send this data twice a second or so...
+                        ui_tx
+                            .send(UiCommand::CurrentTimestamp(packet.ts))
+                            .expect("Failed to send play to ui thread");
+
+                        timer = std::time::Instant::now();
                     }
 
-                    if let Some(ref current_track_path) = current_track_path {
-                        if let Some(audio_output) = audio_engine_state.audio_output.as_mut() {
-                            audio_output.flush()
-                        }
+                    // Decode the packet into audio samples.
+                    match decoder.as_mut().unwrap().decode(&packet) {
+                        Ok(decoded) => {
+                            // Get the audio buffer specification. This is a description of the decoded
+                            // audio buffer's sample format and sample rate.
+                            let spec = *decoded.spec();
+
+                            // Get the capacity of the decoded buffer. Note that this is capacity, not
+                            // length! The capacity of the decoded buffer is constant for the life of the
+                            // decoder, but the length is not.
+                            let duration = decoded.capacity() as u64;
+
+                            let track_scale = audio_engine_state.replay_gain.scale(&replay_gain);
+
+                            if let Err(err) = ensure_output(
+                                &mut audio_output,
+                                &mut output_spec,
+                                &output_device,
+                                spec,
+                                duration,
+                            ) {
+                                stop_on_output_error(err, &mut state, &ui_tx);
+                                break 'once Ok(());
+                            }
 
-                        audio_engine_state.audio_output = None;
+                            // Start mixing in the next track once the current one is within the
+                            // crossfade duration of its end.
+                            let crossfade_ts = crossfade_millis as u64
+                                * audio_engine_state.sample_rate as u64
+                                / 1000;
+                            let fade_start =
+                                audio_engine_state.duration.saturating_sub(crossfade_ts);
+                            let is_fade_due = crossfade_ts > 0
+                                && audio_engine_state.duration > 0
+                                && packet.ts() >= fade_start.max(play_opts.seek_ts);
+
+                            if crossfade.is_none() && is_fade_due {
+                                let next = next_track.as_ref().filter(|next| next.crossfade);
+                                if let Some(next) = next {
+                                    tracing::info!("AudioThread crossfading into {:?}", &next.path);
+                                    crossfade = Some(Crossfade::new(
+                                        spec,
+                                        audio_engine_state.duration - packet.ts(),
+                                    ));
+                                }
+                            }
 
-                        load_file(current_track_path, &mut audio_engine_state, &mut decoder, 0);
+                            if let (Some(fade), Some(next)) =
+                                (crossfade.as_mut(), next_track.as_mut())
+                            {
+                                if fill_crossfade(fade, next, decoded.frames(), &replay_gain) {
+                                    if let Some(audio_output) = audio_output.as_mut() {
+                                        if let Err(err) = audio_output.write(
+                                            fade.mix(&decoded, track_scale),
+                                            &gui_ring_buf_producer,
+                                            &process_gui_samples,
+                                            volume,
+                                        ) {
+                                            stop_on_output_error(err, &mut state, &ui_tx);
+                                            break 'once Ok(());
+                                        }
+                                    }
 
-                        ui_tx
-                            .send(UiCommand::CurrentTimestamp(0))
-                            .expect("Failed to send play to ui thread");
+                                    break 'once Ok(());
+                                }
 
-                        state = PlayerState::Unstarted;
-                    }
-                }
-                PlayerState::SeekTo(seek_timestamp) => {
-                    tracing::info!("AudioThread Seeking");
-                    if let Some(ref current_track_path) = current_track_path {
-                        // Stop current playback
-                        if let Some(audio_output) = audio_engine_state.audio_output.as_mut() {
-                            audio_output.flush()
-                        }
+                                // The tracks can't be mixed, so restart the next one from the
+                                // beginning and play into it without a gap instead.
+                                tracing::warn!("Can't crossfade tracks with different signals");
+                                crossfade = None;
+                                pending_track = Some(PendingTrack::spawn(
+                                    next.path.clone(),
+                                    false,
+                                    next.engine_state.replay_gain,
+                                ));
+                                next_track = None;
+                            }
 
-                        audio_engine_state.audio_output = None;
+                            // Write the decoded audio samples to the audio output if the presentation timestamp
+                            // for the packet is >= the seeked position (0 if not seeking).
+                            if packet.ts() >= play_opts.seek_ts {
+                                if let Some(audio_output) = audio_output.as_mut() {
+                                    if let Err(err) = audio_output.write(
+                                        decoded,
+                                        &gui_ring_buf_producer,
+                                        &process_gui_samples,
+                                        volume * track_scale,
+                                    ) {
+                                        stop_on_output_error(err, &mut state, &ui_tx);
+                                        break 'once Ok(());
+                                    }
+                                }
+                            }
 
-                        load_file(
-                            current_track_path,
-                            &mut audio_engine_state,
-                            &mut decoder,
-                            seek_timestamp,
-                        );
-                        state = PlayerState::Playing;
+                            Ok(())
+                        }
+                        Err(Error::DecodeError(err)) => {
+                            // Decode errors are not fatal. Print the error message and try to decode the next
+                            // packet as usual.
+                            tracing::warn!("decode error: {}", err);
+                            break 'once Ok(());
+                        }
+                        Err(err) => break 'once Err(err),
                     }
+                };
+
+                // Return if a fatal error occured.
+                ignore_end_of_stream_error(result).expect("Encountered some other error than EoF");
+
+                // Finalize the decoder and return the verification result if it's been enabled.
+                _ = do_verification(decoder.as_mut().unwrap().finalize());
+            }
+            PlayerState::Stopped => {
+                // This is kind of a hack to get stopping to work. Flush the buffer so there is
+                // nothing left in the resampler, but the decoder needs to be reset. This is as
+                // simple as reloading the current track so the next time it plays from the
+                // beginning.
+                if let Some(audio_output) = audio_output.as_mut() {
+                    tracing::info!("Audio Thread Stopped - flushing output");
+                    audio_output.flush()
                 }
-                PlayerState::LoadFile(ref path) => {
-                    tracing::info!("AudioThread Loading File");
-                    // Stop current playback
-                    if let Some(audio_output) = audio_engine_state.audio_output.as_mut() {
-                        tracing::info!("AudioThread Loading File - Flushing output");
+
+                crossfade = None;
+
+                if let Some(ref current_track_path) = current_track_path {
+                    if let Some(audio_output) = audio_output.as_mut() {
                         audio_output.flush()
                     }
 
-                    audio_engine_state.audio_output = None;
-
-                    current_track_path = Some((*path).clone());
-                    load_file(path, &mut audio_engine_state, &mut decoder, 0);
+                    audio_output = None;
 
-                    ui_tx
-                        .send(UiCommand::TotalTrackDuration(audio_engine_state.duration))
-                        .expect("Failed to send play to audio thread");
+                    if let Err(err) =
+                        load_file(current_track_path, &mut audio_engine_state, &mut decoder, 0)
+                    {
+                        tracing::warn!("Couldn't reload {:?}: {}", current_track_path, err);
+                    }
 
                     ui_tx
-                        .send(UiCommand::SampleRate(audio_engine_state.sample_rate))
-                        .expect("Failed to send play to audio thread");
+                        .send(UiCommand::CurrentTimestamp(0))
+                        .expect("Failed to send play to ui thread");
 
-                    state = PlayerState::Playing;
+                    state = PlayerState::Unstarted;
                 }
-                PlayerState::Paused => {
-                    // don't decode AND don't flush the buffer?
+            }
+            PlayerState::SeekTo(seek_timestamp) => {
+                tracing::info!("AudioThread Seeking");
+                crossfade = None;
+
+                if let Some(ref current_track_path) = current_track_path {
+                    // Stop current playback
+                    if let Some(audio_output) = audio_output.as_mut() {
+                        audio_output.flush()
+                    }
+
+                    audio_output = None;
+
+                    match load_file(
+                        current_track_path,
+                        &mut audio_engine_state,
+                        &mut decoder,
+                        seek_timestamp,
+                    ) {
+                        Ok(()) => state = PlayerState::Playing,
+                        Err(err) => {
+                            tracing::warn!("Couldn't seek in {:?}: {}", current_track_path, err);
+                            state = PlayerState::Unstarted;
+                            ui_tx
+                                .send(UiCommand::AudioStopped)
+                                .expect("Failed to send stopped to ui thread");
+                        }
+                    }
                 }
-                PlayerState::Unstarted => {}
             }
-        }
-    }); // Audio Thread end
+            PlayerState::LoadFile(ref path, track_gain) => {
+                tracing::info!("AudioThread Loading File");
+                // The output is left open. It's reopened while decoding if the new track's
+                // signal doesn't match it.
+                let path = path.clone();
+                next_track = None;
+                pending_track = None;
+                crossfade = None;
+                audio_engine_state.replay_gain = track_gain;
+                // Playlists keep entries for files that went missing, which the UI skips.
+                if let Err(err) = load_file(&path, &mut audio_engine_state, &mut decoder, 0) {
+                    tracing::warn!("Couldn't play {:?}: {}", path, err);
+                    state = PlayerState::Stopped;
+                    ui_tx
+                        .send(UiCommand::TrackFailed(path))
+                        .expect("Failed to send track failed to ui thread");
+                    continue;
+                }
+                current_track_path = Some(path);
 
-    let native_options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default().with_inner_size([1920.0, 960.0]),
-        ..Default::default()
-    };
+                ui_tx
+                    .send(UiCommand::TotalTrackDuration(audio_engine_state.duration))
+                    .expect("Failed to send play to audio thread");
 
-    eframe::run_native(
-        "Music Player",
-        native_options,
-        Box::new(|_| Ok(Box::new(app))),
-    )
-    .expect("eframe failed: I should change main to return a result and use anyhow");
+                ui_tx
+                    .send(UiCommand::SampleRate(audio_engine_state.sample_rate))
+                    .expect("Failed to send play to audio thread");
+
+                state = PlayerState::Playing;
+            }
+            PlayerState::Paused => {
+                // don't decode AND don't flush the buffer?
+            }
+            PlayerState::Unstarted => {}
+        }
+    }
 }
 
+/// Applies the next pending `AudioCommand`, if any. Returns `false` once the sender is gone.
+#[allow(clippy::too_many_arguments)]
 fn process_audio_cmd(
     audio_rx: &Receiver<AudioCommand>,
     state: &mut PlayerState,
     volume: &mut f32,
+    crossfade_millis: &mut u32,
+    replay_gain: &mut ReplayGainSettings,
+    playing_gain: &mut ReplayGain,
+    next_track: &mut Option<PreloadedTrack>,
+    pending_track: &mut Option<PendingTrack>,
     is_processing_ui_change: &Arc<AtomicBool>,
-) {
+) -> bool {
     match audio_rx.try_recv() {
         Ok(cmd) => {
             //Process Start
@@ -298,9 +504,44 @@ fn process_audio_cmd(
                     tracing::info!("Processing PLAY command");
                     *state = PlayerState::Playing;
                 }
-                AudioCommand::LoadFile(path) => {
+                AudioCommand::LoadFile(path, track_gain) => {
                     tracing::info!("Processing LOAD FILE command for path: {:?}", &path);
-                    *state = PlayerState::LoadFile(path);
+                    *state = PlayerState::LoadFile(path, track_gain);
+                }
+                AudioCommand::QueueNext {
+                    path,
+                    crossfade,
+                    replay_gain: track_gain,
+                } => {
+                    tracing::info!("Processing QUEUE NEXT command for path: {:?}", &path);
+                    match (next_track.as_mut(), pending_track.as_mut(), path) {
+                        // Keep what was already decoded, it may be halfway through a crossfade.
+                        (Some(next), _, Some(path)) if next.path == path => {
+                            next.crossfade = crossfade;
+                            next.engine_state.replay_gain = track_gain;
+                        }
+                        (_, Some(pending), Some(path)) if pending.path == path => {
+                            pending.crossfade = crossfade;
+                            pending.replay_gain = track_gain;
+                        }
+                        (_, _, path) => {
+                            *next_track = None;
+                            *pending_track =
+                                path.map(|path| PendingTrack::spawn(path, crossfade, track_gain));
+                        }
+                    }
+                }
+                AudioCommand::SetCrossfade(millis) => {
+                    tracing::info!("Processing SET CROSSFADE command to: {}ms", millis);
+                    *crossfade_millis = millis;
+                }
+                AudioCommand::SetReplayGain(settings) => {
+                    tracing::info!("Processing SET REPLAY GAIN command to: {:?}", &settings);
+                    *replay_gain = settings;
+                }
+                AudioCommand::SetTrackReplayGain(gain) => {
+                    tracing::info!("Processing SET TRACK REPLAY GAIN command to: {:?}", &gain);
+                    *playing_gain = gain;
                 }
                 AudioCommand::SetVolume(vol) => {
                     tracing::info!("Processing SET VOLUME command to: {:?}", &vol);
@@ -310,9 +551,13 @@ fn process_audio_cmd(
                 _ => tracing::warn!("Unhandled case in audio command loop"),
             }
         }
-        Err(_) => (), // When no commands are sent, this will evaluate. aka - it is the
-                      // common case. No need to print anything
+        // When no commands are sent, this will evaluate. aka - it is the common case. No need to
+        // print anything
+        Err(TryRecvError::Empty) => (),
+        Err(TryRecvError::Disconnected) => return false,
     }
+
+    true
 }
 
 #[allow(dead_code)]
@@ -333,29 +578,218 @@ pub enum PlayerState {
     Stopped,
     Playing,
     Paused,
-    LoadFile(PathBuf),
+    LoadFile(PathBuf, ReplayGain),
     SeekTo(u64),
 }
 
 struct AudioEngineState {
     pub reader: Option<Box<dyn FormatReader>>,
-    pub audio_output: Option<Box<dyn output::AudioOutput>>,
     pub track_num: Option<usize>,
     pub seek: Option<SeekPosition>,
     pub decode_opts: Option<DecoderOptions>,
     pub track_info: Option<PlayTrackOptions>,
     pub duration: u64,
     pub sample_rate: f32,
+    pub replay_gain: ReplayGain,
+}
+
+impl AudioEngineState {
+    fn new() -> Self {
+        Self {
+            reader: None,
+            track_num: None,
+            seek: None,
+            decode_opts: None,
+            track_info: None,
+            duration: 0,
+            sample_rate: 44100.0,
+            replay_gain: ReplayGain::default(),
+        }
+    }
+}
+
+/// The track after the current one, opened ahead of time so the audio thread can decode straight
+/// into it when the current track ends.
+struct PreloadedTrack {
+    path: PathBuf,
+    engine_state: AudioEngineState,
+    decoder: Option<Box<dyn Decoder>>,
+    // Whether the current track should fade into this one instead of ending first.
+    crossfade: bool,
+}
+
+impl PreloadedTrack {
+    fn load(path: PathBuf, crossfade: bool, replay_gain: ReplayGain) -> Option<Self> {
+        let mut engine_state = AudioEngineState::new();
+        engine_state.replay_gain = replay_gain;
+        let mut decoder = None;
+
+        // The current track just ends instead of continuing into this one.
+        if let Err(err) = load_file(&path, &mut engine_state, &mut decoder, 0) {
+            tracing::warn!("Couldn't preload next track {:?}: {}", &path, err);
+            return None;
+        }
+
+        Some(Self {
+            path,
+            engine_state,
+            decoder,
+            crossfade,
+        })
+    }
+}
+
+/// A `PreloadedTrack` being opened on another thread, so probing the file and creating its decoder
+/// doesn't hold up the audio output.
+struct PendingTrack {
+    path: PathBuf,
+    // Kept up to date by `QueueNext` while the track loads.
+    crossfade: bool,
+    replay_gain: ReplayGain,
+    loaded_rx: Receiver<Option<PreloadedTrack>>,
+}
+
+impl PendingTrack {
+    fn spawn(path: PathBuf, crossfade: bool, replay_gain: ReplayGain) -> Self {
+        let (loaded_tx, loaded_rx) = channel();
+        let load_path = path.clone();
+        thread::spawn(move || {
+            // The receiver is gone if another track was queued in the meantime.
+            _ = loaded_tx.send(PreloadedTrack::load(load_path, crossfade, replay_gain));
+        });
+
+        Self {
+            path,
+            crossfade,
+            replay_gain,
+            loaded_rx,
+        }
+    }
+
+    /// Returns the loaded track, `None` if it failed to load, or itself while it's still loading.
+    fn try_finish(self) -> std::result::Result<Option<PreloadedTrack>, Self> {
+        match self.loaded_rx.try_recv() {
+            Ok(loaded) => Ok(self.apply(loaded)),
+            Err(TryRecvError::Empty) => Err(self),
+            Err(TryRecvError::Disconnected) => Ok(None),
+        }
+    }
+
+    /// Waits for the track to finish loading.
+    fn finish(self) -> Option<PreloadedTrack> {
+        let loaded = self.loaded_rx.recv().ok().flatten();
+        self.apply(loaded)
+    }
+
+    fn apply(&self, loaded: Option<PreloadedTrack>) -> Option<PreloadedTrack> {
+        loaded.map(|mut track| {
+            track.crossfade = self.crossfade;
+            track.engine_state.replay_gain = self.replay_gain;
+            track
+        })
+    }
+}
+
+/// Decodes the next track until at least `frames` of it are buffered for mixing, or it ends.
+/// Returns `false` if its signal doesn't match the current track's, so they can't be mixed.
+fn fill_crossfade(
+    crossfade: &mut Crossfade,
+    next: &mut PreloadedTrack,
+    frames: usize,
+    replay_gain: &ReplayGainSettings,
+) -> bool {
+    let scale = next.engine_state.replay_gain.scale(replay_gain);
+
+    let (Some(reader), Some(decoder), Some(track_info)) = (
+        next.engine_state.reader.as_mut(),
+        next.decoder.as_mut(),
+        next.engine_state.track_info,
+    ) else {
+        return false;
+    };
+
+    while crossfade.buffered() < frames {
+        let Ok(packet) = reader.next_packet() else {
+            break;
+        };
+
+        if packet.track_id() != track_info.track_id {
+            continue;
+        }
+
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                if *decoded.spec() != crossfade.spec() {
+                    return false;
+                }
+
+                crossfade.push_incoming(&decoded, scale);
+            }
+            Err(Error::DecodeError(err)) => tracing::warn!("decode error: {}", err),
+            Err(_) => break,
+        }
+    }
+
+    true
+}
+
+/// Only (re)opens the output when there is none or the signal changed, so consecutive tracks
+/// with the same sample rate and channel layout play through one uninterrupted stream.
+fn ensure_output(
+    audio_output: &mut Option<Box<dyn output::AudioOutput>>,
+    output_spec: &mut Option<(SignalSpec, u64)>,
+    output_device: &AudioOutputDevice,
+    spec: SignalSpec,
+    duration: u64,
+) -> output::Result<()> {
+    let needs_reopen = match *output_spec {
+        Some((open_spec, open_duration)) => open_spec != spec || duration > open_duration,
+        None => true,
+    };
+
+    if audio_output.is_none() || needs_reopen {
+        if let Some(audio_output) = audio_output.as_mut() {
+            tracing::info!("AudioThread signal changed - reopening output");
+            audio_output.flush();
+        }
+
+        // Drop the old output first, so a failed reopen doesn't leave it in use.
+        *audio_output = None;
+        *output_spec = None;
+        *audio_output = Some(output::try_open(output_device, spec, duration)?);
+        *output_spec = Some((spec, duration));
+    }
+
+    Ok(())
 }
 
+/// Playback can't go on without an output, so stop instead of decoding into the void.
+fn stop_on_output_error(
+    err: output::AudioOutputError,
+    state: &mut PlayerState,
+    ui_tx: &Sender<UiCommand>,
+) {
+    tracing::error!("AudioThread's audio output failed: {:?}", err);
+    *state = PlayerState::Stopped;
+    ui_tx
+        .send(UiCommand::AudioStopped)
+        .expect("Failed to send stopped to ui thread");
+}
+
+/// Opens `path` and creates its decoder. `audio_engine_state` and `decoder` are only replaced
+/// once the track is ready to play, so a failed load leaves the previous track intact.
 fn load_file(
     path: &PathBuf,
     audio_engine_state: &mut AudioEngineState,
-    decoder: &mut Option<Box<dyn symphonia::core::codecs::Decoder>>,
+    decoder: &mut Option<Box<dyn Decoder>>,
     seek_timestamp: u64,
-) {
-    let hint = Hint::new();
-    let source = Box::new(std::fs::File::open(path).expect("couldn't open file"));
+) -> Result<()> {
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let source = Box::new(std::fs::File::open(path)?);
     let mss = MediaSourceStream::new(source, Default::default());
     let format_opts = FormatOptions {
         enable_gapless: true,
@@ -364,66 +798,59 @@ fn load_file(
     let metadata_opts: MetadataOptions = Default::default();
     let seek = Some(SeekPosition::Timestamp(seek_timestamp));
 
-    match symphonia::default::get_probe().format(&hint, mss, &format_opts, &metadata_opts) {
-        Ok(probed) => {
-            // Set the decoder options.
-            let decode_opts = DecoderOptions {
-                verify: true,
-                ..Default::default()
-            };
-
-            audio_engine_state.reader = Some(probed.format);
-            audio_engine_state.decode_opts = Some(decode_opts);
-            audio_engine_state.seek = seek;
-
-            // Configure everything for playback.
-            _ = setup_audio_reader(audio_engine_state);
-
-            let reader = audio_engine_state.reader.as_mut().unwrap();
-            let play_opts = audio_engine_state.track_info.unwrap();
-            let decode_opts = audio_engine_state.decode_opts.unwrap();
-
-            let track = match reader
-                .tracks()
-                .iter()
-                .find(|track| track.id == play_opts.track_id)
-            {
-                Some(track) => track,
-                _ => {
-                    tracing::warn!("Couldn't find track");
-                    return ();
-                }
-            };
-
-            // Create a decoder for the track.
-            *decoder = Some(
-                symphonia::default::get_codecs()
-                    .make(&track.codec_params, &decode_opts)
-                    .expect("Failed to get decoder"),
-            );
-
-            // Get the selected track's timebase and duration.
-            if let Some(time_base) = track.codec_params.time_base {
-                audio_engine_state.sample_rate = time_base.denom as f32;
-            }
+    let probed =
+        symphonia::default::get_probe().format(&hint, mss, &format_opts, &metadata_opts)?;
 
-            let dur = track
-                .codec_params
-                .n_frames
-                .map(|frames| track.codec_params.start_ts + frames);
+    // Set the decoder options.
+    let decode_opts = DecoderOptions {
+        verify: true,
+        ..Default::default()
+    };
 
-            if let Some(duration) = dur {
-                audio_engine_state.duration = duration;
+    let mut loaded = AudioEngineState::new();
+    loaded.track_num = audio_engine_state.track_num;
+    loaded.replay_gain = audio_engine_state.replay_gain;
+    loaded.reader = Some(probed.format);
+    loaded.decode_opts = Some(decode_opts);
+    loaded.seek = seek;
+
+    // Configure everything for playback.
+    _ = setup_audio_reader(&mut loaded);
+
+    let reader = loaded.reader.as_ref().unwrap();
+    let track = match loaded.track_info.and_then(|play_opts| {
+        reader
+            .tracks()
+            .iter()
+            .find(|track| track.id == play_opts.track_id)
+    }) {
+        Some(track) => track,
+        _ => return Err(Error::Unsupported("no playable track")),
+    };
 
-                tracing::info!("Track Duration: {}", duration);
-            }
-        }
-        Err(err) => {
-            // The input was not supported by any format reader.
-            tracing::warn!("the audio format is not supported: {}", err);
-            // Err(err);
-        }
+    // Create a decoder for the track.
+    let track_decoder = symphonia::default::get_codecs().make(&track.codec_params, &decode_opts)?;
+
+    // Get the selected track's timebase and duration.
+    if let Some(time_base) = track.codec_params.time_base {
+        loaded.sample_rate = time_base.denom as f32;
     }
+
+    let dur = track
+        .codec_params
+        .n_frames
+        .map(|frames| track.codec_params.start_ts + frames);
+
+    if let Some(duration) = dur {
+        loaded.duration = duration;
+
+        tracing::info!("Track Duration: {}", duration);
+    }
+
+    *audio_engine_state = loaded;
+    *decoder = Some(track_decoder);
+
+    Ok(())
 }
 
 fn setup_audio_reader(audio_engine_state: &mut AudioEngineState) -> Result<i32> {
@@ -517,3 +944,322 @@ fn do_verification(finalization: FinalizeResult) -> Result<i32> {
         _ => Ok(0),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use output::wav::{TestDir, WavFile};
+    use std::path::Path;
+    use std::time::Duration;
+    use symphonia::core::audio::Channels;
+
+    const RATE: u32 = 44100;
+    const CHANNELS: u16 = 2;
+    const FRAMES: usize = 44100;
+
+    // Writes one second of a stereo 440Hz sine as a 32-bit float WAV.
+    fn write_fixture(path: &Path) -> Vec<f32> {
+        let samples = (0..FRAMES)
+            .flat_map(|i| {
+                let s = (i as f32 * 440.0 * std::f32::consts::TAU / RATE as f32).sin() * 0.8;
+                [s, s]
+            })
+            .collect::<Vec<f32>>();
+
+        let spec = SignalSpec::new(RATE, Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+        output::wav::write_file(path, spec, &samples).unwrap();
+        samples
+    }
+
+    fn read_wav_samples(path: &Path) -> Vec<f32> {
+        std::fs::read(path).unwrap()[44..]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()
+    }
+
+    fn run_until_finished(device: AudioOutputDevice, cmds: Vec<AudioCommand>) -> Vec<UiCommand> {
+        run_until(device, cmds, |cmd| matches!(cmd, UiCommand::AudioFinished))
+    }
+
+    // Queues the commands before the engine starts so they are processed in order, then runs
+    // the engine until it sends the command `is_last` looks for.
+    fn run_until(
+        device: AudioOutputDevice,
+        cmds: Vec<AudioCommand>,
+        is_last: impl Fn(&UiCommand) -> bool,
+    ) -> Vec<UiCommand> {
+        let (audio_tx, audio_rx) = channel();
+        let (ui_tx, ui_rx) = channel();
+        let gui_ring_buf = SpscRb::new(4096);
+
+        for cmd in cmds {
+            audio_tx.send(cmd).unwrap();
+        }
+
+        let producer = gui_ring_buf.producer();
+        let engine = thread::spawn(move || {
+            run_audio_engine(
+                audio_rx,
+                ui_tx,
+                producer,
+                Arc::new(AtomicBool::new(false)),
+                Arc::new(AtomicBool::new(false)),
+                device,
+            )
+        });
+
+        let mut received = vec![];
+        loop {
+            let cmd = ui_rx
+                .recv_timeout(Duration::from_secs(10))
+                .expect("audio engine never finished the track");
+            let finished = is_last(&cmd);
+            received.push(cmd);
+
+            if finished {
+                break;
+            }
+        }
+
+        drop(audio_tx);
+        engine.join().unwrap();
+        received
+    }
+
+    #[test]
+    fn plays_to_end_of_track_on_null_output() {
+        let dir = TestDir::new("null");
+        let input = dir.join("input.wav");
+        write_fixture(&input);
+
+        let received = run_until_finished(
+            AudioOutputDevice::Null { realtime: false },
+            vec![AudioCommand::LoadFile(input.clone(), ReplayGain::default())],
+        );
+
+        assert!(received
+            .iter()
+            .any(|cmd| matches!(cmd, UiCommand::TotalTrackDuration(d) if *d == FRAMES as u64)));
+        assert!(received
+            .iter()
+            .any(|cmd| matches!(cmd, UiCommand::SampleRate(sr) if *sr == RATE as f32)));
+    }
+
+    #[test]
+    fn renders_track_to_wav_output_with_volume() {
+        let dir = TestDir::new("volume");
+        let input = dir.join("input.wav");
+        let output = dir.join("output.wav");
+        let samples = write_fixture(&input);
+
+        run_until_finished(
+            AudioOutputDevice::Wav(WavFile::new(&output)),
+            vec![
+                AudioCommand::SetVolume(0.5),
+                AudioCommand::LoadFile(input.clone(), ReplayGain::default()),
+            ],
+        );
+
+        let rendered = read_wav_samples(&output);
+        assert_eq!(rendered.len(), samples.len());
+        assert!(rendered
+            .iter()
+            .zip(samples.iter())
+            .all(|(r, s)| (r - s * 0.5).abs() < 1e-6));
+    }
+
+    #[test]
+    fn applies_track_replay_gain() {
+        let dir = TestDir::new("replay-gain");
+        let input = dir.join("input.wav");
+        let output = dir.join("output.wav");
+        let samples = write_fixture(&input);
+        // -6.0206 dB halves the amplitude, and the tagged peak is low enough not to limit it.
+        let track_gain = ReplayGain {
+            track_gain: Some(-20.0 * 2.0f32.log10()),
+            track_peak: Some(0.8),
+            ..Default::default()
+        };
+
+        run_until_finished(
+            AudioOutputDevice::Wav(WavFile::new(&output)),
+            vec![
+                AudioCommand::SetReplayGain(ReplayGainSettings {
+                    mode: app::replay_gain::ReplayGainMode::Track,
+                    preamp_db: 0.0,
+                    prevent_clipping: true,
+                }),
+                AudioCommand::LoadFile(input.clone(), track_gain),
+            ],
+        );
+
+        let rendered = read_wav_samples(&output);
+        assert_eq!(rendered.len(), samples.len());
+        assert!(rendered
+            .iter()
+            .zip(samples.iter())
+            .all(|(r, s)| (r - s * 0.5).abs() < 1e-6));
+    }
+
+    #[test]
+    fn seeking_skips_to_timestamp() {
+        let dir = TestDir::new("seek");
+        let input = dir.join("input.wav");
+        let output = dir.join("output.wav");
+        let samples = write_fixture(&input);
+        let seek_ts = FRAMES as u64 / 2;
+
+        run_until_finished(
+            AudioOutputDevice::Wav(WavFile::new(&output)),
+            vec![
+                AudioCommand::LoadFile(input.clone(), ReplayGain::default()),
+                AudioCommand::Seek(seek_ts),
+            ],
+        );
+
+        // Seeking discards whole packets before the seek timestamp, so playback resumes on the
+        // first packet boundary at or after it.
+        let rendered = read_wav_samples(&output);
+        let skipped_frames = (samples.len() - rendered.len()) / CHANNELS as usize;
+        assert!(skipped_frames >= seek_ts as usize);
+        assert!(skipped_frames < seek_ts as usize + 4096);
+        assert_eq!(rendered[..], samples[skipped_frames * CHANNELS as usize..]);
+    }
+
+    #[test]
+    fn queued_track_plays_without_a_gap() {
+        let dir = TestDir::new("gapless");
+        let first = dir.join("first.wav");
+        let second = dir.join("second.wav");
+        let output = dir.join("output.wav");
+        let first_samples = write_fixture(&first);
+        let second_samples = write_fixture(&second);
+
+        let received = run_until_finished(
+            AudioOutputDevice::Wav(WavFile::new(&output)),
+            vec![
+                AudioCommand::LoadFile(first.clone(), ReplayGain::default()),
+                // Tracks from the same album are queued without a crossfade.
+                AudioCommand::SetCrossfade(250),
+                AudioCommand::QueueNext {
+                    path: Some(second.clone()),
+                    crossfade: false,
+                    replay_gain: ReplayGain::default(),
+                },
+            ],
+        );
+
+        assert!(received
+            .iter()
+            .any(|cmd| matches!(cmd, UiCommand::TrackAdvanced(path) if *path == second)));
+
+        let rendered = read_wav_samples(&output);
+        assert_eq!(rendered.len(), first_samples.len() + second_samples.len());
+        assert_eq!(rendered[..first_samples.len()], first_samples[..]);
+        assert_eq!(rendered[first_samples.len()..], second_samples[..]);
+    }
+
+    #[test]
+    fn unplayable_queued_track_ends_the_current_one() {
+        let dir = TestDir::new("unplayable");
+        let first = dir.join("first.wav");
+        let second = dir.join("second.wav");
+        write_fixture(&first);
+        std::fs::write(&second, b"not audio").unwrap();
+
+        let received = run_until_finished(
+            AudioOutputDevice::Null { realtime: false },
+            vec![
+                AudioCommand::LoadFile(first.clone(), ReplayGain::default()),
+                AudioCommand::QueueNext {
+                    path: Some(second.clone()),
+                    crossfade: false,
+                    replay_gain: ReplayGain::default(),
+                },
+            ],
+        );
+
+        assert!(!received
+            .iter()
+            .any(|cmd| matches!(cmd, UiCommand::TrackAdvanced(_))));
+    }
+
+    #[test]
+    fn reports_missing_track_and_plays_the_next() {
+        let dir = TestDir::new("missing");
+        let missing = dir.join("missing.wav");
+        let input = dir.join("input.wav");
+        write_fixture(&input);
+
+        let received = run_until_finished(
+            AudioOutputDevice::Null { realtime: false },
+            vec![
+                AudioCommand::LoadFile(missing.clone(), ReplayGain::default()),
+                AudioCommand::LoadFile(input.clone(), ReplayGain::default()),
+            ],
+        );
+
+        assert!(matches!(&received[0], UiCommand::TrackFailed(path) if *path == missing));
+        assert!(received
+            .iter()
+            .any(|cmd| matches!(cmd, UiCommand::TotalTrackDuration(d) if *d == FRAMES as u64)));
+    }
+
+    // Ex. a full disk, which stops playback instead of panicking the audio thread.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn stops_when_the_output_fails() {
+        let dir = TestDir::new("full-disk");
+        let input = dir.join("input.wav");
+        write_fixture(&input);
+
+        let received = run_until(
+            AudioOutputDevice::Wav(output::wav::WavFile::new("/dev/full")),
+            vec![AudioCommand::LoadFile(input.clone(), ReplayGain::default())],
+            |cmd| matches!(cmd, UiCommand::AudioStopped | UiCommand::AudioFinished),
+        );
+
+        assert!(matches!(received.last(), Some(UiCommand::AudioStopped)));
+    }
+
+    #[test]
+    fn queued_track_crossfades_into_the_next() {
+        let dir = TestDir::new("crossfade");
+        let first = dir.join("first.wav");
+        let second = dir.join("second.wav");
+        let output = dir.join("output.wav");
+        let first_samples = write_fixture(&first);
+        let second_samples = write_fixture(&second);
+
+        let received = run_until_finished(
+            AudioOutputDevice::Wav(WavFile::new(&output)),
+            vec![
+                AudioCommand::LoadFile(first.clone(), ReplayGain::default()),
+                AudioCommand::SetCrossfade(250),
+                AudioCommand::QueueNext {
+                    path: Some(second.clone()),
+                    crossfade: true,
+                    replay_gain: ReplayGain::default(),
+                },
+            ],
+        );
+
+        assert!(received
+            .iter()
+            .any(|cmd| matches!(cmd, UiCommand::TrackAdvanced(path) if *path == second)));
+
+        // The fade starts on the first packet inside the last 250ms of the first track.
+        let crossfade_frames = RATE as usize / 4;
+        let rendered = read_wav_samples(&output);
+        let overlap_frames =
+            (first_samples.len() + second_samples.len() - rendered.len()) / CHANNELS as usize;
+        assert!(overlap_frames <= crossfade_frames);
+        assert!(overlap_frames + 4096 > crossfade_frames);
+
+        let overlap = overlap_frames * CHANNELS as usize;
+        let fade_start = first_samples.len() - overlap;
+        assert_eq!(rendered[..fade_start], first_samples[..fade_start]);
+        assert_eq!(rendered[fade_start + overlap..], second_samples[overlap..]);
+    }
+}
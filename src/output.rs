@@ -5,13 +5,15 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-//! Platform-dependant Audio Outputs
+//! Platform-dependant Audio Outputs, plus offline sinks for running without a sound card.
 
 use std::result;
 
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 use symphonia::core::audio::{AudioBufferRef, SignalSpec};
+use symphonia::core::conv::{FromSample, IntoSample};
+use symphonia::core::sample::Sample;
 use symphonia::core::units::Duration;
 
 pub trait AudioOutput {
@@ -37,146 +39,86 @@ pub enum AudioOutputError {
 
 pub type Result<T> = result::Result<T, AudioOutputError>;
 
-/*
-#[cfg(target_os = "linux")]
-mod pulseaudio {
-    use super::{AudioOutput, AudioOutputError, Result};
-
-    use symphonia::core::audio::*;
-    use symphonia::core::units::Duration;
-
-    use libpulse_binding as pulse;
-    use libpulse_simple_binding as psimple;
-
-    use log::{error, warn};
+/// Selects which `AudioOutput` the audio thread opens.
+#[derive(Clone)]
+pub enum AudioOutputDevice {
+    /// The default output device of the platform's cpal host.
+    Default,
+    /// Discards every sample. When `realtime` is set, writes are paced to the sample rate of the
+    /// signal so a track takes as long to "play" as it would on a sound card.
+    Null { realtime: bool },
+    /// Renders the interleaved f32 stream into a WAV file.
+    Wav(wav::WavFile),
+}
 
-    pub struct PulseAudioOutput {
-        pa: psimple::Simple,
-        sample_buf: RawSampleBuffer<f32>,
+impl AudioOutputDevice {
+    /// Reads the output device from the `MUSIC_PLAYER_OUTPUT` environment variable, which accepts
+    /// `null`, `null:realtime` and `wav:<path>`. Anything else selects the default device.
+    pub fn from_env() -> Self {
+        match std::env::var("MUSIC_PLAYER_OUTPUT") {
+            Ok(value) => Self::parse(&value),
+            Err(_) => Self::Default,
+        }
     }
 
-    impl PulseAudioOutput {
-        pub fn try_open(spec: SignalSpec, duration: Duration) -> Result<Box<dyn AudioOutput>> {
-            // An interleaved buffer is required to send data to PulseAudio. Use a SampleBuffer to
-            // move data between Symphonia AudioBuffers and the byte buffers required by PulseAudio.
-            let sample_buf = RawSampleBuffer::<f32>::new(duration, spec);
-
-            // Create a PulseAudio stream specification.
-            let pa_spec = pulse::sample::Spec {
-                format: pulse::sample::Format::FLOAT32NE,
-                channels: spec.channels.count() as u8,
-                rate: spec.rate,
-            };
-
-            assert!(pa_spec.is_valid());
-
-            let pa_ch_map = map_channels_to_pa_channelmap(spec.channels);
-
-            // PulseAudio seems to not play very short audio buffers, use these custom buffer
-            // attributes for very short audio streams.
-            //
-            // let pa_buf_attr = pulse::def::BufferAttr {
-            //     maxlength: std::u32::MAX,
-            //     tlength: 1024,
-            //     prebuf: std::u32::MAX,
-            //     minreq: std::u32::MAX,
-            //     fragsize: std::u32::MAX,
-            // };
-
-            // Create a PulseAudio connection.
-            let pa_result = psimple::Simple::new(
-                None,                               // Use default server
-                "Symphonia Player",                 // Application name
-                pulse::stream::Direction::Playback, // Playback stream
-                None,                               // Default playback device
-                "Music",                            // Description of the stream
-                &pa_spec,                           // Signal specification
-                pa_ch_map.as_ref(),                 // Channel map
-                None,                               // Custom buffering attributes
-            );
-
-            match pa_result {
-                Ok(pa) => Ok(Box::new(PulseAudioOutput { pa, sample_buf })),
-                Err(err) => {
-                    error!("audio output stream open error: {}", err);
-
-                    Err(AudioOutputError::OpenStreamError)
+    fn parse(value: &str) -> Self {
+        match value {
+            "null" => Self::Null { realtime: false },
+            "null:realtime" => Self::Null { realtime: true },
+            _ => match value.strip_prefix("wav:") {
+                Some(path) if !path.is_empty() => Self::Wav(wav::WavFile::new(path)),
+                _ => {
+                    if !value.is_empty() {
+                        log::warn!("unknown audio output '{}', using the default device", value);
+                    }
+                    Self::Default
                 }
-            }
+            },
         }
     }
+}
 
-    impl AudioOutput for PulseAudioOutput {
-        fn write(&mut self, decoded: AudioBufferRef<'_>, gui_ring_buf_producer: rb::Producer<f32>) -> Result<()> {
-            // Do nothing if there are no audio frames.
-            if decoded.frames() == 0 {
-                return Ok(());
-            }
-
-            // Interleave samples from the audio buffer into the sample buffer.
-            self.sample_buf.copy_interleaved_ref(decoded);
-
-            // Write interleaved samples to PulseAudio.
-            match self.pa.write(self.sample_buf.as_bytes()) {
-                Err(err) => {
-                    error!("audio output stream write error: {}", err);
-
-                    Err(AudioOutputError::StreamClosedError)
-                }
-                _ => Ok(()),
-            }
-        }
+/// Copies the interleaved samples into the GUI ring buffer when a visualization is listening.
+fn write_gui_samples(
+    samples: &[f32],
+    gui_ring_buf_producer: &rb::Producer<f32>,
+    process_gui_samples: &Arc<AtomicBool>,
+) {
+    use rb::RbProducer;
 
-        fn flush(&mut self) {
-            // Flush is best-effort, ignore the returned result.
-            let _ = self.pa.drain();
-        }
+    if process_gui_samples.load(std::sync::atomic::Ordering::Relaxed) {
+        let _written_count_to_scope = gui_ring_buf_producer.write(samples);
     }
+}
 
-    /// Maps a set of Symphonia `Channels` to a PulseAudio channel map.
-    fn map_channels_to_pa_channelmap(channels: Channels) -> Option<pulse::channelmap::Map> {
-        let mut map: pulse::channelmap::Map = Default::default();
-        map.init();
-        map.set_len(channels.count() as u8);
-
-        let is_mono = channels.count() == 1;
-
-        for (i, channel) in channels.iter().enumerate() {
-            map.get_mut()[i] = match channel {
-                Channels::FRONT_LEFT if is_mono => pulse::channelmap::Position::Mono,
-                Channels::FRONT_LEFT => pulse::channelmap::Position::FrontLeft,
-                Channels::FRONT_RIGHT => pulse::channelmap::Position::FrontRight,
-                Channels::FRONT_CENTRE => pulse::channelmap::Position::FrontCenter,
-                Channels::REAR_LEFT => pulse::channelmap::Position::RearLeft,
-                Channels::REAR_CENTRE => pulse::channelmap::Position::RearCenter,
-                Channels::REAR_RIGHT => pulse::channelmap::Position::RearRight,
-                Channels::LFE1 => pulse::channelmap::Position::Lfe,
-                Channels::FRONT_LEFT_CENTRE => pulse::channelmap::Position::FrontLeftOfCenter,
-                Channels::FRONT_RIGHT_CENTRE => pulse::channelmap::Position::FrontRightOfCenter,
-                Channels::SIDE_LEFT => pulse::channelmap::Position::SideLeft,
-                Channels::SIDE_RIGHT => pulse::channelmap::Position::SideRight,
-                Channels::TOP_CENTRE => pulse::channelmap::Position::TopCenter,
-                Channels::TOP_FRONT_LEFT => pulse::channelmap::Position::TopFrontLeft,
-                Channels::TOP_FRONT_CENTRE => pulse::channelmap::Position::TopFrontCenter,
-                Channels::TOP_FRONT_RIGHT => pulse::channelmap::Position::TopFrontRight,
-                Channels::TOP_REAR_LEFT => pulse::channelmap::Position::TopRearLeft,
-                Channels::TOP_REAR_CENTRE => pulse::channelmap::Position::TopRearCenter,
-                Channels::TOP_REAR_RIGHT => pulse::channelmap::Position::TopRearRight,
-                _ => {
-                    // If a Symphonia channel cannot map to a PulseAudio position then return None
-                    // because PulseAudio will not be able to open a stream with invalid channels.
-                    warn!("failed to map channel {:?} to output", channel);
-                    return None;
-                }
-            }
-        }
+/// Maps interleaved frames of `from` channels onto `to` channels. Mono is copied to every output
+/// channel, a downmix averages the channels folded onto each output channel, and an upmix leaves
+/// the extra channels silent.
+fn remix<T>(samples: &[T], from: usize, to: usize, remixed: &mut Vec<T>)
+where
+    T: Sample + FromSample<f32> + IntoSample<f32>,
+{
+    remixed.clear();
+
+    for frame in samples.chunks_exact(from) {
+        for ch in 0..to {
+            let sample = if from == 1 {
+                frame[0]
+            } else if to > from {
+                frame.get(ch).copied().unwrap_or(T::MID)
+            } else {
+                let folded = frame.iter().skip(ch).step_by(to);
+                let (sum, count) = folded.fold((0.0f32, 0), |(sum, count), s| {
+                    (sum + (*s).into_sample(), count + 1)
+                });
+                T::from_sample(sum / count as f32)
+            };
 
-        Some(map)
+            remixed.push(sample);
+        }
     }
 }
-*/
 
-#[cfg(not(target_os = "linux"))]
 mod cpal {
     use crate::resampler::Resampler;
 
@@ -207,17 +149,25 @@ mod cpal {
         }
     }
 
-    // TODO - I don't think this will actually work as intended due to truncation?
     impl AudioOutputSample for i16 {
         fn mul(&self, n: f32) -> Self {
-            (*self as f32 * n) as i16
+            (*self as f32 * n).round() as i16
+        }
+    }
+
+    // An f32 can't hold every i32 exactly, so scale in f64 to keep the low bits.
+    impl AudioOutputSample for i32 {
+        fn mul(&self, n: f32) -> Self {
+            (*self as f64 * n as f64).round() as i32
         }
     }
 
-    // TODO - I don't think this will actually work as intended due to truncation?
+    // Unsigned samples are centered around u16::MID, so scale the distance from the midpoint
+    // instead of the raw value. Otherwise lowering the volume would add a DC offset.
     impl AudioOutputSample for u16 {
         fn mul(&self, n: f32) -> Self {
-            (*self as f32 * n) as u16
+            let mid = <u16 as cpal::Sample>::EQUILIBRIUM as f32;
+            ((*self as f32 - mid) * n + mid).round() as u16
         }
     }
 
@@ -254,7 +204,13 @@ mod cpal {
                 cpal::SampleFormat::U16 => {
                     CpalAudioOutputImpl::<u16>::try_open(spec, duration, &device)
                 }
-                _ => panic!("Unsupported sample format"),
+                cpal::SampleFormat::I32 => {
+                    CpalAudioOutputImpl::<i32>::try_open(spec, duration, &device)
+                }
+                format => {
+                    error!("unsupported audio output sample format: {}", format);
+                    Err(AudioOutputError::OpenStreamError)
+                }
             }
         }
     }
@@ -267,6 +223,11 @@ mod cpal {
         sample_buf: SampleBuffer<T>,
         stream: cpal::Stream,
         resampler: Option<Resampler<T>>,
+        // The decoded and the device's channel counts, and the buffer frames are remixed into
+        // when they differ.
+        channels: usize,
+        output_channels: usize,
+        remix_buf: Vec<T>,
     }
 
     impl<T: cpal::SizedSample + AudioOutputSample> CpalAudioOutputImpl<T>
@@ -281,7 +242,9 @@ mod cpal {
             let num_channels = spec.channels.count();
 
             // Output audio stream config.
-            let config = if cfg!(not(target_os = "windows")) {
+            let config = if cfg!(target_os = "linux") {
+                stream_config_for_spec::<T>(spec, device)?
+            } else if cfg!(not(target_os = "windows")) {
                 cpal::StreamConfig {
                     channels: num_channels as cpal::ChannelCount,
                     sample_rate: cpal::SampleRate(spec.rate),
@@ -348,15 +311,82 @@ mod cpal {
                 None
             };
 
+            let output_channels = config.channels as usize;
+            if output_channels != num_channels {
+                info!(
+                    "remixing {} channel(s) to play on {} channel(s)",
+                    num_channels, output_channels
+                );
+            }
+
             Ok(Box::new(CpalAudioOutputImpl {
                 ring_buf_producer,
                 sample_buf,
                 stream,
                 resampler,
+                channels: num_channels,
+                output_channels,
+                remix_buf: Vec::new(),
             }))
         }
     }
 
+    /// ALSA devices (including the PulseAudio/PipeWire plugins) don't always accept an arbitrary
+    /// stream config, so look for one that matches the decoded signal before falling back to the
+    /// closest supported channel count and sample rate. The output remixes and the resampler picks
+    /// up any remaining difference.
+    fn stream_config_for_spec<T: cpal::SizedSample>(
+        spec: SignalSpec,
+        device: &cpal::Device,
+    ) -> Result<cpal::StreamConfig> {
+        let num_channels = spec.channels.count() as cpal::ChannelCount;
+
+        let ranges = match device.supported_output_configs() {
+            Ok(ranges) => ranges
+                .filter(|range| range.sample_format() == T::FORMAT)
+                .collect::<Vec<_>>(),
+            Err(err) => {
+                error!("failed to get supported audio output configs: {}", err);
+                return Err(AudioOutputError::OpenStreamError);
+            }
+        };
+
+        // On a tie, prefer more channels so mono still plays on both speakers.
+        let channels = ranges
+            .iter()
+            .map(|range| range.channels())
+            .min_by_key(|&channels| (channels.abs_diff(num_channels), channels < num_channels));
+        let ranges = ranges
+            .into_iter()
+            .filter(|range| Some(range.channels()) == channels)
+            .collect::<Vec<_>>();
+
+        let exact = ranges.iter().find(|range| {
+            range.min_sample_rate().0 <= spec.rate && spec.rate <= range.max_sample_rate().0
+        });
+
+        let supported = match exact {
+            Some(range) => range.with_sample_rate(cpal::SampleRate(spec.rate)),
+            None => match ranges.into_iter().next() {
+                Some(range) => {
+                    let rate = spec
+                        .rate
+                        .clamp(range.min_sample_rate().0, range.max_sample_rate().0);
+                    range.with_sample_rate(cpal::SampleRate(rate))
+                }
+                None => {
+                    error!("audio output device does not support {:?}", T::FORMAT);
+                    return Err(AudioOutputError::OpenStreamError);
+                }
+            },
+        };
+
+        let mut config = supported.config();
+        config.buffer_size = cpal::BufferSize::Default;
+
+        Ok(config)
+    }
+
     impl<T: AudioOutputSample> AudioOutput for CpalAudioOutputImpl<T>
     where
         f32: cpal::FromSample<T>,
@@ -386,6 +416,16 @@ mod cpal {
                 self.sample_buf.samples()
             };
 
+            if self.channels != self.output_channels {
+                super::remix(
+                    samples,
+                    self.channels,
+                    self.output_channels,
+                    &mut self.remix_buf,
+                );
+                samples = &self.remix_buf;
+            }
+
             // TODO - Probably don't need to map the samples twice to be sent to different places.
             // One reason this is difficult is the second write expects a &[T] where the gui RB
             // expects a &[f32]. Maybe the audio sample buffer can be changed to handle only f32
@@ -418,6 +458,16 @@ mod cpal {
             if let Some(resampler) = &mut self.resampler {
                 let mut remaining_samples = resampler.flush().unwrap_or_default();
 
+                if self.channels != self.output_channels {
+                    super::remix(
+                        remaining_samples,
+                        self.channels,
+                        self.output_channels,
+                        &mut self.remix_buf,
+                    );
+                    remaining_samples = &self.remix_buf;
+                }
+
                 while let Some(written) = self.ring_buf_producer.write_blocking(remaining_samples) {
                     remaining_samples = &remaining_samples[written..];
                 }
@@ -429,14 +479,398 @@ mod cpal {
     }
 }
 
-/*
-#[cfg(target_os = "linux")]
-pub fn try_open(spec: SignalSpec, duration: Duration) -> Result<Box<dyn AudioOutput>> {
-    pulseaudio::PulseAudioOutput::try_open(spec, duration)
+mod null {
+    use super::{write_gui_samples, AudioOutput, Result};
+
+    use symphonia::core::audio::{AudioBufferRef, SampleBuffer, SignalSpec};
+    use symphonia::core::units::Duration;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    pub struct NullAudioOutput {
+        sample_buf: SampleBuffer<f32>,
+        sample_rate: u32,
+        realtime: bool,
+        started_at: Option<Instant>,
+        frames_written: u64,
+    }
+
+    impl NullAudioOutput {
+        pub fn try_open(
+            spec: SignalSpec,
+            duration: Duration,
+            realtime: bool,
+        ) -> Result<Box<dyn AudioOutput>> {
+            Ok(Box::new(NullAudioOutput {
+                sample_buf: SampleBuffer::<f32>::new(duration, spec),
+                sample_rate: spec.rate,
+                realtime,
+                started_at: None,
+                frames_written: 0,
+            }))
+        }
+    }
+
+    impl AudioOutput for NullAudioOutput {
+        fn write(
+            &mut self,
+            decoded: AudioBufferRef<'_>,
+            gui_ring_buf_producer: &rb::Producer<f32>,
+            process_gui_samples: &Arc<AtomicBool>,
+            volume: f32,
+        ) -> Result<()> {
+            let frames = decoded.frames();
+
+            if frames == 0 {
+                return Ok(());
+            }
+
+            // Only interleave the samples when something is going to look at them.
+            if process_gui_samples.load(Ordering::Relaxed) {
+                self.sample_buf.copy_interleaved_ref(decoded);
+                let samples = self
+                    .sample_buf
+                    .samples()
+                    .iter()
+                    .map(|s| s * volume)
+                    .collect::<Vec<f32>>();
+
+                write_gui_samples(&samples, gui_ring_buf_producer, process_gui_samples);
+            }
+
+            if self.realtime {
+                // Pace against the wall clock instead of sleeping for each buffer, so the time
+                // spent decoding doesn't accumulate as drift.
+                let started_at = *self.started_at.get_or_insert_with(Instant::now);
+                self.frames_written += frames as u64;
+
+                let due = std::time::Duration::from_secs_f64(
+                    self.frames_written as f64 / self.sample_rate as f64,
+                );
+
+                if let Some(remaining) = due.checked_sub(started_at.elapsed()) {
+                    std::thread::sleep(remaining);
+                }
+            }
+
+            Ok(())
+        }
+
+        fn flush(&mut self) {
+            self.started_at = None;
+            self.frames_written = 0;
+        }
+    }
 }
-*/
 
-#[cfg(not(target_os = "linux"))]
-pub fn try_open(spec: SignalSpec, duration: Duration) -> Result<Box<dyn AudioOutput>> {
-    cpal::CpalAudioOutput::try_open(spec, duration)
+pub mod wav {
+    use super::{write_gui_samples, AudioOutput, AudioOutputError, Result};
+
+    use symphonia::core::audio::{AudioBufferRef, SampleBuffer, SignalSpec};
+    use symphonia::core::units::Duration;
+
+    use log::error;
+    use std::fs::File;
+    use std::io::{BufWriter, Seek, SeekFrom, Write};
+    use std::path::PathBuf;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::{Arc, Mutex};
+
+    const HEADER_LEN: u32 = 44;
+    // The RIFF chunk size is 32 bits, which limits the samples of one file to just under 4 GiB.
+    const MAX_DATA_LEN: u64 = (u32::MAX - HEADER_LEN) as u64;
+    const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+
+    /// A WAV file shared by every output the audio thread opens.
+    ///
+    /// The audio thread reopens its output on seek, stop and track changes, so the file lives
+    /// outside of `WavAudioOutput` and each new output keeps appending to it. The file is created
+    /// on the first open, because that is when the signal spec is known.
+    #[derive(Clone)]
+    pub struct WavFile(Arc<Mutex<WavFileState>>);
+
+    struct WavFileState {
+        path: PathBuf,
+        writer: Option<BufWriter<File>>,
+        spec: Option<SignalSpec>,
+        data_len: u64,
+    }
+
+    impl WavFile {
+        pub fn new(path: impl Into<PathBuf>) -> Self {
+            Self(Arc::new(Mutex::new(WavFileState {
+                path: path.into(),
+                writer: None,
+                spec: None,
+                data_len: 0,
+            })))
+        }
+    }
+
+    impl WavFileState {
+        fn open(&mut self, spec: SignalSpec) -> std::io::Result<()> {
+            let file = File::create(&self.path)?;
+            let mut writer = BufWriter::new(file);
+
+            let channels = spec.channels.count() as u16;
+            let block_align = channels * 4;
+
+            writer.write_all(b"RIFF")?;
+            writer.write_all(&(HEADER_LEN - 8).to_le_bytes())?;
+            writer.write_all(b"WAVE")?;
+            writer.write_all(b"fmt ")?;
+            writer.write_all(&16u32.to_le_bytes())?;
+            writer.write_all(&WAVE_FORMAT_IEEE_FLOAT.to_le_bytes())?;
+            writer.write_all(&channels.to_le_bytes())?;
+            writer.write_all(&spec.rate.to_le_bytes())?;
+            writer.write_all(&(spec.rate * block_align as u32).to_le_bytes())?;
+            writer.write_all(&block_align.to_le_bytes())?;
+            writer.write_all(&32u16.to_le_bytes())?;
+            writer.write_all(b"data")?;
+            writer.write_all(&0u32.to_le_bytes())?;
+
+            self.writer = Some(writer);
+            self.spec = Some(spec);
+            self.data_len = 0;
+
+            Ok(())
+        }
+
+        fn write_samples(&mut self, samples: &[f32]) -> std::io::Result<()> {
+            if let Some(writer) = self.writer.as_mut() {
+                let data_len = self.data_len + (samples.len() * 4) as u64;
+                if data_len > MAX_DATA_LEN {
+                    return Err(std::io::Error::other("the wav file is full"));
+                }
+
+                for sample in samples {
+                    writer.write_all(&sample.to_le_bytes())?;
+                }
+
+                self.data_len = data_len;
+            }
+
+            Ok(())
+        }
+
+        /// Patches the chunk sizes in the header so the file is valid up to this point.
+        fn finalize(&mut self) -> std::io::Result<()> {
+            if let Some(writer) = self.writer.as_mut() {
+                writer.seek(SeekFrom::Start(4))?;
+                // Never more than `MAX_DATA_LEN`, so both sizes fit.
+                let data_len = self.data_len as u32;
+                writer.write_all(&(HEADER_LEN - 8 + data_len).to_le_bytes())?;
+                writer.seek(SeekFrom::Start(HEADER_LEN as u64 - 4))?;
+                writer.write_all(&data_len.to_le_bytes())?;
+                writer.seek(SeekFrom::End(0))?;
+                writer.flush()?;
+            }
+
+            Ok(())
+        }
+    }
+
+    /// Writes `samples` as a complete 32-bit float WAV, e.g. for test fixtures.
+    #[cfg(test)]
+    pub fn write_file(
+        path: &std::path::Path,
+        spec: SignalSpec,
+        samples: &[f32],
+    ) -> std::io::Result<()> {
+        let mut state = WavFileState {
+            path: path.to_path_buf(),
+            writer: None,
+            spec: None,
+            data_len: 0,
+        };
+
+        state.open(spec)?;
+        state.write_samples(samples)?;
+        state.finalize()
+    }
+
+    /// A folder in the temp dir for the files of one test, removed with them once dropped, even
+    /// when the test fails. `name` keeps the tests that run at the same time apart.
+    #[cfg(test)]
+    pub struct TestDir(PathBuf);
+
+    #[cfg(test)]
+    impl TestDir {
+        pub fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("music-player-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        /// Writes a mono WAV of `frames` of silence into the folder.
+        pub fn write_silence(&self, name: &str, frames: usize) -> PathBuf {
+            let path = self.0.join(name);
+            let spec = SignalSpec::new(44100, symphonia::core::audio::Channels::FRONT_LEFT);
+            write_file(&path, spec, &vec![0.0; frames]).unwrap();
+            path
+        }
+    }
+
+    #[cfg(test)]
+    impl std::ops::Deref for TestDir {
+        type Target = std::path::Path;
+
+        fn deref(&self) -> &Self::Target {
+            &self.0
+        }
+    }
+
+    #[cfg(test)]
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    pub struct WavAudioOutput {
+        file: WavFile,
+        sample_buf: SampleBuffer<f32>,
+    }
+
+    impl WavAudioOutput {
+        pub fn try_open(
+            spec: SignalSpec,
+            duration: Duration,
+            file: &WavFile,
+        ) -> Result<Box<dyn AudioOutput>> {
+            let mut state = file.0.lock().unwrap();
+
+            match state.spec {
+                Some(file_spec) if file_spec != spec => {
+                    error!(
+                        "wav output can't change from {:?} to {:?} within one file",
+                        file_spec, spec
+                    );
+                    return Err(AudioOutputError::OpenStreamError);
+                }
+                Some(_) => (),
+                None => {
+                    if let Err(err) = state.open(spec) {
+                        error!("failed to create wav output {:?}: {}", state.path, err);
+                        return Err(AudioOutputError::OpenStreamError);
+                    }
+                }
+            }
+
+            Ok(Box::new(WavAudioOutput {
+                file: file.clone(),
+                sample_buf: SampleBuffer::<f32>::new(duration, spec),
+            }))
+        }
+    }
+
+    impl AudioOutput for WavAudioOutput {
+        fn write(
+            &mut self,
+            decoded: AudioBufferRef<'_>,
+            gui_ring_buf_producer: &rb::Producer<f32>,
+            process_gui_samples: &Arc<AtomicBool>,
+            volume: f32,
+        ) -> Result<()> {
+            if decoded.frames() == 0 {
+                return Ok(());
+            }
+
+            self.sample_buf.copy_interleaved_ref(decoded);
+            let samples = self
+                .sample_buf
+                .samples()
+                .iter()
+                .map(|s| s * volume)
+                .collect::<Vec<f32>>();
+
+            write_gui_samples(&samples, gui_ring_buf_producer, process_gui_samples);
+
+            match self.file.0.lock().unwrap().write_samples(&samples) {
+                Ok(()) => Ok(()),
+                Err(err) => {
+                    error!("wav output write error: {}", err);
+                    Err(AudioOutputError::StreamClosedError)
+                }
+            }
+        }
+
+        fn flush(&mut self) {
+            if let Err(err) = self.file.0.lock().unwrap().finalize() {
+                error!("wav output flush error: {}", err);
+            }
+        }
+    }
+
+    impl Drop for WavAudioOutput {
+        fn drop(&mut self) {
+            self.flush();
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use symphonia::core::audio::Channels;
+
+        #[test]
+        fn stops_before_the_riff_size_overflows() {
+            let dir = TestDir::new("full-wav");
+            let path = dir.join("full.wav");
+            let file = WavFile::new(&path);
+            let mut state = file.0.lock().unwrap();
+            state
+                .open(SignalSpec::new(44100, Channels::FRONT_LEFT))
+                .unwrap();
+
+            state.data_len = MAX_DATA_LEN - 8;
+            assert!(state.write_samples(&[0.0, 0.0]).is_ok());
+            assert!(state.write_samples(&[0.0]).is_err());
+            state.finalize().unwrap();
+            drop(state);
+
+            let header = std::fs::read(&path).unwrap();
+            assert_eq!(header[4..8], (u32::MAX - 8).to_le_bytes());
+            assert_eq!(header[40..44], (u32::MAX - HEADER_LEN).to_le_bytes());
+        }
+    }
+}
+
+pub fn try_open(
+    device: &AudioOutputDevice,
+    spec: SignalSpec,
+    duration: Duration,
+) -> Result<Box<dyn AudioOutput>> {
+    match device {
+        AudioOutputDevice::Default => cpal::CpalAudioOutput::try_open(spec, duration),
+        AudioOutputDevice::Null { realtime } => {
+            null::NullAudioOutput::try_open(spec, duration, *realtime)
+        }
+        AudioOutputDevice::Wav(file) => wav::WavAudioOutput::try_open(spec, duration, file),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remixes_channels() {
+        let mut remixed = vec![];
+
+        remix(&[0.5f32, -0.5], 1, 2, &mut remixed);
+        assert_eq!(remixed, [0.5, 0.5, -0.5, -0.5]);
+
+        remix(&[0.5f32, -0.25, 0.25, 0.75], 2, 1, &mut remixed);
+        assert_eq!(remixed, [0.125, 0.5]);
+
+        remix(&[0.5f32, -0.5], 2, 3, &mut remixed);
+        assert_eq!(remixed, [0.5, -0.5, 0.0]);
+
+        remix(&[0.1f32, 0.2, 0.3, 0.4, 0.5, 0.6], 6, 2, &mut remixed);
+        assert!((remixed[0] - 0.3).abs() < 1e-6 && (remixed[1] - 0.4).abs() < 1e-6);
+    }
 }